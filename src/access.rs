@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::adapters::{api, cli};
+use crate::error::Error;
+use crate::log;
+use crate::middleware::Middleware;
+use crate::registry::Registry;
+use crate::router::Route;
+use crate::types::{Message, Response, Role};
+
+// Roles of the commands the engine handles itself. `!schedule` is for
+// admins because scheduled jobs run later without another check.
+const BUILTIN_ROLES: [(&str, Role); 5] = [
+    ("cancel", Role::Guest),
+    ("jobs", Role::Guest),
+    ("status", Role::Guest),
+    ("undelivered", Role::Member),
+    ("schedule", Role::Admin),
+];
+
+// Adapters whose users are operators: the local terminal and the admin API,
+// which already requires a token. They are admins unless listed otherwise.
+const TRUSTED_SOURCES: [&str; 2] = [cli::SOURCE, api::SOURCE];
+
+// Grant gives `role` to a user of an adapter; either may be "*".
+#[derive(Clone, Debug)]
+pub struct Grant {
+    pub source: String,
+    pub user_id: String,
+    pub role: Role,
+}
+
+// Parses `adapter:user,adapter:user`, e.g. `telegram:123,whatsapp:*`. The
+// user id is everything after the first colon, so WhatsApp users are written
+// `whatsapp:whatsapp:+15551234567`.
+pub fn parse_grants(s: &str, role: Role) -> Result<Vec<Grant>, String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|g| !g.is_empty())
+        .map(|g| match g.split_once(':') {
+            Some((source, user)) if !source.trim().is_empty() && !user.trim().is_empty() => {
+                Ok(Grant {
+                    source: source.trim().to_string(),
+                    user_id: user.trim().to_string(),
+                    role,
+                })
+            }
+            _ => Err(format!("invalid user {g:?}; expected adapter:user_id")),
+        })
+        .collect()
+}
+
+// Access is the access-control middleware. A user's role comes from the
+// grants (an exact user beats a "*"), then from the trusted adapters, then
+// from `default_role`. A user without a role is not on the allowlist and is
+// turned away before their message is even routed. Each command needs at
+// least the role its task declares (`Task::role`), unless `task_roles` says
+// otherwise.
+pub struct Access {
+    reg: Arc<Registry>,
+    grants: Vec<Grant>,
+    default_role: Option<Role>,
+    task_roles: HashMap<String, Role>,
+}
+
+impl Access {
+    pub fn new(
+        reg: Arc<Registry>,
+        grants: Vec<Grant>,
+        default_role: Option<Role>,
+        task_roles: HashMap<String, Role>,
+    ) -> Self {
+        Self {
+            reg,
+            grants,
+            default_role,
+            task_roles,
+        }
+    }
+
+    fn role_of(&self, source: &str, user_id: &str) -> Option<Role> {
+        let granted = |src: &str, user: &str| {
+            self.grants
+                .iter()
+                .find(|g| g.source == src && g.user_id == user)
+                .map(|g| g.role)
+        };
+        granted(source, user_id)
+            .or_else(|| granted("*", user_id))
+            .or_else(|| granted(source, "*"))
+            .or_else(|| granted("*", "*"))
+            .or_else(|| TRUSTED_SOURCES.contains(&source).then_some(Role::Admin))
+            .or(self.default_role)
+    }
+
+    fn required(&self, task_name: &str) -> Role {
+        if let Some(r) = self.task_roles.get(task_name) {
+            return *r;
+        }
+        if let Some((_, r)) = BUILTIN_ROLES.iter().find(|(name, _)| *name == task_name) {
+            return *r;
+        }
+        self.reg
+            .lookup(task_name)
+            .map(|t| t.role())
+            .unwrap_or(Role::Admin)
+    }
+}
+
+impl Middleware for Access {
+    fn name(&self) -> &'static str {
+        "access"
+    }
+
+    // Users off the allowlist get no further, not even usage errors.
+    fn before_route(&self, msg: &mut Message) -> Option<Response> {
+        if msg.text.trim().is_empty() || self.role_of(&msg.source, &msg.user_id).is_some() {
+            return None;
+        }
+        Some(not_listed(msg, ""))
+    }
+
+    fn after_route(&self, msg: &Message, route: &mut Route) -> Option<Response> {
+        let Some(role) = self.role_of(&msg.source, &msg.user_id) else {
+            return Some(not_listed(msg, &route.task_name));
+        };
+        let required = self.required(&route.task_name);
+        if role >= required {
+            return None;
+        }
+        let err = Error::forbidden(format!(
+            "!{} needs the {} role; you are a {}",
+            route.task_name,
+            required.as_str(),
+            role.as_str()
+        ));
+        Some(deny(msg, &route.task_name, Some(role), Some(required), err))
+    }
+}
+
+fn not_listed(msg: &Message, task_name: &str) -> Response {
+    let err = Error::forbidden(format!(
+        "you are not allowed to use this bot; ask the operator to add {} user {}",
+        msg.source, msg.user_id
+    ));
+    deny(msg, task_name, None, None, err)
+}
+
+fn deny(
+    msg: &Message,
+    task_name: &str,
+    role: Option<Role>,
+    required: Option<Role>,
+    err: Error,
+) -> Response {
+    log::warn(
+        "access denied",
+        &[
+            ("user", &msg.user_id),
+            ("channel", &msg.channel),
+            ("source", &msg.source),
+            ("task", &task_name),
+            ("role", &role.map(Role::as_str).unwrap_or("none")),
+            ("required", &required.map(Role::as_str).unwrap_or_default()),
+            ("error_id", &err.id),
+        ],
+    );
+    Response {
+        ephemeral: true,
+        ..Response::error(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access(grants: &str, default_role: Option<Role>) -> Access {
+        let grants = parse_grants(grants, Role::Member).unwrap();
+        Access::new(
+            Arc::new(Registry::new()),
+            grants,
+            default_role,
+            HashMap::new(),
+        )
+    }
+
+    #[test]
+    fn parses_grants() {
+        let g = parse_grants(" telegram:1, whatsapp:whatsapp:+1555 ,", Role::Admin).unwrap();
+        assert_eq!(g.len(), 2);
+        assert_eq!(
+            (g[1].source.as_str(), g[1].user_id.as_str()),
+            ("whatsapp", "whatsapp:+1555")
+        );
+        assert!(parse_grants("telegram", Role::Admin).is_err());
+        assert!(parse_grants("telegram:", Role::Admin).is_err());
+    }
+
+    #[test]
+    fn the_most_specific_grant_wins() {
+        // Listed broadest first, so the ranking can't come from the order.
+        let mut grants = parse_grants("*:*", Role::Guest).unwrap();
+        grants.extend(parse_grants("telegram:*", Role::Member).unwrap());
+        grants.extend(parse_grants("*:7", Role::Member).unwrap());
+        grants.extend(parse_grants("telegram:7", Role::Admin).unwrap());
+        let a = Access::new(Arc::new(Registry::new()), grants, None, HashMap::new());
+        assert_eq!(a.role_of("telegram", "7"), Some(Role::Admin));
+        assert_eq!(a.role_of("whatsapp", "7"), Some(Role::Member));
+        assert_eq!(a.role_of("telegram", "8"), Some(Role::Member));
+        assert_eq!(a.role_of("whatsapp", "8"), Some(Role::Guest));
+        // A grant outranks trusted adapters.
+        assert_eq!(a.role_of("cli", "8"), Some(Role::Guest));
+    }
+
+    #[test]
+    fn trusted_adapters_then_the_default() {
+        let a = access("telegram:1", None);
+        assert_eq!(a.role_of("telegram", "1"), Some(Role::Member));
+        assert_eq!(a.role_of("telegram", "2"), None);
+        assert_eq!(a.role_of("cli", "anyone"), Some(Role::Admin));
+        assert_eq!(a.role_of("api", "anyone"), Some(Role::Admin));
+
+        let a = access("", Some(Role::Guest));
+        assert_eq!(a.role_of("telegram", "2"), Some(Role::Guest));
+    }
+
+    #[test]
+    fn builtin_commands_have_fixed_roles() {
+        let a = access("", None);
+        assert_eq!(a.required("status"), Role::Guest);
+        assert_eq!(a.required("schedule"), Role::Admin);
+        // Unknown tasks are for admins only.
+        assert_eq!(a.required("nope"), Role::Admin);
+        let a = Access::new(
+            Arc::new(Registry::new()),
+            Vec::new(),
+            None,
+            HashMap::from([("schedule".to_string(), Role::Member)]),
+        );
+        assert_eq!(a.required("schedule"), Role::Member);
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::clock::{parse_duration, rfc3339};
+use crate::engine::{Core, Engine, ResultSink};
+use crate::error::Error;
+use crate::http::{self, Reply, Request};
+use crate::jobs::JobEntry;
+use crate::json;
+use crate::types::{FileBody, Job, META_DISPLAY_NAME, META_USERNAME, Message, Response};
+
+pub const SOURCE: &str = "api";
+
+// Results of API-submitted jobs kept for `GET /v1/jobs/{id}`; the oldest are
+// dropped first.
+const KEEP_RESULTS: usize = 500;
+
+// Upper bound for `"wait"` on `POST /v1/messages`.
+const MAX_WAIT: Duration = Duration::from_secs(300);
+
+// Adapter serves the local admin API. Every `/v1/` request needs
+// `Authorization: Bearer <token>`; `/healthz` and `/readyz` are open so
+// probes need no secret.
+pub struct Adapter {
+    token: String,
+    core: Arc<Core>,
+    results: Mutex<Results>,
+    delivered: Condvar,
+}
+
+#[derive(Default)]
+struct Results {
+    // The JSON fields each result adds to a job: see `result_fields`.
+    by_job: HashMap<String, Vec<(&'static str, String)>>,
+    order: VecDeque<String>,
+}
+
+impl Adapter {
+    pub fn new(token: String, core: Arc<Core>) -> Self {
+        Self {
+            token,
+            core,
+            results: Mutex::new(Results::default()),
+            delivered: Condvar::new(),
+        }
+    }
+
+    pub fn serve(a: &Arc<Self>, addr: &str) -> Result<(), String> {
+        if a.token.trim().is_empty() {
+            return Err("CRABPLANE_API_TOKEN is empty".to_string());
+        }
+        let a = Arc::clone(a);
+        http::serve("api", addr, move |req| a.route(req))
+    }
+
+    fn route(&self, req: &Request) -> Reply {
+        let path = req.path.trim_end_matches('/');
+        match (req.method.as_str(), path) {
+            ("GET", "/healthz") => Reply::text(200, "ok\n"),
+            ("GET", "/readyz") if self.core.is_ready() => Reply::text(200, "ready\n"),
+            ("GET", "/readyz") => Reply::text(503, "shutting down\n"),
+            (_, p) if p.starts_with("/v1/") && !self.authorized(req) => {
+                error(401, "missing or invalid bearer token")
+            }
+            ("POST", "/v1/messages") => self.post_message(req),
+            ("GET", "/v1/jobs") => self.list_jobs(req),
+            ("GET", p) if p.starts_with("/v1/jobs/") => self.get_job(&p["/v1/jobs/".len()..]),
+            (_, "/healthz" | "/readyz" | "/v1/messages" | "/v1/jobs") => {
+                error(405, "method not allowed")
+            }
+            _ => error(404, "not found"),
+        }
+    }
+
+    fn authorized(&self, req: &Request) -> bool {
+        let Some(got) = req
+            .header("authorization")
+            .and_then(|h| h.strip_prefix("Bearer "))
+        else {
+            return false;
+        };
+        // Compare in constant time.
+        let (a, b) = (got.trim().as_bytes(), self.token.as_bytes());
+        a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+    }
+
+    // Body: {"text": "!ask ...", "user_id": "ci", "channel": "deploys",
+    // "wait": "30s", "username": "ci-bot", "display_name": "CI"}. Only `text`
+    // is required. With `wait`, the reply holds
+    // the job's result if it is delivered in time.
+    fn post_message(&self, req: &Request) -> Reply {
+        let body = String::from_utf8_lossy(&req.body);
+        let fields = match json::parse_object(&body) {
+            Ok(f) => f,
+            Err(e) => return error(400, &e),
+        };
+        let text = fields.get("text").map(|t| t.trim()).unwrap_or_default();
+        if text.is_empty() {
+            return error(400, "text is required");
+        }
+        let wait = match fields.get("wait") {
+            Some(w) => match parse_duration(w) {
+                Some(d) => d.min(MAX_WAIT),
+                None => return error(400, &format!("invalid wait: {w}")),
+            },
+            None => Duration::ZERO,
+        };
+        let field = |k: &str| {
+            fields
+                .get(k)
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| SOURCE.to_string())
+        };
+
+        let metadata = [META_USERNAME, META_DISPLAY_NAME]
+            .into_iter()
+            .filter_map(|k| {
+                let v = fields.get(k)?.trim();
+                (!v.is_empty()).then(|| (k.to_string(), v.to_string()))
+            })
+            .collect();
+
+        let resp = self.core.handle(Message {
+            source: SOURCE.to_string(),
+            user_id: field("user_id"),
+            channel: field("channel"),
+            text: text.to_string(),
+            attachments: Vec::new(),
+            metadata,
+        });
+
+        let mut out = vec![
+            ("text", json::quote(&resp.text)),
+            ("ephemeral", resp.ephemeral.to_string()),
+        ];
+        if let Some(e) = &resp.error {
+            out.push(("error_kind", json::quote(e.kind.as_str())));
+        }
+        let Some(job_id) = resp.job_id else {
+            return Reply::json(200, object(&out));
+        };
+        out.push(("job_id", json::quote(&job_id)));
+        match self.wait_result(&job_id, wait) {
+            Some(result) => {
+                out.extend(result);
+                Reply::json(200, object(&out))
+            }
+            None => Reply::json(202, object(&out)),
+        }
+    }
+
+    // `?source=<adapter>` and `?user=<id>` narrow the list; `?limit=`
+    // defaults to 50.
+    fn list_jobs(&self, req: &Request) -> Reply {
+        let limit = req
+            .query
+            .get("limit")
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(50);
+        let source = req.query.get("source").map(String::as_str);
+        let user = req.query.get("user").map(String::as_str);
+        let jobs: Vec<String> = self
+            .core
+            .recent_jobs(source, user, limit)
+            .iter()
+            .map(|e| self.job_json(e))
+            .collect();
+        Reply::json(200, format!("{{\"jobs\":[{}]}}", jobs.join(",")))
+    }
+
+    fn get_job(&self, id: &str) -> Reply {
+        match self.core.find_job(id) {
+            Ok(e) => Reply::json(200, self.job_json(&e)),
+            Err(e) => error(404, &e),
+        }
+    }
+
+    fn job_json(&self, e: &JobEntry) -> String {
+        let time = |t: Option<SystemTime>| t.map(|t| json::quote(&rfc3339(t)));
+        let fields = [
+            ("id", Some(json::quote(&e.id))),
+            ("task", Some(json::quote(&e.task_name))),
+            ("source", Some(json::quote(&e.source))),
+            ("user_id", Some(json::quote(&e.user_id))),
+            ("state", Some(json::quote(e.state.as_str()))),
+            ("attempt", Some(e.attempt.to_string())),
+            ("worker", e.worker_id.map(|w| w.to_string())),
+            ("created_at", time(Some(e.created_at))),
+            ("not_before", time(e.not_before)),
+            ("started_at", time(e.started_at)),
+            ("finished_at", time(e.finished_at)),
+            ("duration_ms", e.dur.map(|d| d.as_millis().to_string())),
+            ("error", e.err.as_ref().map(|e| json::quote(&e.msg))),
+            (
+                "error_kind",
+                e.err.as_ref().map(|e| json::quote(e.kind.as_str())),
+            ),
+            ("error_id", e.err.as_ref().map(|e| json::quote(&e.id))),
+        ];
+        let mut present: Vec<(&str, String)> = fields
+            .into_iter()
+            .filter_map(|(k, v)| Some((k, v?)))
+            .collect();
+        present.extend(self.result(&e.id).unwrap_or_default());
+        object(&present)
+    }
+
+    fn result(&self, job_id: &str) -> Option<Vec<(&'static str, String)>> {
+        let g = self.results.lock().ok()?;
+        g.by_job.get(job_id).cloned()
+    }
+
+    fn wait_result(&self, job_id: &str, wait: Duration) -> Option<Vec<(&'static str, String)>> {
+        let deadline = Instant::now() + wait;
+        let mut g = self.results.lock().ok()?;
+        loop {
+            if let Some(r) = g.by_job.get(job_id) {
+                return Some(r.clone());
+            }
+            let left = deadline.checked_duration_since(Instant::now())?;
+            if left.is_zero() {
+                return None;
+            }
+            g = self.delivered.wait_timeout(g, left).ok()?.0;
+        }
+    }
+}
+
+impl ResultSink for Adapter {
+    fn deliver(&self, job: &Job, resp: &Response) -> Result<(), Error> {
+        let mut g = self
+            .results
+            .lock()
+            .map_err(|_| Error::internal("api results lock poisoned"))?;
+        if g.by_job
+            .insert(job.id.clone(), result_fields(resp))
+            .is_none()
+        {
+            g.order.push_back(job.id.clone());
+        }
+        while g.order.len() > KEEP_RESULTS {
+            if let Some(old) = g.order.pop_front() {
+                g.by_job.remove(&old);
+            }
+        }
+        self.delivered.notify_all();
+        Ok(())
+    }
+}
+
+// `result` is the text; `files` and `buttons` are listed when there are any.
+// File contents are not kept: a file has its `url`, or its `size` in bytes
+// when it was sent as data.
+fn result_fields(resp: &Response) -> Vec<(&'static str, String)> {
+    let mut out = vec![("result", json::quote(&resp.text))];
+    if !resp.files.is_empty() {
+        let files: Vec<String> = resp
+            .files
+            .iter()
+            .map(|f| {
+                let body = match &f.body {
+                    FileBody::Url(u) => ("url", json::quote(u)),
+                    FileBody::Bytes(b) => ("size", b.len().to_string()),
+                };
+                object(&[
+                    ("name", json::quote(&f.name)),
+                    ("mime", json::quote(&f.mime)),
+                    body,
+                ])
+            })
+            .collect();
+        out.push(("files", format!("[{}]", files.join(","))));
+    }
+    if !resp.buttons.is_empty() {
+        let buttons: Vec<String> = resp
+            .buttons
+            .iter()
+            .map(|b| {
+                object(&[
+                    ("label", json::quote(&b.label)),
+                    ("command", json::quote(&b.command)),
+                ])
+            })
+            .collect();
+        out.push(("buttons", format!("[{}]", buttons.join(","))));
+    }
+    out
+}
+
+fn object(fields: &[(&str, String)]) -> String {
+    let body: Vec<String> = fields
+        .iter()
+        .map(|(k, v)| format!("{}:{v}", json::quote(k)))
+        .collect();
+    format!("{{{}}}", body.join(","))
+}
+
+fn error(status: u16, msg: &str) -> Reply {
+    Reply::json(status, object(&[("error", json::quote(msg))]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Button, OutFile};
+
+    #[test]
+    fn results_list_files_and_buttons() {
+        let text = Response {
+            text: "hi".to_string(),
+            ..Response::default()
+        };
+        assert_eq!(object(&result_fields(&text)), r#"{"result":"hi"}"#);
+        let resp = Response {
+            files: vec![
+                OutFile {
+                    name: "a.png".to_string(),
+                    mime: "image/png".to_string(),
+                    body: FileBody::Bytes(vec![0; 3]),
+                },
+                OutFile {
+                    name: "b".to_string(),
+                    mime: String::new(),
+                    body: FileBody::Url("https://x/b".to_string()),
+                },
+            ],
+            buttons: vec![Button {
+                label: "More".to_string(),
+                command: "!onboard ai".to_string(),
+            }],
+            ..text
+        };
+        assert_eq!(
+            object(&result_fields(&resp)),
+            concat!(
+                r#"{"result":"hi","files":[{"name":"a.png","mime":"image/png","size":3},"#,
+                r#"{"name":"b","mime":"","url":"https://x/b"}],"#,
+                r#""buttons":[{"label":"More","command":"!onboard ai"}]}"#
+            )
+        );
+    }
+}
@@ -1,10 +1,16 @@
 use std::collections::HashMap;
-use std::io::{self, BufRead, Write};
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use crate::engine::{Engine, ResultSink};
-use crate::types::{Job, Message, Response};
+use crate::error::{Error, Kind};
+use crate::markdown;
+use crate::types::{FileBody, Job, Message, OutFile, Response};
+
+pub const SOURCE: &str = "cli";
 
 pub struct Adapter {
     eng: Arc<dyn Engine>,
@@ -31,7 +37,7 @@ impl Adapter {
         let (tx, rx) = std::sync::mpsc::channel::<String>();
         std::thread::spawn(move || {
             let stdin = io::stdin();
-            for line in stdin.lock().lines().flatten() {
+            for line in stdin.lock().lines().map_while(Result::ok) {
                 let line = line.trim().to_string();
                 if line.is_empty() {
                     continue;
@@ -46,14 +52,27 @@ impl Adapter {
             match rx.recv_timeout(Duration::from_millis(100)) {
                 Ok(line) => {
                     let resp = self.eng.handle(Message {
+                        source: SOURCE.to_string(),
                         user_id: "cli".to_string(),
                         channel: "cli".to_string(),
                         text: line,
+                        attachments: Vec::new(),
                         metadata: HashMap::new(),
                     });
-                    if !resp.text.is_empty() {
+                    let text = match &resp.error {
+                        Some(e) => error_line(e),
+                        None => resp.text,
+                    };
+                    if text.is_empty() {
+                        continue;
+                    }
+                    if resp.ephemeral {
+                        // Acks go to stderr so stdout carries only results;
+                        // dimmed on a terminal, unless they report an error.
+                        note(&text, resp.error.is_none())?;
+                    } else {
                         let mut out = self.out.lock().unwrap();
-                        writeln!(out, "{}", resp.text)?;
+                        writeln!(out, "{text}")?;
                     }
                 }
                 Err(std::sync::mpsc::RecvTimeoutError::Timeout) => continue,
@@ -66,25 +85,138 @@ impl Adapter {
 
 pub struct Sink {
     out: Mutex<Box<dyn Write + Send>>,
+    // Text already printed for jobs that are streaming, by job id, with the
+    // attempt it belongs to.
+    streamed: Mutex<HashMap<String, (u32, String)>>,
+    // Where files in results are saved.
+    download_dir: PathBuf,
 }
 
 impl Sink {
-    pub fn new() -> Self {
+    pub fn new(download_dir: PathBuf) -> Self {
         Self {
             out: Mutex::new(Box::new(io::stdout())),
+            streamed: Mutex::new(HashMap::new()),
+            download_dir,
         }
     }
+
+    // Saves a file sent as bytes and says where; linked files are printed.
+    fn file_line(&self, job: &Job, f: &OutFile) -> Result<String, String> {
+        let data = match &f.body {
+            FileBody::Url(url) => return Ok(format!("file: {} <{url}>", f.name)),
+            FileBody::Bytes(data) => data,
+        };
+        fs::create_dir_all(&self.download_dir)
+            .map_err(|e| format!("create {}: {e}", self.download_dir.display()))?;
+        let name: String = f
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let short_id = &job.id[..job.id.len().min(8)];
+        let path = self
+            .download_dir
+            .join(format!("{short_id}-{}", name.trim_start_matches('.')));
+        fs::write(&path, data).map_err(|e| format!("write {}: {e}", path.display()))?;
+        Ok(format!("file: {} saved to {}", f.name, path.display()))
+    }
 }
 
 impl ResultSink for Sink {
-    fn deliver(&self, _job: &Job, resp: &Response) -> Result<(), String> {
-        if resp.text.is_empty() {
+    fn deliver(&self, job: &Job, resp: &Response) -> Result<(), Error> {
+        let streamed = self
+            .streamed
+            .lock()
+            .ok()
+            .and_then(|mut g| g.remove(&job.id))
+            .map(|(_, text)| text);
+        let mut out = self
+            .out
+            .lock()
+            .map_err(|_| Error::internal("stdout lock poisoned"))?;
+        let write_err = |e: io::Error| Error::internal(format!("write stdout: {e}"));
+        let mut lines = Vec::new();
+        // The answer was already printed chunk by chunk; just end the line.
+        if let Some(s) = &streamed
+            && s.trim() == resp.text.trim()
+        {
+            if !s.ends_with('\n') {
+                writeln!(out).map_err(write_err)?;
+            }
+        } else if let Some(e) = &resp.error {
+            if streamed.is_some() {
+                writeln!(out).map_err(write_err)?;
+            }
+            lines.push(error_line(e));
+        } else if resp.markdown {
+            lines.push(markdown::to_plain(&resp.text));
+        } else if !resp.text.is_empty() {
+            lines.push(resp.text.clone());
+        }
+        for f in &resp.files {
+            lines.push(self.file_line(job, f).map_err(Error::internal)?);
+        }
+        for b in &resp.buttons {
+            lines.push(format!("[{}] {}", b.label, b.command));
+        }
+        if lines.is_empty() {
             return Ok(());
         }
+        writeln!(out, "{}", lines.join("\n")).map_err(write_err)
+    }
+
+    // A retry prints its answer again on a new line.
+    fn deliver_chunk(&self, job: &Job, chunk: &str) -> Result<(), Error> {
+        let mut restart = false;
+        if let Ok(mut g) = self.streamed.lock() {
+            let s = g
+                .entry(job.id.clone())
+                .or_insert_with(|| (job.attempt, String::new()));
+            if s.0 != job.attempt {
+                restart = !s.1.is_empty() && !s.1.ends_with('\n');
+                *s = (job.attempt, String::new());
+            }
+            s.1.push_str(chunk);
+        }
         let mut out = self
             .out
             .lock()
-            .map_err(|_| "stdout lock poisoned".to_string())?;
-        writeln!(out, "{}", resp.text).map_err(|e| e.to_string())
+            .map_err(|_| Error::internal("stdout lock poisoned"))?;
+        let write_err = |e: io::Error| Error::internal(format!("write stdout: {e}"));
+        if restart {
+            writeln!(out).map_err(write_err)?;
+        }
+        write!(out, "{chunk}").map_err(write_err)?;
+        out.flush().map_err(write_err)
+    }
+
+    // Shown like acks, on stderr.
+    fn deliver_progress(&self, _job: &Job, text: &str) -> Result<(), Error> {
+        note(text, true).map_err(|e| Error::internal(format!("write stderr: {e}")))
+    }
+}
+
+// Writes a line to stderr, dimmed on a terminal if `dim`.
+fn note(text: &str, dim: bool) -> io::Result<()> {
+    let mut err = io::stderr().lock();
+    if dim && err.is_terminal() {
+        writeln!(err, "\x1b[2m{text}\x1b[0m")
+    } else {
+        writeln!(err, "{text}")
+    }
+}
+
+// The terminal belongs to the operator, so errors are shown in full.
+fn error_line(e: &Error) -> String {
+    match e.kind {
+        Kind::UserInput => format!("error: {}", e.msg),
+        kind => format!("error: {} ({}, error {})", e.msg, kind.as_str(), e.id),
     }
 }
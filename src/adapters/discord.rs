@@ -1,8 +1,11 @@
 use std::sync::Arc;
 
 use crate::engine::{Engine, ResultSink};
+use crate::error::Error;
 use crate::types::{Job, Response};
 
+pub const SOURCE: &str = "discord";
+
 pub struct Adapter {
     token: String,
     _eng: Arc<dyn Engine>,
@@ -26,7 +29,7 @@ impl Adapter {
 }
 
 impl ResultSink for Adapter {
-    fn deliver(&self, _job: &Job, _resp: &Response) -> Result<(), String> {
+    fn deliver(&self, _job: &Job, _resp: &Response) -> Result<(), Error> {
         // No-op in the stub implementation.
         Ok(())
     }
@@ -1,4 +1,45 @@
+pub mod api;
 pub mod cli;
 pub mod discord;
 pub mod telegram;
 pub mod whatsapp;
+
+use crate::error::Error;
+
+// Maps a failed `curl --fail-with-body` call to an error. A 4xx other than
+// 429 means the request itself was refused (chat not found, bot blocked,
+// message too long), so sending it again won't help.
+pub fn send_error(msg: String) -> Error {
+    let status = msg
+        .split_once("returned error: ")
+        .and_then(|(_, rest)| rest.get(..3))
+        .and_then(|s| s.parse::<u16>().ok());
+    match status {
+        Some(s) if (400..500).contains(&s) && s != 429 => Error::rejected(msg),
+        _ => Error::upstream(msg),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Kind;
+
+    #[test]
+    fn client_errors_other_than_429_are_not_retried() {
+        let err = |status: &str| {
+            send_error(format!(
+                "curl failed: curl: (22) The requested URL returned error: {status} {{\"ok\":false}}"
+            ))
+            .kind
+        };
+        assert_eq!(err("403"), Kind::Rejected);
+        assert_eq!(err("400"), Kind::Rejected);
+        assert_eq!(err("429"), Kind::Upstream);
+        assert_eq!(err("502"), Kind::Upstream);
+        assert_eq!(
+            send_error("curl failed: curl: (28) Operation timed out".to_string()).kind,
+            Kind::Upstream
+        );
+    }
+}
@@ -1,21 +1,79 @@
 use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
 use std::process::Command;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use crate::adapters::send_error;
 use crate::engine::{Engine, ResultSink};
-use crate::types::{Job, Message, Response};
+use crate::error::Error;
+use crate::json;
+use crate::markdown;
+use crate::types::{
+    Attachment, Button, FileBody, Job, META_CHAT_TYPE, META_DISPLAY_NAME, META_MESSAGE_ID,
+    META_REPLY_TO, META_USERNAME, Message, OutFile, Response,
+};
+
+// Streamed answers are edited in place at most this often; Telegram rate
+// limits edits to the same chat.
+const EDIT_INTERVAL: Duration = Duration::from_millis(1500);
+
+// Telegram's message length limit; longer streams stop updating and the
+// final answer is sent as a new message.
+const MAX_MESSAGE_LEN: usize = 4096;
+
+// Acks and error replies are deleted once their job's result is delivered,
+// or after this long.
+const EPHEMERAL_TTL: Duration = Duration::from_secs(120);
+
+// Telegram's limit on a button's `callback_data`; longer commands are listed
+// under the text instead.
+const MAX_CALLBACK_DATA: usize = 64;
+
+pub const SOURCE: &str = "telegram";
 
 pub struct Adapter {
     token: String,
     eng: Arc<dyn Engine>,
+    streams: Mutex<HashMap<String, Streamed>>,
+    acks: Mutex<Vec<Ack>>,
+    // How many parts of a result were sent before its delivery failed, by job
+    // id; the text is one part and each file another. Retries skip them, and
+    // `forget` drops the count once the dispatcher gives up.
+    sent: Mutex<HashMap<String, usize>>,
+}
+
+// An ephemeral reply waiting to be deleted.
+struct Ack {
+    chat_id: i64,
+    message_id: i64,
+    job_id: Option<String>,
+    sent_at: Instant,
+}
+
+// The message a running job's output is being streamed into.
+struct Streamed {
+    attempt: u32,
+    message_id: Option<i64>,
+    text: String,
+    // What the message currently shows.
+    shown: String,
+    last_edit: Instant,
 }
 
 impl Adapter {
     pub fn new(token: String, eng: Arc<dyn Engine>) -> Self {
-        Self { token, eng }
+        Self {
+            token,
+            eng,
+            streams: Mutex::new(HashMap::new()),
+            acks: Mutex::new(Vec::new()),
+            sent: Mutex::new(HashMap::new()),
+        }
     }
 
     pub fn run(&self, stop: &AtomicBool) -> Result<(), String> {
@@ -25,6 +83,7 @@ impl Adapter {
 
         let mut offset: i64 = 0;
         while !stop.load(Ordering::Relaxed) {
+            self.delete_acks(None);
             let body = get_updates(&self.token, offset)?;
             let updates = parse_updates(&body);
             if updates.is_empty() {
@@ -34,6 +93,10 @@ impl Adapter {
 
             for u in updates {
                 offset = (u.update_id + 1).max(offset);
+                // A pressed button: stop its spinner, then run its command.
+                if let Some(id) = &u.callback_id {
+                    let _ = answer_callback(&self.token, id);
+                }
                 if u.text.is_empty() {
                     continue;
                 }
@@ -45,16 +108,35 @@ impl Adapter {
                     let _ = send_chat_action(&self.token, u.chat_id, "typing");
                 }
 
+                let message_id = u.message_id;
                 let resp = self.eng.handle(Message {
+                    source: SOURCE.to_string(),
                     user_id: u.user_id,
                     channel: u.chat_id.to_string(),
                     text: u.text,
-                    metadata: HashMap::new(),
+                    attachments: u.attachments,
+                    metadata: u.metadata,
                 });
                 if resp.text.is_empty() {
                     continue;
                 }
-                let _ = send_message(&self.token, u.chat_id, &resp.text);
+                let out = Outgoing::of(&resp).reply_to(message_id);
+                let sent = post_message(&self.token, u.chat_id, &out);
+                // Command output such as `!jobs` is ephemeral too, but it is
+                // what the user asked to read, so only acks and errors go.
+                if let Ok(body) = sent
+                    && resp.ephemeral
+                    && (resp.job_id.is_some() || resp.error.is_some())
+                    && let Some(message_id) = extract_i64_after(&body, "\"message_id\":")
+                    && let Ok(mut acks) = self.acks.lock()
+                {
+                    acks.push(Ack {
+                        chat_id: u.chat_id,
+                        message_id,
+                        job_id: resp.job_id,
+                        sent_at: Instant::now(),
+                    });
+                }
             }
         }
 
@@ -64,42 +146,326 @@ impl Adapter {
     pub fn close(&self) -> Result<(), String> {
         Ok(())
     }
+
+    // Deletes the acks that have been shown for `EPHEMERAL_TTL`, or all
+    // acks of `job_id` once its result is out.
+    fn delete_acks(&self, job_id: Option<&str>) {
+        let due: Vec<Ack> = match self.acks.lock() {
+            Ok(mut acks) => {
+                let (due, keep) = acks.drain(..).partition(|a| {
+                    a.sent_at.elapsed() >= EPHEMERAL_TTL
+                        || (job_id.is_some() && a.job_id.as_deref() == job_id)
+                });
+                *acks = keep;
+                due
+            }
+            Err(_) => return,
+        };
+        for a in due {
+            // Best effort: the message may be gone or too old to delete.
+            let _ = delete_message(&self.token, a.chat_id, a.message_id);
+        }
+    }
 }
 
 impl ResultSink for Adapter {
-    fn deliver(&self, job: &Job, resp: &Response) -> Result<(), String> {
-        if resp.text.is_empty() {
+    fn deliver(&self, job: &Job, resp: &Response) -> Result<(), Error> {
+        // The streamed message is forgotten only once the final text is in
+        // place, so a retried delivery still edits it instead of posting
+        // the answer a second time.
+        let streamed = self
+            .streams
+            .lock()
+            .ok()
+            .and_then(|g| g.get(&job.id).map(|s| (s.message_id, s.shown.clone())));
+        let forget_stream = || {
+            if let Ok(mut g) = self.streams.lock() {
+                g.remove(&job.id);
+            }
+        };
+        if resp.text.is_empty() && resp.files.is_empty() {
+            forget_stream();
             return Ok(());
         }
-        let chat_id = job
-            .channel_id
-            .parse::<i64>()
-            .map_err(|_| format!("invalid telegram chat id: {}", job.channel_id))?;
-        send_message(&self.token, chat_id, &resp.text)
+        let chat_id = chat_id_of(job)?;
+        let done = self
+            .sent
+            .lock()
+            .ok()
+            .and_then(|g| g.get(&job.id).copied())
+            .unwrap_or(0);
+        let record = |n: usize| {
+            if let Ok(mut g) = self.sent.lock() {
+                g.insert(job.id.clone(), n);
+            }
+        };
+        if !resp.text.is_empty() && done == 0 {
+            let out = Outgoing::of(resp);
+            match streamed {
+                // An error is sent on its own, leaving the partial answer.
+                Some((Some(id), shown))
+                    if resp.error.is_none() && resp.text.chars().count() <= MAX_MESSAGE_LEN =>
+                {
+                    // Telegram rejects edits that change nothing.
+                    if shown != resp.text || out.html || !out.keyboard.is_empty() {
+                        edit_message(&self.token, chat_id, id, &out).map_err(send_error)?;
+                    }
+                }
+                _ => {
+                    let out = out.reply_to(reply_target(job));
+                    post_message(&self.token, chat_id, &out).map_err(send_error)?;
+                }
+            }
+            record(1);
+        }
+        forget_stream();
+        let first_file = usize::from(!resp.text.is_empty());
+        for (i, f) in resp.files.iter().enumerate() {
+            let part = first_file + i;
+            if part < done {
+                continue;
+            }
+            send_file(&self.token, chat_id, f).map_err(send_error)?;
+            record(part + 1);
+        }
+        if let Ok(mut g) = self.sent.lock() {
+            g.remove(&job.id);
+        }
+        self.delete_acks(Some(&job.id));
+        Ok(())
+    }
+
+    // The first chunk is sent as a new message, which later chunks edit. The
+    // stream map is not held while Telegram is called, so other jobs' chunks
+    // and results are not held up.
+    fn deliver_chunk(&self, job: &Job, chunk: &str) -> Result<(), Error> {
+        let chat_id = chat_id_of(job)?;
+        let lock = || {
+            self.streams
+                .lock()
+                .map_err(|_| Error::internal("telegram stream lock poisoned"))
+        };
+        let (text, message_id) = {
+            let mut g = lock()?;
+            let s = g.entry(job.id.clone()).or_insert_with(|| Streamed {
+                attempt: job.attempt,
+                message_id: None,
+                text: String::new(),
+                shown: String::new(),
+                last_edit: Instant::now(),
+            });
+            // A retry starts over in the same message.
+            if s.attempt != job.attempt {
+                s.attempt = job.attempt;
+                s.text.clear();
+            }
+            s.text.push_str(chunk);
+
+            let text = s.text.trim().to_string();
+            if text.is_empty() || text == s.shown || text.chars().count() > MAX_MESSAGE_LEN {
+                return Ok(());
+            }
+            if s.message_id.is_some() && s.last_edit.elapsed() < EDIT_INTERVAL {
+                return Ok(());
+            }
+            (text, s.message_id)
+        };
+
+        let message_id = match message_id {
+            None => {
+                let out = Outgoing::plain(&text).reply_to(reply_target(job));
+                let body = post_message(&self.token, chat_id, &out).map_err(send_error)?;
+                extract_i64_after(&body, "\"message_id\":").ok_or_else(|| {
+                    Error::upstream(format!("telegram sendMessage failed: {}", body.trim()))
+                })?
+            }
+            Some(id) => {
+                edit_message(&self.token, chat_id, id, &Outgoing::plain(&text))
+                    .map_err(send_error)?;
+                id
+            }
+        };
+
+        // Chunks of one job arrive in order, so the entry is still this
+        // attempt's unless the job finished meanwhile.
+        if let Some(s) = lock()?.get_mut(&job.id)
+            && s.attempt == job.attempt
+        {
+            s.message_id = Some(message_id);
+            s.shown = text;
+            s.last_edit = Instant::now();
+        }
+        Ok(())
+    }
+
+    // Progress notes are kept like acks, so they go once the result is out.
+    fn deliver_progress(&self, job: &Job, note: &str) -> Result<(), Error> {
+        let chat_id = chat_id_of(job)?;
+        let body =
+            post_message(&self.token, chat_id, &Outgoing::plain(note)).map_err(send_error)?;
+        if let Some(message_id) = extract_i64_after(&body, "\"message_id\":")
+            && let Ok(mut acks) = self.acks.lock()
+        {
+            acks.push(Ack {
+                chat_id,
+                message_id,
+                job_id: Some(job.id.clone()),
+                sent_at: Instant::now(),
+            });
+        }
+        Ok(())
+    }
+
+    fn forget(&self, job: &Job) {
+        if let Ok(mut g) = self.streams.lock() {
+            g.remove(&job.id);
+        }
+        if let Ok(mut g) = self.sent.lock() {
+            g.remove(&job.id);
+        }
     }
 }
 
+// Results are sent as replies to the message that asked for them.
+fn reply_target(job: &Job) -> Option<i64> {
+    job.metadata.get(META_MESSAGE_ID)?.parse().ok()
+}
+
+fn chat_id_of(job: &Job) -> Result<i64, Error> {
+    job.channel_id
+        .parse::<i64>()
+        .map_err(|_| Error::internal(format!("invalid telegram chat id: {}", job.channel_id)))
+}
+
 #[derive(Debug)]
 struct TelegramUpdate {
     update_id: i64,
     chat_id: i64,
     user_id: String,
     text: String,
+    attachments: Vec<Attachment>,
+    // Set when a button was pressed; `text` is then the button's command.
+    callback_id: Option<String>,
+    // The message's id, or for a pressed button the id of its message.
+    message_id: Option<i64>,
+    metadata: HashMap<String, String>,
+}
+
+// A text message as sent to the Bot API.
+struct Outgoing {
+    text: String,
+    // `text` is HTML (`parse_mode=HTML`).
+    html: bool,
+    // `reply_markup` JSON; empty for none.
+    keyboard: String,
+    // Message to reply to when sending (not when editing).
+    reply_to: Option<i64>,
+}
+
+impl Outgoing {
+    fn plain(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            html: false,
+            keyboard: String::new(),
+            reply_to: None,
+        }
+    }
+
+    fn reply_to(self, message_id: Option<i64>) -> Self {
+        Self {
+            reply_to: message_id,
+            ..self
+        }
+    }
+
+    // Markdown is rendered as HTML and buttons become an inline keyboard,
+    // one button per row.
+    fn of(resp: &Response) -> Self {
+        let (fits, long): (Vec<&Button>, Vec<&Button>) = resp
+            .buttons
+            .iter()
+            .partition(|b| b.command.len() <= MAX_CALLBACK_DATA);
+        let mut text = if resp.markdown {
+            markdown::to_html(&resp.text)
+        } else {
+            resp.text.clone()
+        };
+        if resp.error.is_some() {
+            text.insert_str(0, "⚠️ ");
+        }
+        for b in long {
+            let cmd = if resp.markdown {
+                format!("<code>{}</code>", escape_html(&b.command))
+            } else {
+                b.command.clone()
+            };
+            text.push_str(&format!("\n{}: {cmd}", b.label));
+        }
+        let keyboard = if fits.is_empty() {
+            String::new()
+        } else {
+            let rows: Vec<String> = fits
+                .iter()
+                .map(|b| {
+                    format!(
+                        "[{{\"text\":{},\"callback_data\":{}}}]",
+                        json::quote(&b.label),
+                        json::quote(&b.command)
+                    )
+                })
+                .collect();
+            format!("{{\"inline_keyboard\":[{}]}}", rows.join(","))
+        };
+        Self {
+            text,
+            html: resp.markdown,
+            keyboard,
+            reply_to: None,
+        }
+    }
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--data-urlencode".to_string(),
+            format!("text={}", self.text),
+        ];
+        if self.html {
+            args.extend(["-d".to_string(), "parse_mode=HTML".to_string()]);
+        }
+        if !self.keyboard.is_empty() {
+            args.extend([
+                "--data-urlencode".to_string(),
+                format!("reply_markup={}", self.keyboard),
+            ]);
+        }
+        args
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
 }
 
 fn get_updates(token: &str, offset: i64) -> Result<String, String> {
     let url = format!(
-        "https://api.telegram.org/bot{token}/getUpdates?timeout=25&offset={offset}&allowed_updates=%5B%22message%22%5D"
+        "https://api.telegram.org/bot{token}/getUpdates?timeout=25&offset={offset}&allowed_updates=%5B%22message%22%2C%22callback_query%22%5D"
     );
     run_curl(["-sS", "--max-time", "30", &url])
 }
 
 fn send_message(token: &str, chat_id: i64, text: &str) -> Result<(), String> {
+    post_message(token, chat_id, &Outgoing::plain(text)).map(|_| ())
+}
+
+fn post_message(token: &str, chat_id: i64, out: &Outgoing) -> Result<String, String> {
     let url = format!("https://api.telegram.org/bot{token}/sendMessage");
     let chat = format!("chat_id={chat_id}");
-    let text_arg = format!("text={text}");
-    let _ = run_curl([
+    let mut args: Vec<String> = [
         "-sS",
+        "--fail-with-body",
         "--max-time",
         "30",
         "-X",
@@ -107,8 +473,129 @@ fn send_message(token: &str, chat_id: i64, text: &str) -> Result<(), String> {
         &url,
         "-d",
         &chat,
+    ]
+    .map(String::from)
+    .to_vec();
+    args.extend(out.args());
+    // Still sent if the message was deleted in the meantime.
+    if let Some(id) = out.reply_to {
+        args.extend([
+            "--data-urlencode".to_string(),
+            format!(
+                "reply_parameters={{\"message_id\":{id},\"allow_sending_without_reply\":true}}"
+            ),
+        ]);
+    }
+    run_curl(args)
+}
+
+fn edit_message(token: &str, chat_id: i64, message_id: i64, out: &Outgoing) -> Result<(), String> {
+    let url = format!("https://api.telegram.org/bot{token}/editMessageText");
+    let chat = format!("chat_id={chat_id}");
+    let msg = format!("message_id={message_id}");
+    let mut args: Vec<String> = [
+        "-sS",
+        "--fail-with-body",
+        "--max-time",
+        "10",
+        "-X",
+        "POST",
+        &url,
+        "-d",
+        &chat,
+        "-d",
+        &msg,
+    ]
+    .map(String::from)
+    .to_vec();
+    args.extend(out.args());
+    let _ = run_curl(args)?;
+    Ok(())
+}
+
+// Sends a file with the method matching its type, so images show inline.
+// Files from incoming messages (`telegram:<file_id>`) are re-sent by id and
+// URLs are fetched by Telegram; bytes are uploaded from a temporary file.
+fn send_file(token: &str, chat_id: i64, f: &OutFile) -> Result<(), String> {
+    let (method, field) = match f.mime.as_str() {
+        _ if f.is_image() => ("sendPhoto", "photo"),
+        m if m.starts_with("video/") => ("sendVideo", "video"),
+        "audio/ogg" => ("sendVoice", "voice"),
+        m if m.starts_with("audio/") => ("sendAudio", "audio"),
+        _ => ("sendDocument", "document"),
+    };
+    let url = format!("https://api.telegram.org/bot{token}/{method}");
+    let chat = format!("chat_id={chat_id}");
+    let mut args: Vec<String> = [
+        "-sS",
+        "--fail-with-body",
+        "--max-time",
+        "60",
+        &url,
+        "-F",
+        &chat,
+    ]
+    .map(String::from)
+    .to_vec();
+    match &f.body {
+        FileBody::Url(u) => {
+            let file = u.strip_prefix("telegram:").unwrap_or(u);
+            args.extend(["--form-string".to_string(), format!("{field}={file}")]);
+            run_curl(args).map(|_| ())
+        }
+        FileBody::Bytes(data) => {
+            static SEQ: AtomicU64 = AtomicU64::new(0);
+            let path = env::temp_dir().join(format!(
+                "crabplane-upload-{}-{}",
+                std::process::id(),
+                SEQ.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::write(&path, data).map_err(|e| format!("write {}: {e}", path.display()))?;
+            // `;` and `"` would end curl's form parameters.
+            let name = f.name.replace([';', '"', ','], "_");
+            args.extend([
+                "-F".to_string(),
+                format!("{field}=@{};filename=\"{name}\"", path.display()),
+            ]);
+            let res = run_curl(args);
+            let _ = fs::remove_file(&path);
+            res.map(|_| ())
+        }
+    }
+}
+
+fn delete_message(token: &str, chat_id: i64, message_id: i64) -> Result<(), String> {
+    let url = format!("https://api.telegram.org/bot{token}/deleteMessage");
+    let chat = format!("chat_id={chat_id}");
+    let msg = format!("message_id={message_id}");
+    let _ = run_curl([
+        "-sS",
+        "--fail-with-body",
+        "--max-time",
+        "10",
+        "-X",
+        "POST",
+        &url,
+        "-d",
+        &chat,
+        "-d",
+        &msg,
+    ])?;
+    Ok(())
+}
+
+fn answer_callback(token: &str, callback_id: &str) -> Result<(), String> {
+    let url = format!("https://api.telegram.org/bot{token}/answerCallbackQuery");
+    let id = format!("callback_query_id={callback_id}");
+    let _ = run_curl([
+        "-sS",
+        "--max-time",
+        "10",
+        "-X",
+        "POST",
+        &url,
         "--data-urlencode",
-        &text_arg,
+        &id,
     ])?;
     Ok(())
 }
@@ -143,14 +630,22 @@ fn should_send_typing_status(text: &str) -> bool {
     !t.starts_with('!')
 }
 
-fn run_curl<const N: usize>(args: [&str; N]) -> Result<String, String> {
+fn run_curl<I, S>(args: I) -> Result<String, String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
     let out = Command::new("curl")
         .args(args)
         .output()
         .map_err(|e| format!("failed to execute curl: {e}"))?;
     if !out.status.success() {
         let stderr = String::from_utf8_lossy(&out.stderr);
-        return Err(format!("curl failed: {}", stderr.trim()));
+        // With `--fail-with-body` the API's error description is on stdout.
+        let body = String::from_utf8_lossy(&out.stdout);
+        return Err(format!("curl failed: {} {}", stderr.trim(), body.trim())
+            .trim_end()
+            .to_string());
     }
     Ok(String::from_utf8_lossy(&out.stdout).to_string())
 }
@@ -180,12 +675,163 @@ fn parse_updates(body: &str) -> Vec<TelegramUpdate> {
             .or_else(|| extract_i64_after(chunk, "\"from\": {\"id\":"))
             .map(|v| v.to_string())
             .unwrap_or_else(|| "telegram".to_string());
-        let text = extract_json_string_after(chunk, "\"text\":").unwrap_or_default();
+        // A pressed button carries its command as `data`; the message it
+        // was under follows and must not be read as new text.
+        let callback_id = extract_json_string_after(chunk, "\"callback_query\":{\"id\":");
+        let message_id = extract_i64_after(chunk, "\"message_id\":");
+        let metadata = parse_metadata(chunk, message_id);
+        if callback_id.is_some() {
+            out.push(TelegramUpdate {
+                update_id,
+                chat_id,
+                user_id,
+                text: extract_json_string_after(chunk, "\"data\":").unwrap_or_default(),
+                attachments: Vec::new(),
+                callback_id,
+                message_id,
+                metadata,
+            });
+            continue;
+        }
+        // The replied-to message is embedded whole; only this message's own
+        // text and files count.
+        let chunk = &without_object(chunk, "\"reply_to_message\":");
+        // Photos and documents carry their text as a caption.
+        let text = extract_json_string_after(chunk, "\"text\":")
+            .or_else(|| extract_json_string_after(chunk, "\"caption\":"))
+            .unwrap_or_default();
         out.push(TelegramUpdate {
             update_id,
             chat_id,
             user_id,
             text,
+            attachments: parse_attachments(chunk),
+            callback_id: None,
+            message_id,
+            metadata,
+        });
+    }
+    out
+}
+
+// Sender and chat facts for `Message.metadata`. The first `from` and `chat`
+// objects are the update's own; a replied-to message's come after them.
+fn parse_metadata(chunk: &str, message_id: Option<i64>) -> HashMap<String, String> {
+    let mut m = HashMap::new();
+    if let Some(id) = message_id {
+        m.insert(META_MESSAGE_ID.to_string(), id.to_string());
+    }
+    if let Some(from) = object_after(chunk, "\"from\":") {
+        if let Some(u) = extract_json_string_after(from, "\"username\":") {
+            m.insert(META_USERNAME.to_string(), u);
+        }
+        let name: Vec<String> = ["\"first_name\":", "\"last_name\":"]
+            .iter()
+            .filter_map(|k| extract_json_string_after(from, k))
+            .collect();
+        if !name.is_empty() {
+            m.insert(META_DISPLAY_NAME.to_string(), name.join(" "));
+        }
+    }
+    if let Some(chat) = object_after(chunk, "\"chat\":")
+        && let Some(t) = extract_json_string_after(chat, "\"type\":")
+    {
+        m.insert(META_CHAT_TYPE.to_string(), t);
+    }
+    if let Some(i) = chunk.find("\"reply_to_message\":")
+        && let Some(id) = extract_i64_after(&chunk[i..], "\"message_id\":")
+    {
+        m.insert(META_REPLY_TO.to_string(), id.to_string());
+    }
+    m
+}
+
+// The flat `{...}` object following `key`.
+fn object_after<'a>(s: &'a str, key: &str) -> Option<&'a str> {
+    let start = s.find(key)? + key.len();
+    let obj = s[start..].trim_start().strip_prefix('{')?;
+    Some(&obj[..obj.find('}').unwrap_or(obj.len())])
+}
+
+// `s` with the (possibly nested) object following `key` removed.
+fn without_object(s: &str, key: &str) -> String {
+    let Some(start) = s.find(key) else {
+        return s.to_string();
+    };
+    let bytes = s.as_bytes();
+    let (mut depth, mut in_str, mut esc) = (0usize, false, false);
+    let mut i = start + key.len();
+    while i < bytes.len() {
+        let b = bytes[i];
+        i += 1;
+        if in_str {
+            match b {
+                _ if esc => esc = false,
+                b'\\' => esc = true,
+                b'"' => in_str = false,
+                _ => {}
+            }
+            continue;
+        }
+        match b {
+            b'"' => in_str = true,
+            b'{' => depth += 1,
+            b'}' => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    format!("{}{}", &s[..start], &s[i..])
+}
+
+// Files on a message, referenced as `telegram:<file_id>` (downloading one
+// needs `getFile` and the bot token). Of a photo's sizes only the largest,
+// listed last, is kept.
+fn parse_attachments(chunk: &str) -> Vec<Attachment> {
+    let mut out = Vec::new();
+    if let Some(i) = chunk.find("\"photo\":[") {
+        let photo = &chunk[i..];
+        let photo = &photo[..photo.find(']').unwrap_or(photo.len())];
+        if let Some(j) = photo.rfind("\"file_id\":")
+            && let Some(id) = extract_json_string_after(&photo[j..], "\"file_id\":")
+        {
+            out.push(Attachment {
+                name: "photo.jpg".to_string(),
+                mime: "image/jpeg".to_string(),
+                url: format!("telegram:{id}"),
+            });
+        }
+    }
+    for (key, name, mime) in [
+        ("\"document\":{", "document", "application/octet-stream"),
+        ("\"audio\":{", "audio", "audio/mpeg"),
+        ("\"voice\":{", "voice.ogg", "audio/ogg"),
+        ("\"video\":{", "video.mp4", "video/mp4"),
+    ] {
+        let Some(i) = chunk.find(key) else { continue };
+        let obj = &chunk[i..];
+        // Skip the nested thumbnail objects, which have file ids of their own.
+        let mut fields = obj.to_string();
+        for thumb in ["\"thumbnail\":{", "\"thumb\":{"] {
+            while let Some(t) = fields.find(thumb) {
+                let end = fields[t..].find('}').map_or(fields.len(), |e| t + e + 1);
+                fields.replace_range(t..end, "");
+            }
+        }
+        let obj = &fields[..fields.find('}').unwrap_or(fields.len())];
+        let Some(id) = extract_json_string_after(obj, "\"file_id\":") else {
+            continue;
+        };
+        out.push(Attachment {
+            name: extract_json_string_after(obj, "\"file_name\":")
+                .unwrap_or_else(|| name.to_string()),
+            mime: extract_json_string_after(obj, "\"mime_type\":")
+                .unwrap_or_else(|| mime.to_string()),
+            url: format!("telegram:{id}"),
         });
     }
     out
@@ -275,5 +921,11 @@ fn telegram_help_text() -> &'static str {
      !echo <text> - echo back text\n\
      !onboard [chat|ai|all] - show setup checklist\n\
      !ask <prompt> - run prompt via CRABPLANE_AI_BACKEND\n\
+     !remind <10m|2h|14:30> <text> - send yourself a reminder later\n\
+     !schedule add <cron> <task> [text] | list | rm <id> - recurring jobs\n\
+     !cancel <job-id|last> - stop a queued or running job\n\
+     !jobs - list your recent jobs\n\
+     !status [job-id] - show details of a job\n\
+     !undelivered [resend|drop <job-id|all>] - results that could not be sent\n\
      Any non-command message is routed to !ask."
 }
@@ -1,23 +1,43 @@
 use std::collections::HashMap;
+use std::ffi::OsStr;
 use std::process::Command;
-use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
+use crate::adapters::send_error;
 use crate::engine::{Engine, ResultSink};
-use crate::types::{Job, Message, Response};
+use crate::error::Error;
+use crate::markdown;
+use crate::types::{Attachment, FileBody, Job, META_CHAT_TYPE, META_MESSAGE_ID, Message, Response};
+
+pub const SOURCE: &str = "whatsapp";
 
 pub struct Adapter {
     account_sid: String,
     auth_token: String,
     from_number: String,
     eng: Arc<dyn Engine>,
+    // How many messages of a result were sent before its delivery failed, by
+    // job id; the text is one and each media file another. Retries skip them.
+    sent: Mutex<HashMap<String, usize>>,
 }
 
 impl Adapter {
-    pub fn new(account_sid: String, auth_token: String, from_number: String, eng: Arc<dyn Engine>) -> Self {
-        Self { account_sid, auth_token, from_number, eng }
+    pub fn new(
+        account_sid: String,
+        auth_token: String,
+        from_number: String,
+        eng: Arc<dyn Engine>,
+    ) -> Self {
+        Self {
+            account_sid,
+            auth_token,
+            from_number,
+            eng,
+            sent: Mutex::new(HashMap::new()),
+        }
     }
 
     pub fn run(&self, stop: &AtomicBool) -> Result<(), String> {
@@ -51,23 +71,53 @@ impl Adapter {
                     continue;
                 }
                 if is_help_command(&msg.body) {
-                    let _ = send_message(&self.account_sid, &self.auth_token, &self.from_number, &msg.from, whatsapp_help_text());
+                    let _ = send_message(
+                        &self.account_sid,
+                        &self.auth_token,
+                        &self.from_number,
+                        &msg.from,
+                        whatsapp_help_text(),
+                        None,
+                    );
                     continue;
                 }
 
                 // For WhatsApp, we don't have a typing indicator API like Telegram
                 // Twilio doesn't support WhatsApp typing indicators
 
+                let attachments = msg
+                    .attachments
+                    .into_iter()
+                    .flat_map(|a| self.resolve_media(a))
+                    .collect();
+                // No username or display name: Twilio sends `ProfileName`
+                // only to webhooks, not to the Messages list polled here.
+                // No reply target either, as Twilio cannot send replies.
+                let metadata = HashMap::from([
+                    (META_MESSAGE_ID.to_string(), msg.sid.clone()),
+                    (META_CHAT_TYPE.to_string(), "private".to_string()),
+                ]);
                 let resp = self.eng.handle(Message {
+                    source: SOURCE.to_string(),
                     user_id: msg.from.clone(),
                     channel: msg.from.clone(),
                     text: msg.body,
-                    metadata: HashMap::new(),
+                    attachments,
+                    metadata,
                 });
                 if resp.text.is_empty() {
                     continue;
                 }
-                let _ = send_message(&self.account_sid, &self.auth_token, &self.from_number, &msg.from, &resp.text);
+                // Twilio cannot delete WhatsApp messages, so ephemeral replies
+                // are sent like any other; the chats are one-to-one anyway.
+                let _ = send_message(
+                    &self.account_sid,
+                    &self.auth_token,
+                    &self.from_number,
+                    &msg.from,
+                    &shown_text(&resp),
+                    None,
+                );
             }
         }
 
@@ -77,15 +127,105 @@ impl Adapter {
     pub fn close(&self) -> Result<(), String> {
         Ok(())
     }
+
+    // Replaces a message's media list with one attachment per file, linked
+    // by its media URL. If the list can't be read the list itself is kept.
+    fn resolve_media(&self, list: Attachment) -> Vec<Attachment> {
+        let auth = format!("{}:{}", self.account_sid, self.auth_token);
+        let Ok(body) = run_curl(["-sS", "--fail", "--max-time", "30", "-u", &auth, &list.url])
+        else {
+            return vec![list];
+        };
+        let media = parse_media(&body);
+        if media.is_empty() { vec![list] } else { media }
+    }
 }
 
 impl ResultSink for Adapter {
-    fn deliver(&self, job: &Job, resp: &Response) -> Result<(), String> {
-        if resp.text.is_empty() {
-            return Ok(());
+    // WhatsApp takes one media file per message, by a URL Twilio can fetch,
+    // so each linked file follows the text as its own message. Files sent as
+    // bytes and buttons are listed in the text.
+    fn deliver(&self, job: &Job, resp: &Response) -> Result<(), Error> {
+        let mut text = shown_text(resp);
+        for b in &resp.buttons {
+            text.push_str(&format!("\n{}: reply {}", b.label, b.command));
         }
+        let mut media = Vec::new();
+        for f in &resp.files {
+            match &f.body {
+                FileBody::Url(u) if u.starts_with("https://") || u.starts_with("http://") => {
+                    media.push(u.as_str())
+                }
+                _ => text.push_str(&format!("\n[{} can't be sent on WhatsApp]", f.name)),
+            }
+        }
+        let text = text.trim();
+
         // channel_id should be the WhatsApp number in E.164 format
-        send_message(&self.account_sid, &self.auth_token, &self.from_number, &job.channel_id, &resp.text)
+        let send = |text: &str, media: Option<&str>| {
+            send_message(
+                &self.account_sid,
+                &self.auth_token,
+                &self.from_number,
+                &job.channel_id,
+                text,
+                media,
+            )
+            .map_err(send_error)
+        };
+        let done = self
+            .sent
+            .lock()
+            .ok()
+            .and_then(|g| g.get(&job.id).copied())
+            .unwrap_or(0);
+        let record = |n: usize| {
+            if let Ok(mut g) = self.sent.lock() {
+                g.insert(job.id.clone(), n);
+            }
+        };
+        let parts = (!text.is_empty())
+            .then_some((text, None))
+            .into_iter()
+            .chain(media.into_iter().map(|url| ("", Some(url))));
+        for (i, (text, media)) in parts.enumerate().skip(done) {
+            send(text, media)?;
+            record(i + 1);
+        }
+        if let Ok(mut g) = self.sent.lock() {
+            g.remove(&job.id);
+        }
+        Ok(())
+    }
+
+    fn deliver_progress(&self, job: &Job, note: &str) -> Result<(), Error> {
+        send_message(
+            &self.account_sid,
+            &self.auth_token,
+            &self.from_number,
+            &job.channel_id,
+            note,
+            None,
+        )
+        .map_err(send_error)
+    }
+
+    fn forget(&self, job: &Job) {
+        if let Ok(mut g) = self.sent.lock() {
+            g.remove(&job.id);
+        }
+    }
+}
+
+fn shown_text(resp: &Response) -> String {
+    let text = if resp.markdown {
+        markdown::to_whatsapp(&resp.text)
+    } else {
+        resp.text.clone()
+    };
+    match resp.error {
+        Some(_) => format!("⚠️ {text}"),
+        None => text,
     }
 }
 
@@ -94,6 +234,7 @@ struct WhatsAppMessage {
     sid: String,
     from: String,
     body: String,
+    attachments: Vec<Attachment>,
 }
 
 fn get_messages(account_sid: &str, auth_token: &str, from_number: &str) -> Result<String, String> {
@@ -106,7 +247,14 @@ fn get_messages(account_sid: &str, auth_token: &str, from_number: &str) -> Resul
     run_curl(["-sS", "--max-time", "30", "-u", &auth, &url])
 }
 
-fn send_message(account_sid: &str, auth_token: &str, from_number: &str, to_number: &str, text: &str) -> Result<(), String> {
+fn send_message(
+    account_sid: &str,
+    auth_token: &str,
+    from_number: &str,
+    to_number: &str,
+    text: &str,
+    media_url: Option<&str>,
+) -> Result<(), String> {
     let url = format!(
         "https://api.twilio.com/2010-04-01/Accounts/{}/Messages.json",
         account_sid
@@ -116,35 +264,48 @@ fn send_message(account_sid: &str, auth_token: &str, from_number: &str, to_numbe
     // Format numbers with whatsapp: prefix
     let from_arg = format!("From=whatsapp:{}", from_number);
     let to_arg = format!("To=whatsapp:{}", to_number);
-    let body_arg = format!("Body={}", text);
-
-    let _ = run_curl([
-        "-sS",
-        "--max-time",
-        "30",
-        "-u",
-        &auth,
-        "-X",
-        "POST",
-        &url,
-        "-d",
-        &from_arg,
-        "-d",
-        &to_arg,
-        "--data-urlencode",
-        &body_arg,
-    ])?;
+    let mut args = vec![
+        "-sS".to_string(),
+        "--fail-with-body".to_string(),
+        "--max-time".to_string(),
+        "30".to_string(),
+        "-u".to_string(),
+        auth,
+        "-X".to_string(),
+        "POST".to_string(),
+        url,
+        "-d".to_string(),
+        from_arg,
+        "-d".to_string(),
+        to_arg,
+    ];
+    if !text.is_empty() {
+        args.extend(["--data-urlencode".to_string(), format!("Body={}", text)]);
+    }
+    if let Some(m) = media_url {
+        args.extend(["--data-urlencode".to_string(), format!("MediaUrl={m}")]);
+    }
+
+    let _ = run_curl(args)?;
     Ok(())
 }
 
-fn run_curl<const N: usize>(args: [&str; N]) -> Result<String, String> {
+fn run_curl<I, S>(args: I) -> Result<String, String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
     let out = Command::new("curl")
         .args(args)
         .output()
         .map_err(|e| format!("failed to execute curl: {e}"))?;
     if !out.status.success() {
         let stderr = String::from_utf8_lossy(&out.stderr);
-        return Err(format!("curl failed: {}", stderr.trim()));
+        // With `--fail-with-body` the API's error description is on stdout.
+        let body = String::from_utf8_lossy(&out.stdout);
+        return Err(format!("curl failed: {} {}", stderr.trim(), body.trim())
+            .trim_end()
+            .to_string());
     }
     Ok(String::from_utf8_lossy(&out.stdout).to_string())
 }
@@ -184,10 +345,25 @@ fn parse_messages(body: &str) -> Vec<WhatsAppMessage> {
 
         let body_text = extract_json_string_after(chunk, "\"body\":").unwrap_or_default();
 
+        // Media is listed under the message's media subresource, which needs
+        // the account credentials to read; `Adapter::resolve_media` expands it.
+        let mut attachments = Vec::new();
+        let num_media = extract_json_string_after(chunk, "\"num_media\":").unwrap_or_default();
+        if num_media.parse::<u32>().unwrap_or(0) > 0
+            && let Some(uri) = extract_json_string_after(chunk, "\"media\":")
+        {
+            attachments.push(Attachment {
+                name: format!("{num_media} media file(s)"),
+                mime: String::new(),
+                url: format!("https://api.twilio.com{uri}"),
+            });
+        }
+
         out.push(WhatsAppMessage {
             sid,
             from,
             body: body_text,
+            attachments,
         });
     }
 
@@ -196,6 +372,46 @@ fn parse_messages(body: &str) -> Vec<WhatsAppMessage> {
     out
 }
 
+// The files in a Media list response. A file's URL is its resource URI
+// without `.json`.
+fn parse_media(body: &str) -> Vec<Attachment> {
+    let Some(start) = body.find("\"media_list\":") else {
+        return Vec::new();
+    };
+    let list = &body[start..];
+    let list = &list[..list.find(']').unwrap_or(list.len())];
+    let mut out = Vec::new();
+    for item in list.split('}') {
+        let Some(uri) = extract_json_string_after(item, "\"uri\":") else {
+            continue;
+        };
+        let Some(path) = uri.strip_suffix(".json") else {
+            continue;
+        };
+        let mime = extract_json_string_after(item, "\"content_type\":").unwrap_or_default();
+        let sid = path.rsplit('/').next().unwrap_or_default();
+        out.push(Attachment {
+            name: format!("{sid}{}", extension(&mime)),
+            mime,
+            url: format!("https://api.twilio.com{path}"),
+        });
+    }
+    out
+}
+
+fn extension(mime: &str) -> &'static str {
+    match mime {
+        "image/jpeg" => ".jpg",
+        "image/png" => ".png",
+        "image/webp" => ".webp",
+        "audio/ogg" => ".ogg",
+        "audio/mpeg" => ".mp3",
+        "video/mp4" => ".mp4",
+        "application/pdf" => ".pdf",
+        _ => "",
+    }
+}
+
 fn extract_json_string_after(s: &str, marker: &str) -> Option<String> {
     let idx = s.find(marker)?;
     let bytes = s.as_bytes();
@@ -253,6 +469,12 @@ fn whatsapp_help_text() -> &'static str {
      !echo <text> - echo back text\n\
      !onboard [chat|ai|all] - show setup checklist\n\
      !ask <prompt> - run prompt via CRABPLANE_AI_BACKEND\n\
+     !remind <10m|2h|14:30> <text> - send yourself a reminder later\n\
+     !schedule add <cron> <task> [text] | list | rm <id> - recurring jobs\n\
+     !cancel <job-id|last> - stop a queued or running job\n\
+     !jobs - list your recent jobs\n\
+     !status [job-id] - show details of a job\n\
+     !undelivered [resend|drop <job-id|all>] - results that could not be sent\n\
      Any non-command message is routed to !ask."
 }
 
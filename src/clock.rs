@@ -0,0 +1,284 @@
+use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Parses `500ms`, `10s`, `5m`, `2h`, `1d`; a bare number is seconds.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    if let Some(v) = s.strip_suffix("ms") {
+        return v.trim().parse::<u64>().ok().map(Duration::from_millis);
+    }
+    let units = [('s', 1), ('m', 60), ('h', 60 * 60), ('d', 24 * 60 * 60)];
+    for (suffix, secs) in units {
+        if let Some(v) = s.strip_suffix(suffix) {
+            let n = v.trim().parse::<u64>().ok()?;
+            return n.checked_mul(secs).map(Duration::from_secs);
+        }
+    }
+    // If no suffix, treat as seconds.
+    s.parse::<u64>().ok().map(Duration::from_secs)
+}
+
+// Parses a point in time at the start of `s` and returns it with the rest of
+// the string. Accepted forms:
+// - a duration from `now`: `10m`, `2h`
+// - a wall-clock time: `14:30` (today, or tomorrow if already past)
+// - a date and time: `2026-10-20 09:00` or `2026-10-20T09:00`
+// Wall-clock times use `CRABPLANE_UTC_OFFSET` (e.g. `+09:00`; default UTC).
+// Times more than `MAX_AHEAD` after `now` are rejected.
+pub fn parse_when(s: &str, now: SystemTime) -> Option<(SystemTime, &str)> {
+    let (at, rest) = when_at(s, now)?;
+    match at.duration_since(now) {
+        Ok(ahead) if ahead > MAX_AHEAD => None,
+        _ => Some((at, rest)),
+    }
+}
+
+// How far ahead `parse_when` accepts: about five years.
+const MAX_AHEAD: Duration = Duration::from_secs(5 * 366 * 24 * 60 * 60);
+
+fn when_at(s: &str, now: SystemTime) -> Option<(SystemTime, &str)> {
+    let s = s.trim_start();
+    let (first, rest) = split_token(s);
+
+    if let (Some(date), (second, rest2)) = (parse_date(first), split_token(rest))
+        && let Some(hm) = parse_hm(second)
+    {
+        return Some((from_local(date, hm)?, rest2));
+    }
+    if let Some((d, t)) = first.split_once('T')
+        && let (Some(date), Some(hm)) = (parse_date(d), parse_hm(t))
+    {
+        return Some((from_local(date, hm)?, rest));
+    }
+    if let Some(hm) = parse_hm(first) {
+        let today = local_days(now);
+        let mut at = from_local(civil_from_days(today), hm)?;
+        if at <= now {
+            at = from_local(civil_from_days(today + 1), hm)?;
+        }
+        return Some((at, rest));
+    }
+    // Durations need an explicit unit here, so a bare number stays text.
+    if first.ends_with(|c: char| c.is_ascii_alphabetic()) {
+        let d = parse_duration(first)?;
+        return Some((now.checked_add(d)?, rest));
+    }
+    None
+}
+
+// Local calendar position of `t`: (days since the epoch, minute of the day).
+pub fn local_minute(t: SystemTime) -> (i64, u32) {
+    let secs = local_secs(t);
+    (
+        secs.div_euclid(86_400),
+        (secs.rem_euclid(86_400) / 60) as u32,
+    )
+}
+
+// Inverse of `local_minute`.
+pub fn from_local_minute(day: i64, minute: u32) -> Option<SystemTime> {
+    let secs = day * 86_400 + i64::from(minute) * 60 - offset_secs();
+    u64::try_from(secs)
+        .ok()
+        .map(|s| UNIX_EPOCH + Duration::from_secs(s))
+}
+
+// (year, month, day, weekday with 0 = Sunday) of a local day number.
+pub fn local_date(day: i64) -> (i64, u32, u32, u32) {
+    let (y, m, d) = civil_from_days(day);
+    // 1970-01-01 was a Thursday.
+    (y, m, d, (day + 4).rem_euclid(7) as u32)
+}
+
+// `t` as an RFC 3339 UTC timestamp with milliseconds, for logs.
+pub fn rfc3339(t: SystemTime) -> String {
+    let d = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = d.as_secs() as i64;
+    let (y, m, day) = civil_from_days(secs.div_euclid(86_400));
+    let s = secs.rem_euclid(86_400);
+    format!(
+        "{y:04}-{m:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        s / 3600,
+        s % 3600 / 60,
+        s % 60,
+        d.subsec_millis()
+    )
+}
+
+// Offset from UTC for wall-clock times, in seconds, from
+// `CRABPLANE_UTC_OFFSET` (`+09:00`, `-0530`, `+9`).
+fn offset_secs() -> i64 {
+    let raw = env::var("CRABPLANE_UTC_OFFSET").unwrap_or_default();
+    let raw = raw.trim();
+    let (sign, digits) = match raw.strip_prefix('-') {
+        Some(d) => (-1, d),
+        None => (1, raw.strip_prefix('+').unwrap_or(raw)),
+    };
+    let digits = digits.replace(':', "");
+    let (h, m) = match digits.len() {
+        1 | 2 => (digits.parse::<i64>().ok(), Some(0)),
+        3 | 4 => {
+            let split = digits.len() - 2;
+            (
+                digits[..split].parse::<i64>().ok(),
+                digits[split..].parse::<i64>().ok(),
+            )
+        }
+        _ => (None, None),
+    };
+    match (h, m) {
+        (Some(h), Some(m)) if h <= 14 && m < 60 => sign * (h * 3600 + m * 60),
+        _ => 0,
+    }
+}
+
+fn split_token(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+    match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], s[i..].trim_start()),
+        None => (s, ""),
+    }
+}
+
+fn parse_date(s: &str) -> Option<(i64, u32, u32)> {
+    let mut it = s.splitn(3, '-');
+    let y = it.next()?.parse::<i64>().ok()?;
+    let m = it.next()?.parse::<u32>().ok()?;
+    let d = it.next()?.parse::<u32>().ok()?;
+    if !(1..=12).contains(&m) || !(1..=31).contains(&d) || s.len() != 10 {
+        return None;
+    }
+    Some((y, m, d))
+}
+
+fn parse_hm(s: &str) -> Option<(u32, u32)> {
+    let (h, m) = s.split_once(':')?;
+    let h = h.parse::<u32>().ok()?;
+    let m = m.parse::<u32>().ok()?;
+    if h > 23 || m > 59 {
+        return None;
+    }
+    Some((h, m))
+}
+
+fn from_local((y, m, d): (i64, u32, u32), (hh, mm): (u32, u32)) -> Option<SystemTime> {
+    let secs = days_from_civil(y, m, d) * 86_400 + i64::from(hh) * 3600 + i64::from(mm) * 60
+        - offset_secs();
+    u64::try_from(secs)
+        .ok()
+        .map(|s| UNIX_EPOCH + Duration::from_secs(s))
+}
+
+// Days since the epoch of the local date at `t`.
+fn local_days(t: SystemTime) -> i64 {
+    local_secs(t).div_euclid(86_400)
+}
+
+fn local_secs(t: SystemTime) -> i64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64 + offset_secs()
+}
+
+// Howard Hinnant's days_from_civil / civil_from_days (proleptic Gregorian).
+fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = i64::from(m);
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + i64::from(d) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(z: i64) -> (i64, u32, u32) {
+    let z = z + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + i64::from(m <= 2);
+    (y, m, d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    // These assume `CRABPLANE_UTC_OFFSET` is unset, i.e. local time is UTC.
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration(" 1d "), Some(Duration::from_secs(86_400)));
+        assert_eq!(parse_duration("soon"), None);
+    }
+
+    #[test]
+    fn parses_dates_and_times() {
+        // 2026-10-20 10:00 UTC, a Tuesday.
+        let now = at(1_792_490_400);
+        assert_eq!(
+            parse_when("2026-10-20 09:00 standup", now),
+            Some((at(1_792_486_800), "standup"))
+        );
+        assert_eq!(
+            parse_when("2026-10-20T09:00 standup", now),
+            Some((at(1_792_486_800), "standup"))
+        );
+        assert_eq!(
+            parse_when("14:30 tea", now),
+            Some((at(1_792_506_600), "tea"))
+        );
+        // Already past today, so tomorrow.
+        assert_eq!(
+            parse_when("09:30 tea", now),
+            Some((at(1_792_575_000), "tea"))
+        );
+        assert_eq!(
+            parse_when("  2h  call mum", now),
+            Some((at(1_792_497_600), "call mum"))
+        );
+    }
+
+    #[test]
+    fn leaves_other_text_alone() {
+        let now = at(1_792_490_400);
+        assert_eq!(parse_when("5 apples", now), None);
+        assert_eq!(parse_when("25:00 x", now), None);
+        assert_eq!(parse_when("2026-13-01 09:00 x", now), None);
+        assert_eq!(parse_when("tomorrow x", now), None);
+    }
+
+    #[test]
+    fn formats_rfc3339() {
+        assert_eq!(
+            rfc3339(at(1_792_486_800) + Duration::from_millis(7)),
+            "2026-10-20T09:00:00.007Z"
+        );
+    }
+
+    #[test]
+    fn durations_do_not_overflow() {
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("99999999999999999d"), None);
+        assert_eq!(
+            parse_duration("18446744073709551615s"),
+            Some(Duration::from_secs(u64::MAX))
+        );
+    }
+
+    #[test]
+    fn reminders_beyond_the_horizon_are_rejected() {
+        let now = at(1_800_000_000);
+        assert_eq!(parse_when("10m hi", now), Some((at(1_800_000_600), "hi")));
+        assert_eq!(parse_when("99999999999999999d hi", now), None);
+        assert_eq!(parse_when("18446744073709551615s hi", now), None);
+        assert_eq!(parse_when("3650d hi", now), None);
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::jobs::{fmt_dur, short_id};
+use crate::journal::{
+    decode_fields, decode_job, encode_fields, encode_job, from_unix_millis, unix_millis,
+};
+use crate::types::{Button, FileBody, Job, OutFile};
+
+const LOG_FILE: &str = "undelivered.log";
+
+// How much of a result `!undelivered` shows per entry.
+const PREVIEW_LEN: usize = 60;
+
+// DeadLetter is a finished job whose result could not be delivered.
+#[derive(Clone, Debug)]
+pub struct DeadLetter {
+    pub job: Job,
+    pub text: String,
+    // `text` is Markdown.
+    pub markdown: bool,
+    pub files: Vec<OutFile>,
+    pub buttons: Vec<Button>,
+    pub err: String,
+    pub failed_at: SystemTime,
+}
+
+impl DeadLetter {
+    // One-line summary for `!undelivered`.
+    pub fn summary(&self) -> String {
+        let mut preview: String = self.text.chars().take(PREVIEW_LEN).collect();
+        if preview.len() < self.text.len() {
+            preview.push_str("...");
+        }
+        if !self.files.is_empty() {
+            preview.push_str(&format!(" [+{} file(s)]", self.files.len()));
+        }
+        format!(
+            "{} {} to {}:{} {} ago: {} ({})",
+            short_id(&self.job.id),
+            self.job.task_name,
+            self.job.source,
+            self.job.channel_id,
+            fmt_dur(self.failed_at.elapsed().unwrap_or_default()),
+            preview.replace('\n', " "),
+            self.err
+        )
+    }
+}
+
+// DeadLetters keeps results that ran out of delivery attempts so they can be
+// inspected and re-sent. With a directory it is backed by an append-only log
+// (`D` adds an entry, `R` removes one) compacted on open, like the queue
+// journal; otherwise entries only live in memory.
+pub struct DeadLetters {
+    log: Option<(PathBuf, Mutex<File>)>,
+    entries: Mutex<Vec<DeadLetter>>,
+}
+
+impl DeadLetters {
+    pub fn new() -> Self {
+        Self {
+            log: None,
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn open(dir: &Path) -> Result<Self, String> {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("dead letters: create dir {}: {e}", dir.display()))?;
+        let path = dir.join(LOG_FILE);
+        let entries = if path.exists() {
+            replay(&path)?
+        } else {
+            Vec::new()
+        };
+
+        let tmp = dir.join(format!("{LOG_FILE}.tmp"));
+        {
+            let mut f = File::create(&tmp)
+                .map_err(|e| format!("dead letters: create {}: {e}", tmp.display()))?;
+            for d in &entries {
+                writeln!(f, "D\t{}", encode(d))
+                    .map_err(|e| format!("dead letters: write {}: {e}", tmp.display()))?;
+            }
+            f.sync_all()
+                .map_err(|e| format!("dead letters: sync {}: {e}", tmp.display()))?;
+        }
+        fs::rename(&tmp, &path)
+            .map_err(|e| format!("dead letters: rename {}: {e}", path.display()))?;
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("dead letters: open {}: {e}", path.display()))?;
+
+        Ok(Self {
+            log: Some((path, Mutex::new(file))),
+            entries: Mutex::new(entries),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().map(|g| g.len()).unwrap_or(0)
+    }
+
+    pub fn add(&self, d: DeadLetter) -> Result<(), String> {
+        self.write_record(&format!("D\t{}", encode(&d)))?;
+        let mut g = self
+            .entries
+            .lock()
+            .map_err(|_| "dead letters unavailable".to_string())?;
+        g.retain(|e| e.job.id != d.job.id);
+        g.push(d);
+        Ok(())
+    }
+
+    // Entries `owner`, a (source, user id) pair, may see, oldest first; None
+    // lists everything.
+    pub fn list(&self, owner: Option<(&str, &str)>) -> Vec<DeadLetter> {
+        let Ok(g) = self.entries.lock() else {
+            return Vec::new();
+        };
+        g.iter()
+            .filter(|d| owner.is_none_or(|(s, u)| d.job.source == s && d.job.user_id == u))
+            .cloned()
+            .collect()
+    }
+
+    // Errors if there is no entry for `job_id`.
+    pub fn remove(&self, job_id: &str) -> Result<(), String> {
+        let mut g = self
+            .entries
+            .lock()
+            .map_err(|_| "dead letters unavailable".to_string())?;
+        let idx = g
+            .iter()
+            .position(|d| d.job.id == job_id)
+            .ok_or_else(|| format!("no undelivered result {job_id}"))?;
+        self.write_record(&format!(
+            "R\t{}",
+            encode_fields(&[("id", job_id.to_string())])
+        ))?;
+        g.remove(idx);
+        Ok(())
+    }
+
+    fn write_record(&self, line: &str) -> Result<(), String> {
+        let Some((path, file)) = &self.log else {
+            return Ok(());
+        };
+        let mut f = file
+            .lock()
+            .map_err(|_| "dead letters: poisoned lock".to_string())?;
+        writeln!(f, "{line}")
+            .map_err(|e| format!("dead letters: write {}: {e}", path.display()))?;
+        f.sync_data()
+            .map_err(|e| format!("dead letters: sync {}: {e}", path.display()))
+    }
+}
+
+fn replay(path: &Path) -> Result<Vec<DeadLetter>, String> {
+    let f = File::open(path).map_err(|e| format!("dead letters: open {}: {e}", path.display()))?;
+    let mut entries: Vec<DeadLetter> = Vec::new();
+    for line in BufReader::new(f).lines() {
+        let line = line.map_err(|e| format!("dead letters: read {}: {e}", path.display()))?;
+        if let Some(rest) = line.strip_prefix("D\t") {
+            let Some(d) = decode(rest) else { continue };
+            entries.retain(|e| e.job.id != d.job.id);
+            entries.push(d);
+        } else if let Some(rest) = line.strip_prefix("R\t")
+            && let Some(id) = decode_fields(rest).get("id")
+        {
+            entries.retain(|e| &e.job.id != id);
+        }
+    }
+    Ok(entries)
+}
+
+// A dead letter is the job's journal record plus the result fields. Files
+// are numbered `file0_name`, `file0_mime` and either `file0_url` or
+// `file0_hex` (the bytes, hex-encoded); buttons `button0_label` and
+// `button0_command`.
+fn encode(d: &DeadLetter) -> String {
+    let mut parts = vec![
+        encode_job(&d.job),
+        encode_fields(&[
+            ("result", d.text.clone()),
+            ("markdown", if d.markdown { "1" } else { "" }.to_string()),
+            ("err", d.err.clone()),
+            ("failed_at", unix_millis(d.failed_at).to_string()),
+        ]),
+    ];
+    for (i, f) in d.files.iter().enumerate() {
+        let body = match &f.body {
+            FileBody::Url(url) => ("url", url.clone()),
+            FileBody::Bytes(b) => ("hex", to_hex(b)),
+        };
+        parts.push(numbered(
+            "file",
+            i,
+            &[("name", f.name.clone()), ("mime", f.mime.clone()), body],
+        ));
+    }
+    for (i, b) in d.buttons.iter().enumerate() {
+        parts.push(numbered(
+            "button",
+            i,
+            &[("label", b.label.clone()), ("command", b.command.clone())],
+        ));
+    }
+    parts.join("\t")
+}
+
+// Fields of the `i`th file or button, keyed `<prefix><i>_<key>`.
+fn numbered(prefix: &str, i: usize, fields: &[(&str, String)]) -> String {
+    let keys: Vec<String> = fields
+        .iter()
+        .map(|(k, _)| format!("{prefix}{i}_{k}"))
+        .collect();
+    let fields: Vec<(&str, String)> = keys
+        .iter()
+        .zip(fields)
+        .map(|(k, (_, v))| (k.as_str(), v.clone()))
+        .collect();
+    encode_fields(&fields)
+}
+
+fn decode(s: &str) -> Option<DeadLetter> {
+    let job = decode_job(s)?;
+    let f = decode_fields(s);
+    Some(DeadLetter {
+        job,
+        text: f.get("result").cloned().unwrap_or_default(),
+        markdown: f.get("markdown").is_some_and(|v| v == "1"),
+        files: decode_files(&f),
+        buttons: decode_buttons(&f),
+        err: f.get("err").cloned().unwrap_or_default(),
+        failed_at: f
+            .get("failed_at")
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(from_unix_millis)
+            .unwrap_or_else(SystemTime::now),
+    })
+}
+
+fn decode_files(f: &HashMap<String, String>) -> Vec<OutFile> {
+    let mut out = Vec::new();
+    loop {
+        let i = out.len();
+        let body = if let Some(url) = f.get(&format!("file{i}_url")) {
+            FileBody::Url(url.clone())
+        } else if let Some(b) = f.get(&format!("file{i}_hex")).and_then(|h| from_hex(h)) {
+            FileBody::Bytes(b)
+        } else {
+            return out;
+        };
+        out.push(OutFile {
+            name: f.get(&format!("file{i}_name")).cloned().unwrap_or_default(),
+            mime: f.get(&format!("file{i}_mime")).cloned().unwrap_or_default(),
+            body,
+        });
+    }
+}
+
+fn decode_buttons(f: &HashMap<String, String>) -> Vec<Button> {
+    let mut out = Vec::new();
+    while let Some(command) = f.get(&format!("button{}_command", out.len())) {
+        let label = format!("button{}_label", out.len());
+        out.push(Button {
+            label: f.get(&label).cloned().unwrap_or_default(),
+            command: command.clone(),
+        });
+    }
+    out
+}
+
+fn to_hex(b: &[u8]) -> String {
+    b.iter().map(|c| format!("{c:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
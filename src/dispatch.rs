@@ -0,0 +1,179 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use crate::log;
+use crate::metrics;
+use crate::worker::ResultItem;
+
+// Results for one (source, channel) pair are delivered in order; distinct
+// channels are delivered in parallel.
+type ChannelKey = (String, String);
+
+// Dispatcher fans results out to per-channel delivery threads. A channel with
+// pending results holds one of its sink's slots while it drains; at most
+// `CRABPLANE_DELIVERY_CONCURRENCY` (default 4) channels per sink are in flight
+// and the rest wait their turn in arrival order.
+pub struct Dispatcher {
+    deliver: Box<dyn Fn(ResultItem) + Send + Sync>,
+    limit: usize,
+    state: Mutex<State>,
+    idle: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    pending: HashMap<ChannelKey, VecDeque<ResultItem>>,
+    active: HashSet<ChannelKey>,
+    waiting: VecDeque<ChannelKey>,
+    in_flight: HashMap<String, usize>,
+}
+
+impl Dispatcher {
+    pub fn new(deliver: impl Fn(ResultItem) + Send + Sync + 'static) -> Arc<Self> {
+        let limit = env::var("CRABPLANE_DELIVERY_CONCURRENCY")
+            .ok()
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .unwrap_or(4)
+            .max(1);
+        Arc::new(Self {
+            deliver: Box::new(deliver),
+            limit,
+            state: Mutex::new(State::default()),
+            idle: Condvar::new(),
+        })
+    }
+
+    pub fn submit(self: &Arc<Self>, res: ResultItem) {
+        let key = (res.job.source.clone(), res.job.channel_id.clone());
+        let Ok(mut g) = self.state.lock() else { return };
+        g.pending.entry(key.clone()).or_default().push_back(res);
+        if g.active.contains(&key) || g.waiting.contains(&key) {
+            return;
+        }
+        if self.has_slot(&g, &key.0) {
+            self.start(&mut g, key);
+        } else {
+            g.waiting.push_back(key);
+        }
+    }
+
+    // Blocks until everything submitted so far has been delivered.
+    pub fn wait_idle(&self) {
+        let Ok(mut g) = self.state.lock() else { return };
+        while !g.active.is_empty() || !g.waiting.is_empty() {
+            match self.idle.wait(g) {
+                Ok(ng) => g = ng,
+                Err(_) => return,
+            }
+        }
+    }
+
+    fn has_slot(&self, g: &State, source: &str) -> bool {
+        g.in_flight.get(source).copied().unwrap_or(0) < self.limit
+    }
+
+    fn start(self: &Arc<Self>, g: &mut State, key: ChannelKey) {
+        *g.in_flight.entry(key.0.clone()).or_default() += 1;
+        g.active.insert(key.clone());
+        let d = Arc::clone(self);
+        thread::spawn(move || d.drain(key));
+    }
+
+    fn drain(self: Arc<Self>, key: ChannelKey) {
+        loop {
+            let next = self.state.lock().ok().and_then(|mut g| {
+                let res = g.pending.get_mut(&key).and_then(|q| q.pop_front());
+                if res.is_none() {
+                    self.finish(&mut g, &key);
+                }
+                res
+            });
+            let Some(res) = next else { return };
+            // A panicking sink counts as a failed delivery; the channel keeps
+            // draining so its slot is released. The job stays in the journal.
+            let job = res.job.clone();
+            if panic::catch_unwind(AssertUnwindSafe(|| (self.deliver)(res))).is_err() {
+                metrics::delivery_failed(&job.source);
+                log::error(
+                    "delivery panicked",
+                    &[
+                        ("job_id", &job.id),
+                        ("task", &job.task_name),
+                        ("channel", &job.channel_id),
+                        ("source", &job.source),
+                    ],
+                );
+            }
+        }
+    }
+
+    // Releases `key`'s slot and hands it to the oldest waiting channel of the
+    // same sink.
+    fn finish(self: &Arc<Self>, g: &mut State, key: &ChannelKey) {
+        g.pending.remove(key);
+        g.active.remove(key);
+        if let Some(n) = g.in_flight.get_mut(&key.0) {
+            *n = n.saturating_sub(1);
+        }
+        if let Some(i) = g.waiting.iter().position(|k| k.0 == key.0)
+            && let Some(next) = g.waiting.remove(i)
+        {
+            self.start(g, next);
+        }
+        self.idle.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tasks::TaskOutput;
+    use crate::types::Job;
+    use std::time::{Duration, SystemTime};
+
+    fn result(id: &str, channel: &str) -> ResultItem {
+        ResultItem {
+            job: Job {
+                channel_id: channel.to_string(),
+                ..Job::test(id, "echo", "u")
+            },
+            output: TaskOutput::None,
+            err: None,
+            finished_at: SystemTime::now(),
+            dur: Duration::ZERO,
+            worker: 0,
+        }
+    }
+
+    #[test]
+    fn delivers_in_order_per_channel() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let s = Arc::clone(&seen);
+        let d = Dispatcher::new(move |res| s.lock().unwrap().push(res.job.id));
+        for id in ["1", "2", "3"] {
+            d.submit(result(id, "c"));
+        }
+        d.wait_idle();
+        assert_eq!(*seen.lock().unwrap(), ["1", "2", "3"]);
+    }
+
+    #[test]
+    fn a_panicking_sink_does_not_block_the_channel() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let s = Arc::clone(&seen);
+        let d = Dispatcher::new(move |res| {
+            if res.job.id == "boom" {
+                panic!("sink failed");
+            }
+            s.lock().unwrap().push(res.job.id);
+        });
+        d.submit(result("boom", "c"));
+        d.wait_idle();
+        d.submit(result("after", "c"));
+        d.wait_idle();
+        assert_eq!(*seen.lock().unwrap(), ["after"]);
+    }
+}
@@ -1,27 +1,129 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, RwLock, mpsc};
 use std::thread::{self, JoinHandle};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use crate::adapters::cli;
+use crate::deadletter::{DeadLetter, DeadLetters};
+use crate::dispatch::Dispatcher;
+use crate::error::{Error, Kind};
+use crate::jobs::{JobEntry, JobTable, fmt_dur, short_id};
+use crate::log;
+use crate::metrics;
+use crate::middleware::Middleware;
+use crate::queue::QueueError;
 use crate::registry::Registry;
-use crate::router::Router;
-use crate::tasks::TaskOutput;
-use crate::types::{Job, Message, Response};
+use crate::router::{Router, parse_args};
+use crate::scheduler::Scheduler;
+use crate::tasks::{Rich, TaskOutput};
+use crate::types::{Job, Message, Response, TaskInput};
 use crate::worker::{Pool, ResultItem};
 
+// Result delivery is retried this many times, starting DELIVERY_BACKOFF apart
+// and doubling, before the result is moved to the dead letters.
+const DELIVERY_ATTEMPTS: u32 = 5;
+const DELIVERY_BACKOFF: Duration = Duration::from_secs(1);
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub trait Engine: Send + Sync {
     fn handle(&self, msg: Message) -> Response;
 }
 
 pub trait ResultSink: Send + Sync {
-    fn deliver(&self, job: &Job, resp: &Response) -> Result<(), String>;
+    fn deliver(&self, job: &Job, resp: &Response) -> Result<(), Error>;
+
+    // Partial output of a running job; `chunk` is only the new text. Sinks
+    // that cannot show progress ignore it and wait for `deliver`, which
+    // always follows with the complete output.
+    fn deliver_chunk(&self, _job: &Job, _chunk: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // An interim note from a running job, shown apart from its result.
+    // Sinks may drop it.
+    fn deliver_progress(&self, _job: &Job, _note: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // Delivery of `job`'s result was given up on; sinks drop anything they
+    // kept to resume it. A resend from the dead letters starts over.
+    fn forget(&self, _job: &Job) {}
+}
+
+// RoutingSink delivers each result through the adapter its job came from
+// (`Job.source`). Jobs without a source, such as schedules written before
+// it was recorded, go to the only adapter when there is just one; a source
+// with no adapter is an error, so the result is dead-lettered rather than
+// sent to the wrong chat.
+#[derive(Default)]
+pub struct RoutingSink {
+    sinks: RwLock<HashMap<String, Arc<dyn ResultSink>>>,
+}
+
+impl RoutingSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self, source: &str, sink: Arc<dyn ResultSink>) {
+        if let Ok(mut g) = self.sinks.write() {
+            g.insert(source.to_string(), sink);
+        }
+    }
+
+    fn route(&self, job: &Job) -> Result<Arc<dyn ResultSink>, Error> {
+        let g = self
+            .sinks
+            .read()
+            .map_err(|_| Error::internal("sink table unavailable"))?;
+        if let Some(s) = g.get(&job.source) {
+            return Ok(Arc::clone(s));
+        }
+        match g.values().next() {
+            Some(s) if g.len() == 1 && job.source.is_empty() => Ok(Arc::clone(s)),
+            _ => Err(Error::config(format!(
+                "no adapter running for source {:?}",
+                job.source
+            ))),
+        }
+    }
+}
+
+impl ResultSink for RoutingSink {
+    fn deliver(&self, job: &Job, resp: &Response) -> Result<(), Error> {
+        self.route(job)?.deliver(job, resp)
+    }
+
+    fn deliver_chunk(&self, job: &Job, chunk: &str) -> Result<(), Error> {
+        self.route(job)?.deliver_chunk(job, chunk)
+    }
+
+    fn deliver_progress(&self, job: &Job, note: &str) -> Result<(), Error> {
+        self.route(job)?.deliver_progress(job, note)
+    }
+
+    fn forget(&self, job: &Job) {
+        if let Ok(s) = self.route(job) {
+            s.forget(job);
+        }
+    }
 }
 
 pub struct Core {
     router: Arc<dyn Router>,
     reg: Arc<Registry>,
+    jobs: Arc<JobTable>,
     pool: RwLock<Pool>,
     sink: RwLock<Option<Arc<dyn ResultSink>>>,
     dispatch_join: RwLock<Option<JoinHandle<()>>>,
+    // Set while results are being dispatched, for `!undelivered resend`.
+    dispatcher: RwLock<Option<Arc<Dispatcher>>>,
+    scheduler: Arc<Scheduler>,
+    dead: Arc<DeadLetters>,
+    schedule_join: RwLock<Option<JoinHandle<()>>>,
+    closing: AtomicBool,
+    middleware: RwLock<Vec<Arc<dyn Middleware>>>,
 }
 
 impl Core {
@@ -31,78 +133,622 @@ impl Core {
         mut pool: Pool,
         results_rx: mpsc::Receiver<ResultItem>,
         sink: Option<Arc<dyn ResultSink>>,
+        scheduler: Arc<Scheduler>,
+        dead: Arc<DeadLetters>,
     ) -> Arc<Self> {
         pool.start();
         let c = Arc::new(Self {
             router,
             reg,
+            jobs: pool.jobs(),
             pool: RwLock::new(pool),
             sink: RwLock::new(sink),
             dispatch_join: RwLock::new(None),
+            dispatcher: RwLock::new(None),
+            scheduler,
+            dead,
+            schedule_join: RwLock::new(None),
+            closing: AtomicBool::new(false),
+            middleware: RwLock::new(Vec::new()),
         });
 
         let c2 = Arc::clone(&c);
         let j = thread::spawn(move || c2.dispatch_results(results_rx));
         *c.dispatch_join.write().unwrap() = Some(j);
+
+        let c2 = Arc::clone(&c);
+        let j = thread::spawn(move || c2.run_schedules());
+        *c.schedule_join.write().unwrap() = Some(j);
         c
     }
 
-    pub fn set_sink(&self, s: Option<Arc<dyn ResultSink>>) {
-        if let Ok(mut g) = self.sink.write() {
-            *g = s;
+    // Appends `m` to the middleware chain. Add middleware before the adapters
+    // start so every message sees the same chain.
+    pub fn add_middleware(&self, m: Arc<dyn Middleware>) {
+        if let Ok(mut g) = self.middleware.write() {
+            g.push(m);
         }
     }
 
-    pub fn shutdown(&self) {
+    // Runs `hook` on each middleware in turn until one answers the message;
+    // returns that middleware's name and answer.
+    fn intercept(
+        &self,
+        mut hook: impl FnMut(&dyn Middleware) -> Option<Response>,
+    ) -> Option<(&'static str, Response)> {
+        let chain = self.middleware.read().ok()?;
+        chain
+            .iter()
+            .find_map(|m| hook(m.as_ref()).map(|resp| (m.name(), resp)))
+    }
+
+    // False once shutdown has begun.
+    pub fn is_ready(&self) -> bool {
+        !self.closing.load(Ordering::Relaxed)
+    }
+
+    // Most recent jobs from `source` and `user_id` (any for None), newest
+    // first.
+    pub fn recent_jobs(
+        &self,
+        source: Option<&str>,
+        user_id: Option<&str>,
+        limit: usize,
+    ) -> Vec<JobEntry> {
+        self.jobs.list(source, user_id, limit)
+    }
+
+    // Any user's job by full id or unambiguous prefix.
+    pub fn find_job(&self, id: &str) -> Result<JobEntry, String> {
+        self.jobs.find(None, id, false)
+    }
+
+    // Stops the workers, waiting up to `timeout` for running jobs, then gives
+    // the dispatcher another `timeout` to deliver what finished. Abandoned
+    // jobs are logged.
+    pub fn shutdown(&self, timeout: Duration) {
+        self.closing.store(true, Ordering::Relaxed);
+        if let Ok(mut j) = self.schedule_join.write()
+            && let Some(h) = j.take()
+        {
+            let _ = h.join();
+        }
         if let Ok(mut p) = self.pool.write() {
-            p.shutdown();
+            for e in p.shutdown(timeout) {
+                log::warn(
+                    "shutdown abandoned running job",
+                    &[
+                        ("job_id", &e.id),
+                        ("task", &e.task_name),
+                        ("user", &e.user_id),
+                    ],
+                );
+            }
+            let queued = p.queued();
+            if queued > 0 {
+                log::warn("shutdown left queued jobs", &[("count", &queued)]);
+            }
         }
-        if let Ok(mut j) = self.dispatch_join.write() {
-            if let Some(h) = j.take() {
+        let deadline = Instant::now() + timeout;
+        if let Ok(mut j) = self.dispatch_join.write()
+            && let Some(h) = j.take()
+        {
+            while !h.is_finished() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(20));
+            }
+            if h.is_finished() {
                 let _ = h.join();
+            } else {
+                log::warn("shutdown gave up waiting for result delivery", &[]);
             }
         }
     }
 
-    fn dispatch_results(&self, results_rx: mpsc::Receiver<ResultItem>) {
+    // Hands results to the dispatcher until the workers are gone, then waits
+    // for the last deliveries.
+    fn dispatch_results(self: Arc<Self>, results_rx: mpsc::Receiver<ResultItem>) {
+        let core = Arc::clone(&self);
+        let dispatcher = Dispatcher::new(move |res| core.deliver_result(res));
+        if let Ok(mut d) = self.dispatcher.write() {
+            *d = Some(Arc::clone(&dispatcher));
+        }
         for res in results_rx {
-            let text = format_result(&res);
-            if text.is_empty() {
-                continue;
+            dispatcher.submit(res);
+        }
+        if let Ok(mut d) = self.dispatcher.write() {
+            d.take();
+        }
+        dispatcher.wait_idle();
+    }
+
+    fn deliver_result(&self, res: ResultItem) {
+        if let TaskOutput::Chunk(text) | TaskOutput::Progress(text) = &res.output {
+            let progress = matches!(res.output, TaskOutput::Progress(_));
+            log::debug(
+                if progress {
+                    "job progress"
+                } else {
+                    "job chunk"
+                },
+                &[
+                    ("job_id", &res.job.id),
+                    ("task", &res.job.task_name),
+                    ("worker", &res.worker),
+                    ("bytes", &text.len()),
+                ],
+            );
+            // Best effort: chunks are covered by the final result, and
+            // progress notes are only interim.
+            let sink = self.sink.read().ok().and_then(|g| g.as_ref().cloned());
+            let sent = match &sink {
+                Some(sink) if progress => sink.deliver_progress(&res.job, text),
+                Some(sink) => sink.deliver_chunk(&res.job, text),
+                None => Ok(()),
+            };
+            if let Err(e) = sent {
+                log::warn(
+                    if progress {
+                        "progress delivery failed"
+                    } else {
+                        "chunk delivery failed"
+                    },
+                    &[
+                        ("job_id", &res.job.id),
+                        ("err", &e),
+                        ("error_kind", &e.kind.as_str()),
+                        ("error_id", &e.id),
+                    ],
+                );
             }
-            let resp = Response {
-                text,
-                ephemeral: false,
+            return;
+        }
+        let mut resp = result_response(&res);
+        if let Ok(chain) = self.middleware.read() {
+            for m in chain.iter() {
+                m.after_result(&res.job, &mut resp);
+            }
+        }
+        if resp.text.is_empty() && resp.files.is_empty() {
+            self.ack(&res.job.id);
+            return;
+        }
+
+        let sink = self.sink.read().ok().and_then(|g| g.as_ref().cloned());
+        let Some(sink) = sink else {
+            self.ack(&res.job.id);
+            return;
+        };
+
+        let start = Instant::now();
+        match self.deliver_with_retry(&sink, &res.job, &resp) {
+            Ok(()) => {
+                let job = &res.job;
+                log::info(
+                    "job delivered",
+                    &[
+                        ("job_id", &job.id),
+                        ("task", &job.task_name),
+                        ("worker", &res.worker),
+                        ("user", &job.user_id),
+                        ("channel", &job.channel_id),
+                        ("source", &job.source),
+                        ("dur_ms", &res.dur.as_millis()),
+                        ("delivery_ms", &start.elapsed().as_millis()),
+                        (
+                            "total_ms",
+                            &job.created_at.elapsed().unwrap_or_default().as_millis(),
+                        ),
+                    ],
+                );
+                self.ack(&job.id);
+                // A re-sent dead letter is done with.
+                let _ = self.dead.remove(&job.id);
+            }
+            Err(e) => {
+                sink.forget(&res.job);
+                self.dead_letter(res.job, resp, e);
+            }
+        }
+    }
+
+    // Tries `DELIVERY_ATTEMPTS` times, doubling the wait between attempts.
+    // Errors that another attempt cannot fix are final, and so is any failure
+    // during shutdown, so the result is saved promptly.
+    fn deliver_with_retry(
+        &self,
+        sink: &Arc<dyn ResultSink>,
+        job: &Job,
+        resp: &Response,
+    ) -> Result<(), Error> {
+        let mut delay = DELIVERY_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            let mut err = match deliver_once(sink, job, resp) {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
             };
+            metrics::delivery_failed(&job.source);
+            let permanent = matches!(
+                err.kind,
+                Kind::UserInput | Kind::ConfigMissing | Kind::Rejected
+            );
+            if attempt >= DELIVERY_ATTEMPTS || permanent || self.closing.load(Ordering::Relaxed) {
+                err.msg = format!("{} (after {attempt} delivery attempts)", err.msg);
+                return Err(err);
+            }
+            log::warn(
+                "delivery failed",
+                &[
+                    ("job_id", &job.id),
+                    ("task", &job.task_name),
+                    ("user", &job.user_id),
+                    ("channel", &job.channel_id),
+                    ("source", &job.source),
+                    ("attempt", &attempt),
+                    ("retry_in", &fmt_dur(delay)),
+                    ("err", &err),
+                    ("error_kind", &err.kind.as_str()),
+                    ("error_id", &err.id),
+                ],
+            );
+            thread::sleep(delay);
+            delay *= 2;
+            attempt += 1;
+        }
+    }
 
-            let sink = self.sink.read().ok().and_then(|g| g.as_ref().cloned());
-            let Some(sink) = sink else { continue };
-
-            // v0: best-effort delivery, with a coarse timeout via a helper thread.
-            let job = res.job;
-            let resp2 = resp.clone();
-            let (tx, rx) = mpsc::channel();
-            thread::spawn(move || {
-                let r = sink.deliver(&job, &resp2);
-                let _ = tx.send(r);
+    // Keeps an undeliverable result for `!undelivered`. Once it is stored the
+    // job is acknowledged; if storing fails it stays in the journal instead.
+    fn dead_letter(&self, job: Job, resp: Response, err: Error) {
+        metrics::undeliverable(&job.source);
+        log::error(
+            "result undeliverable",
+            &[
+                ("job_id", &job.id),
+                ("task", &job.task_name),
+                ("user", &job.user_id),
+                ("channel", &job.channel_id),
+                ("source", &job.source),
+                ("err", &err),
+                ("error_kind", &err.kind.as_str()),
+                ("error_id", &err.id),
+            ],
+        );
+        let job_id = job.id.clone();
+        let d = DeadLetter {
+            job,
+            text: resp.text,
+            markdown: resp.markdown,
+            files: resp.files,
+            buttons: resp.buttons,
+            err: err.msg,
+            failed_at: SystemTime::now(),
+        };
+        match self.dead.add(d) {
+            Ok(()) => self.ack(&job_id),
+            Err(e) => log::error(
+                "failed to store dead letter",
+                &[("job_id", &job_id), ("err", &e)],
+            ),
+        }
+    }
+
+    // Submits due schedules once a second until shutdown.
+    fn run_schedules(&self) {
+        while !self.closing.load(Ordering::Relaxed) {
+            for s in self.scheduler.due(SystemTime::now()) {
+                let Some(task) = self.reg.lookup(&s.task_name) else {
+                    log::warn(
+                        "schedule skipped",
+                        &[
+                            ("id", &s.id),
+                            ("err", &format!("unknown task: {}", s.task_name)),
+                        ],
+                    );
+                    continue;
+                };
+                let job = Job {
+                    id: new_id(),
+                    task_name: s.task_name,
+                    input: text_input(&s.text),
+                    priority: task.priority(),
+                    user_id: s.user_id,
+                    channel_id: s.channel_id,
+                    source: s.source,
+                    created_at: SystemTime::now(),
+                    attempt: 1,
+                    not_before: None,
+                    // Nothing to reply to: the schedule was set up long ago.
+                    metadata: HashMap::new(),
+                };
+                match self.submit(&job) {
+                    Ok(()) => log::info(
+                        "schedule fired",
+                        &[("id", &s.id), ("task", &job.task_name), ("job_id", &job.id)],
+                    ),
+                    Err(e) => log::warn(
+                        "schedule skipped",
+                        &[("id", &s.id), ("err", &format!("{e:?}"))],
+                    ),
+                }
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    // Queues `job` and tracks it in the job table. "job queued" is logged
+    // first so it never trails a worker's "job started".
+    fn submit(&self, job: &Job) -> Result<(), QueueError> {
+        self.jobs.track(job);
+        let delay = job
+            .not_before
+            .and_then(|t| t.duration_since(SystemTime::now()).ok())
+            .map(fmt_dur)
+            .unwrap_or_default();
+        log::info(
+            "job queued",
+            &[
+                ("job_id", &job.id),
+                ("task", &job.task_name),
+                ("user", &job.user_id),
+                ("channel", &job.channel_id),
+                ("source", &job.source),
+                ("priority", &format!("{:?}", job.priority).to_lowercase()),
+                ("attempt", &job.attempt),
+                ("delay", &delay),
+            ],
+        );
+        let r = match self.pool.read() {
+            Ok(p) => p.submit(job.clone()),
+            Err(_) => Err(QueueError::Closed),
+        };
+        if let Err(e) = &r {
+            log::warn(
+                "job rejected",
+                &[("job_id", &job.id), ("err", &format!("{e:?}"))],
+            );
+            self.jobs.forget(&job.id);
+        }
+        r
+    }
+
+    fn ack(&self, job_id: &str) {
+        if let Ok(p) = self.pool.read()
+            && let Err(e) = p.ack(job_id)
+        {
+            log::warn("failed to ack job", &[("job_id", &job_id), ("err", &e)]);
+        }
+    }
+}
+
+impl Core {
+    // Built-in commands that act on the engine itself rather than running as
+    // queued tasks.
+    fn handle_builtin(
+        &self,
+        msg: &Message,
+        task_name: &str,
+        input: &TaskInput,
+    ) -> Option<Response> {
+        let arg = input.text().trim();
+        let text = match task_name {
+            "cancel" => self.cancel(msg, arg),
+            "jobs" => self.list_jobs(msg),
+            "schedule" => self.schedule(msg, arg),
+            "undelivered" => self.undelivered(msg, arg),
+            "status" => match self
+                .jobs
+                .find(Some((&msg.source, &msg.user_id)), arg, false)
+            {
+                Ok(e) => e.details(),
+                Err(e) => e,
+            },
+            _ => return None,
+        };
+        Some(Response {
+            text,
+            ephemeral: true,
+            ..Default::default()
+        })
+    }
+
+    fn cancel(&self, msg: &Message, id: &str) -> String {
+        let entry = match self.jobs.find(Some((&msg.source, &msg.user_id)), id, true) {
+            Ok(e) => e,
+            Err(e) => return e,
+        };
+        let removed = self
+            .pool
+            .read()
+            .map(|p| p.remove_queued(&entry.id))
+            .unwrap_or(false);
+        if removed {
+            return format!("canceled job {} ({})", short_id(&entry.id), entry.task_name);
+        }
+        entry.cancel.cancel();
+        format!(
+            "canceling job {} ({})...",
+            short_id(&entry.id),
+            entry.task_name
+        )
+    }
+
+    // `!schedule add <cron> <task> [text]`, `!schedule list`, `!schedule rm <id>`.
+    fn schedule(&self, msg: &Message, arg: &str) -> String {
+        let usage = "usage: !schedule add <cron|@daily> <task> [text] | list | rm <id>";
+        let (cmd, rest) = arg.split_once(char::is_whitespace).unwrap_or((arg, ""));
+        let rest = rest.trim();
+        match cmd {
+            "add" => {
+                let words: Vec<&str> = rest.split_whitespace().collect();
+                let n = if rest.starts_with('@') { 1 } else { 5 };
+                if words.len() <= n {
+                    return usage.to_string();
+                }
+                let spec = words[..n].join(" ");
+                let task_name = words[n].trim_start_matches('!');
+                let text = words[n + 1..].join(" ");
+                let Some(task) = self.reg.lookup(task_name) else {
+                    return format!("task not found: {task_name}");
+                };
+                if let Err(e) = task.validate(&text_input(&text)) {
+                    return e;
+                }
+                match self.scheduler.add(
+                    &spec,
+                    task_name,
+                    &text,
+                    &msg.channel,
+                    &msg.source,
+                    &msg.user_id,
+                ) {
+                    Ok(s) => {
+                        let mut reply = format!("added schedule {}", s.summary());
+                        if let Some(t) = s.next {
+                            let wait = t.duration_since(SystemTime::now()).unwrap_or_default();
+                            reply.push_str(&format!("\nnext run in {}", fmt_dur(wait)));
+                        }
+                        if !self.scheduler.is_persistent() {
+                            reply.push_str("\n(not saved: no schedule file configured)");
+                        }
+                        reply
+                    }
+                    Err(e) => e,
+                }
+            }
+            "list" | "" => {
+                let list = self.scheduler.list(&msg.source, &msg.channel);
+                if list.is_empty() {
+                    return "no schedules in this chat".to_string();
+                }
+                let mut lines = vec!["schedules in this chat:".to_string()];
+                lines.extend(list.iter().map(|s| s.summary()));
+                lines.join("\n")
+            }
+            // Users may remove the schedules they added; the local CLI
+            // operator may remove any.
+            "rm" if !rest.is_empty() => {
+                let owner = (msg.source != cli::SOURCE).then_some(msg.user_id.as_str());
+                match self
+                    .scheduler
+                    .remove(&msg.source, &msg.channel, rest, owner)
+                {
+                    Ok(s) => format!("removed schedule {}", s.summary()),
+                    Err(e) => e,
+                }
+            }
+            _ => usage.to_string(),
+        }
+    }
+
+    // `!undelivered [list]`, `!undelivered resend <id|all>`,
+    // `!undelivered drop <id|all>`. Users see results of their own jobs; the
+    // local CLI operator sees everything.
+    fn undelivered(&self, msg: &Message, arg: &str) -> String {
+        let usage = "usage: !undelivered [list] | resend <job-id|all> | drop <job-id|all>";
+        let (cmd, id) = arg.split_once(char::is_whitespace).unwrap_or((arg, ""));
+        let id = id.trim();
+        let owner =
+            (msg.source != cli::SOURCE).then_some((msg.source.as_str(), msg.user_id.as_str()));
+        let visible = self.dead.list(owner);
+
+        if matches!(cmd, "" | "list") {
+            if visible.is_empty() {
+                return "no undelivered results".to_string();
+            }
+            let mut lines = vec![format!("undelivered results ({}):", visible.len())];
+            lines.extend(visible.iter().map(|d| d.summary()));
+            return lines.join("\n");
+        }
+        if !matches!(cmd, "resend" | "drop") || id.is_empty() {
+            return usage.to_string();
+        }
+        let picked: Vec<DeadLetter> = visible
+            .into_iter()
+            .filter(|d| id == "all" || d.job.id.starts_with(id))
+            .collect();
+        if picked.is_empty() {
+            return format!("no undelivered result {id}");
+        }
+        if id != "all" && picked.len() > 1 {
+            return format!("job id {id} is ambiguous; use more characters");
+        }
+
+        if cmd == "drop" {
+            let dropped = picked
+                .iter()
+                .filter(|d| self.dead.remove(&d.job.id).is_ok())
+                .count();
+            return format!("dropped {dropped} undelivered result(s)");
+        }
+
+        if self
+            .sink
+            .read()
+            .ok()
+            .and_then(|g| g.as_ref().cloned())
+            .is_none()
+        {
+            return "no adapter to deliver through".to_string();
+        }
+        let Some(dispatcher) = self.dispatcher.read().ok().and_then(|g| g.clone()) else {
+            return "shutting down; resend after the restart".to_string();
+        };
+        // Delivered like fresh results, with the same retries; a result that
+        // fails again goes back to the dead letters.
+        let n = picked.len();
+        for d in picked {
+            dispatcher.submit(ResultItem {
+                job: d.job,
+                output: TaskOutput::Rich(Rich {
+                    text: d.text,
+                    markdown: d.markdown,
+                    files: d.files,
+                    buttons: d.buttons,
+                }),
+                err: None,
+                finished_at: SystemTime::now(),
+                dur: Duration::ZERO,
+                worker: 0,
             });
-            let _ = rx.recv_timeout(Duration::from_secs(10));
         }
+        format!("resending {n} undelivered result(s); see !undelivered for any that fail again")
+    }
+
+    fn list_jobs(&self, msg: &Message) -> String {
+        let jobs = self.jobs.list(Some(&msg.source), Some(&msg.user_id), 10);
+        if jobs.is_empty() {
+            return "you have no jobs".to_string();
+        }
+        let mut lines = vec!["your recent jobs:".to_string()];
+        lines.extend(jobs.iter().map(|e| e.summary()));
+        lines.join("\n")
     }
 }
 
 impl Engine for Core {
-    fn handle(&self, msg: Message) -> Response {
-        let route = match self.router.route(&msg) {
+    fn handle(&self, mut msg: Message) -> Response {
+        if let Some((name, resp)) = self.intercept(|m| m.before_route(&mut msg)) {
+            return stopped("before_route", name, &msg, resp);
+        }
+
+        let mut route = match self.router.route(&msg) {
             Ok(None) => return Response::default(),
             Ok(Some(r)) => r,
             Err(e) => {
                 return Response {
-                    text: e,
                     ephemeral: true,
+                    ..Response::error(e)
                 };
             }
         };
+        if let Some((name, resp)) = self.intercept(|m| m.after_route(&msg, &mut route)) {
+            return stopped("after_route", name, &msg, resp);
+        }
+
+        if let Some(resp) = self.handle_builtin(&msg, &route.task_name, &route.input) {
+            return resp;
+        }
 
         let task = match self.reg.lookup(&route.task_name) {
             Some(t) => t,
@@ -110,72 +756,180 @@ impl Engine for Core {
                 return Response {
                     text: format!("task not found: {}", route.task_name),
                     ephemeral: true,
+                    ..Default::default()
                 };
             }
         };
 
         if let Err(e) = task.validate(&route.input) {
             return Response {
-                text: e,
                 ephemeral: true,
+                ..Response::error(Error::user(e))
             };
         }
 
-        let job = Job {
+        let mut job = Job {
             id: new_id(),
             task_name: route.task_name,
             input: route.input,
-            user_id: msg.user_id,
-            channel_id: msg.channel,
+            priority: route.priority.unwrap_or_else(|| task.priority()),
+            user_id: msg.user_id.clone(),
+            channel_id: msg.channel.clone(),
+            source: msg.source.clone(),
             created_at: SystemTime::now(),
+            attempt: 1,
+            not_before: route.not_before,
+            metadata: msg.metadata.clone(),
         };
+        if let Some((name, resp)) = self.intercept(|m| m.before_submit(&mut job)) {
+            return stopped("before_submit", name, &msg, resp);
+        }
 
-        if let Ok(p) = self.pool.read() {
-            if let Err(e) = p.submit(job.clone()) {
-                return Response {
-                    text: e,
-                    ephemeral: true,
-                };
-            }
-        } else {
+        if let Err(e) = self.submit(&job) {
+            let text = match e {
+                QueueError::UserLimit(n) => format!(
+                    "you have {n} jobs pending; please wait for them to finish before sending more"
+                ),
+                e => format!("failed to queue job: {e:?}"),
+            };
             return Response {
-                text: "worker pool unavailable".to_string(),
+                text,
                 ephemeral: true,
+                ..Default::default()
             };
         }
 
         Response {
-            text: queue_status_text(&job.task_name),
+            text: queue_status_text(&job, short_id(&job.id)),
             ephemeral: true,
+            job_id: Some(job.id),
+            ..Default::default()
         }
     }
 }
 
-fn queue_status_text(task_name: &str) -> String {
-    match task_name {
+// Logs a message answered by middleware instead of the engine.
+fn stopped(stage: &str, middleware: &str, msg: &Message, resp: Response) -> Response {
+    log::info(
+        "message stopped",
+        &[
+            ("middleware", &middleware),
+            ("stage", &stage),
+            ("user", &msg.user_id),
+            ("channel", &msg.channel),
+            ("source", &msg.source),
+        ],
+    );
+    resp
+}
+
+fn queue_status_text(job: &Job, job_id: &str) -> String {
+    if let Some(at) = job.not_before {
+        let wait = at.duration_since(SystemTime::now()).unwrap_or_default();
+        return format!("scheduled in {} (job {job_id})", fmt_dur(wait));
+    }
+    match job.task_name.as_str() {
         // Ask-like tasks use adapter-level typing indicators where available.
-        "ask" => String::new(),
-        _ => "working...".to_string(),
+        "ask" => format!("(job {job_id})"),
+        _ => format!("working... (job {job_id})"),
     }
 }
 
-fn format_result(res: &ResultItem) -> String {
+// One delivery attempt, bounded by `DELIVERY_TIMEOUT` via a helper thread.
+fn deliver_once(sink: &Arc<dyn ResultSink>, job: &Job, resp: &Response) -> Result<(), Error> {
+    let (tx, rx) = mpsc::channel();
+    let (sink, job, resp) = (Arc::clone(sink), job.clone(), resp.clone());
+    thread::spawn(move || {
+        let _ = tx.send(sink.deliver(&job, &resp));
+    });
+    match rx.recv_timeout(DELIVERY_TIMEOUT) {
+        Ok(r) => r,
+        Err(_) => Err(Error::timeout(format!(
+            "delivery timed out after {}",
+            fmt_dur(DELIVERY_TIMEOUT)
+        ))),
+    }
+}
+
+// Input for a scheduled job, split like a typed command's arguments.
+fn text_input(text: &str) -> TaskInput {
+    if text.is_empty() {
+        TaskInput::Empty
+    } else {
+        TaskInput::Args(parse_args(text, Vec::new()))
+    }
+}
+
+fn result_response(res: &ResultItem) -> Response {
+    let text = |text: String| Response {
+        text,
+        ..Default::default()
+    };
     if let Some(e) = &res.err {
-        return format!("error: {}", e);
+        return Response::error(e.clone());
     }
     match &res.output {
-        TaskOutput::None => "ok".to_string(),
-        TaskOutput::Text(s) => s.to_string(),
+        TaskOutput::None => text("ok".to_string()),
+        TaskOutput::Text(s) | TaskOutput::Chunk(s) | TaskOutput::Progress(s) => text(s.to_string()),
+        TaskOutput::Rich(r) => Response {
+            text: r.text.clone(),
+            markdown: r.markdown,
+            files: r.files.clone(),
+            buttons: r.buttons.clone(),
+            ..Default::default()
+        },
     }
 }
 
 fn new_id() -> String {
-    // 16 bytes hex-ish, using time + address entropy. Not cryptographic; good enough for v0.
+    // 16 bytes hex: a mixed prefix (so short prefixes are distinct) + the timestamp.
+    // Not cryptographic; good enough for v0.
     // Avoids external crates (uuid/rand/hex) due to offline build constraints.
+    static SEQ: AtomicU64 = AtomicU64::new(0);
     let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap_or_default()
-        .as_nanos();
-    let addr = (&now as *const u128 as usize) as u128;
-    format!("{:032x}", now ^ addr)
+        .as_nanos() as u64;
+    let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+    let addr = &seq as *const u64 as u64;
+    format!(
+        "{:016x}{:016x}",
+        mix64(now ^ addr.rotate_left(32) ^ seq),
+        now
+    )
+}
+
+// splitmix64 finalizer.
+fn mix64(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Null;
+
+    impl ResultSink for Null {
+        fn deliver(&self, _job: &Job, _resp: &Response) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn only_jobs_without_a_source_fall_back() {
+        let r = RoutingSink::new();
+        r.add("telegram", Arc::new(Null));
+        let job = |source: &str| Job {
+            source: source.to_string(),
+            ..Job::test("1", "echo", "u")
+        };
+        assert!(r.route(&job("telegram")).is_ok());
+        assert!(r.route(&job("")).is_ok());
+        let err = r.route(&job("discord")).err().unwrap();
+        assert_eq!(err.kind, Kind::ConfigMissing);
+    }
 }
@@ -0,0 +1,146 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Error is returned by tasks, routers, the registry and result sinks. `msg`
+// is the full detail, for logs and operators; chat users are shown
+// `user_message`, which hides the detail of anything but bad input behind a
+// generic line carrying `id`, so a report can be matched to the log entry.
+#[derive(Clone, Debug)]
+pub struct Error {
+    pub kind: Kind,
+    pub msg: String,
+    pub id: String,
+    // How many times the job ran before giving up; set by the worker once
+    // retries are exhausted, 0 when the job was not retried.
+    pub attempts: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    // The user asked for something invalid; `msg` tells them what to fix.
+    UserInput,
+    // A token, key or command the task needs is not set up.
+    ConfigMissing,
+    // An API or program the task depends on failed or could not be reached.
+    Upstream,
+    // An API refused the request in a way retrying won't change, such as
+    // an HTTP 4xx other than 429.
+    Rejected,
+    Timeout,
+    // The user may not run this; `msg` says why.
+    Forbidden,
+    Internal,
+}
+
+impl Kind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::UserInput => "user_input",
+            Kind::ConfigMissing => "config_missing",
+            Kind::Upstream => "upstream_unavailable",
+            Kind::Rejected => "rejected",
+            Kind::Timeout => "timeout",
+            Kind::Forbidden => "forbidden",
+            Kind::Internal => "internal",
+        }
+    }
+}
+
+impl Error {
+    pub fn new(kind: Kind, msg: impl Into<String>) -> Self {
+        Self {
+            kind,
+            msg: msg.into(),
+            id: new_error_id(),
+            attempts: 0,
+        }
+    }
+
+    pub fn user(msg: impl Into<String>) -> Self {
+        Self::new(Kind::UserInput, msg)
+    }
+
+    pub fn config(msg: impl Into<String>) -> Self {
+        Self::new(Kind::ConfigMissing, msg)
+    }
+
+    pub fn upstream(msg: impl Into<String>) -> Self {
+        Self::new(Kind::Upstream, msg)
+    }
+
+    pub fn rejected(msg: impl Into<String>) -> Self {
+        Self::new(Kind::Rejected, msg)
+    }
+
+    pub fn timeout(msg: impl Into<String>) -> Self {
+        Self::new(Kind::Timeout, msg)
+    }
+
+    pub fn forbidden(msg: impl Into<String>) -> Self {
+        Self::new(Kind::Forbidden, msg)
+    }
+
+    pub fn internal(msg: impl Into<String>) -> Self {
+        Self::new(Kind::Internal, msg)
+    }
+
+    // What a chat user is told.
+    pub fn user_message(&self) -> String {
+        let reference = match self.attempts {
+            0 | 1 => format!("error {}", self.id),
+            n => format!("error {}, after {n} attempts", self.id),
+        };
+        match self.kind {
+            Kind::UserInput | Kind::Forbidden => self.msg.clone(),
+            Kind::Timeout => format!("{} ({reference})", self.msg),
+            Kind::ConfigMissing => {
+                format!("this is not set up yet; see !onboard or ask the operator ({reference})")
+            }
+            Kind::Upstream => {
+                format!("a service this needs is unavailable; please try again later ({reference})")
+            }
+            Kind::Rejected | Kind::Internal => format!("something went wrong ({reference})"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+// 8 hex digits: short enough to read out, distinct enough to grep for.
+fn new_error_id() -> String {
+    static SEQ: AtomicU64 = AtomicU64::new(0);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+    let mut x = now ^ seq.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    x ^= x >> 33;
+    format!("{:08x}", x as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_message_hides_detail_but_not_attempts() {
+        let mut e = Error::upstream("curl: (7) connection refused");
+        assert!(!e.user_message().contains("curl"));
+        assert!(e.user_message().ends_with(&format!("(error {})", e.id)));
+
+        e.attempts = 3;
+        assert!(
+            e.user_message()
+                .ends_with(&format!("(error {}, after 3 attempts)", e.id))
+        );
+        assert_eq!(Error::user("bad date").user_message(), "bad date");
+    }
+}
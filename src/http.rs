@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::log;
+
+// Requests larger than this are rejected; the endpoints only take small
+// JSON bodies.
+const MAX_BODY: usize = 1 << 20;
+const MAX_HEADER_LINES: usize = 100;
+// Longest request or header line, newline included.
+const MAX_LINE: u64 = 8 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Connections handled at once per server; more are answered 503 right away,
+// so slow or idle clients cannot pile up threads.
+const MAX_CONNECTIONS: usize = 32;
+const BUSY_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// A minimal HTTP/1.1 server for local endpoints (metrics, admin API): one
+// request per connection, each handled on its own thread, up to
+// `MAX_CONNECTIONS` at a time.
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    // Header names are lower-cased.
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+}
+
+#[derive(Debug)]
+pub struct Reply {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Reply {
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into(),
+        }
+    }
+
+    pub fn json(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: body.into(),
+        }
+    }
+}
+
+type Handler = dyn Fn(&Request) -> Reply + Send + Sync;
+
+// Binds `addr` and serves requests with `handler` on a background thread.
+// Binding errors are returned; the listener runs until the process exits.
+pub fn serve(
+    name: &'static str,
+    addr: &str,
+    handler: impl Fn(&Request) -> Reply + Send + Sync + 'static,
+) -> Result<(), String> {
+    let listener = TcpListener::bind(addr).map_err(|e| format!("{name}: listen {addr}: {e}"))?;
+    let handler: Arc<Handler> = Arc::new(handler);
+    let open = Arc::new(AtomicUsize::new(0));
+    thread::spawn(move || {
+        for conn in listener.incoming() {
+            let Ok(mut stream) = conn else { continue };
+            if open.fetch_add(1, Ordering::AcqRel) >= MAX_CONNECTIONS {
+                open.fetch_sub(1, Ordering::AcqRel);
+                let _ = stream.set_write_timeout(Some(BUSY_WRITE_TIMEOUT));
+                let _ = write_reply(&mut stream, &Reply::text(503, "too many connections\n"));
+                continue;
+            }
+            let (handler, slot) = (Arc::clone(&handler), Slot(Arc::clone(&open)));
+            thread::spawn(move || {
+                let _slot = slot;
+                if let Err(e) = handle_conn(stream, &*handler) {
+                    log::debug("http request failed", &[("server", &name), ("err", &e)]);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+// A taken connection slot, given back when the handler thread ends, even by
+// a panic.
+struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+fn handle_conn(mut stream: TcpStream, handler: &Handler) -> Result<(), String> {
+    stream
+        .set_read_timeout(Some(READ_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    let reply = match read_request(&stream) {
+        Ok(req) => handler(&req),
+        Err(reply) => reply,
+    };
+    write_reply(&mut stream, &reply)
+}
+
+fn write_reply(stream: &mut TcpStream, reply: &Reply) -> Result<(), String> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        reply.status,
+        reason(reply.status),
+        reply.content_type,
+        reply.body.len()
+    );
+    stream
+        .write_all(head.as_bytes())
+        .and_then(|_| stream.write_all(reply.body.as_bytes()))
+        .and_then(|_| stream.flush())
+        .map_err(|e| e.to_string())
+}
+
+fn read_request(stream: impl Read) -> Result<Request, Reply> {
+    let bad = |msg: &str| Reply::text(400, format!("{msg}\n"));
+    let mut r = BufReader::new(stream);
+
+    let line = read_line(&mut r).ok_or_else(|| bad("bad request line"))?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(bad("bad request line"));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut req = Request {
+        method: method.to_string(),
+        path: percent_decode(path),
+        query: parse_query(query),
+        headers: HashMap::new(),
+        body: Vec::new(),
+    };
+
+    let mut ended = false;
+    for _ in 0..MAX_HEADER_LINES {
+        let line = read_line(&mut r).ok_or_else(|| bad("bad header"))?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            ended = true;
+            break;
+        }
+        if let Some((k, v)) = line.split_once(':') {
+            req.headers
+                .insert(k.trim().to_ascii_lowercase(), v.trim().to_string());
+        }
+    }
+    if !ended {
+        return Err(Reply::text(431, "too many headers\n"));
+    }
+
+    let len = match req.header("content-length") {
+        Some(v) => v.parse::<usize>().map_err(|_| bad("bad content-length"))?,
+        None => 0,
+    };
+    if len > MAX_BODY {
+        return Err(Reply::text(413, "request body too large\n"));
+    }
+    req.body = vec![0; len];
+    r.read_exact(&mut req.body).map_err(|_| bad("short body"))?;
+    Ok(req)
+}
+
+// One line of at most `MAX_LINE` bytes; None if it is longer, cut short or
+// can't be read.
+fn read_line(r: &mut impl BufRead) -> Option<String> {
+    let mut line = String::new();
+    r.by_ref().take(MAX_LINE).read_line(&mut line).ok()?;
+    line.ends_with('\n').then_some(line)
+}
+
+// Parses `a=1&b=two` into a map, decoding keys and values.
+fn parse_query(q: &str) -> HashMap<String, String> {
+    q.split('&')
+        .filter(|kv| !kv.is_empty())
+        .map(|kv| {
+            let (k, v) = kv.split_once('=').unwrap_or((kv, ""));
+            (
+                percent_decode(&k.replace('+', " ")),
+                percent_decode(&v.replace('+', " ")),
+            )
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> String {
+    let b = s.as_bytes();
+    let mut out = Vec::with_capacity(b.len());
+    let mut i = 0;
+    while i < b.len() {
+        let hex = b
+            .get(i + 1..i + 3)
+            .and_then(|h| u8::from_str_radix(std::str::from_utf8(h).ok()?, 16).ok());
+        match (b[i], hex) {
+            (b'%', Some(v)) => {
+                out.push(v);
+                i += 3;
+            }
+            (c, _) => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn free_addr() -> String {
+        let l = TcpListener::bind("127.0.0.1:0").unwrap();
+        l.local_addr().unwrap().to_string()
+    }
+
+    // Sends raw `req` to `addr` and returns the status code of the reply.
+    fn status(addr: &str, req: &[u8]) -> u16 {
+        let mut s = TcpStream::connect(addr).unwrap();
+        let _ = s.write_all(req);
+        let mut out = String::new();
+        let _ = s.read_to_string(&mut out);
+        out.split_whitespace()
+            .nth(1)
+            .and_then(|c| c.parse().ok())
+            .unwrap_or(0)
+    }
+
+    fn parse(raw: &str) -> Result<Request, u16> {
+        read_request(raw.as_bytes()).map_err(|r| r.status)
+    }
+
+    #[test]
+    fn parses_requests() {
+        let req = parse("POST /jobs/a%20b?x=1&y=two+words HTTP/1.1\r\nX-Token: s\r\nContent-Length: 2\r\n\r\nhi")
+            .unwrap();
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.path, "/jobs/a b");
+        assert_eq!(req.query["y"], "two words");
+        assert_eq!(req.header("x-token"), Some("s"));
+        assert_eq!(req.body, b"hi");
+    }
+
+    #[test]
+    fn rejects_oversized_requests() {
+        let long = "a".repeat(MAX_LINE as usize);
+        assert_eq!(
+            parse(&format!("GET /{long} HTTP/1.1\r\n\r\n")).unwrap_err(),
+            400
+        );
+        assert_eq!(
+            parse(&format!("GET / HTTP/1.1\r\nX: {long}\r\n\r\n")).unwrap_err(),
+            400
+        );
+        let headers = "X: y\r\n".repeat(MAX_HEADER_LINES);
+        assert_eq!(
+            parse(&format!("GET / HTTP/1.1\r\n{headers}\r\n")).unwrap_err(),
+            431
+        );
+        assert_eq!(
+            parse("GET / HTTP/1.1\r\nContent-Length: 2000000\r\n\r\n").unwrap_err(),
+            413
+        );
+    }
+
+    #[test]
+    fn panicking_handlers_give_their_slot_back() {
+        let addr = free_addr();
+        serve("test", &addr, |req| {
+            if req.path == "/boom" {
+                panic!("handler failed");
+            }
+            Reply::text(200, "ok\n")
+        })
+        .unwrap();
+        for _ in 0..MAX_CONNECTIONS + 8 {
+            status(&addr, b"GET /boom HTTP/1.1\r\n\r\n");
+        }
+        // Slots are handed back just after the connection closes.
+        let ok = (0..100).any(|_| {
+            thread::sleep(Duration::from_millis(10));
+            status(&addr, b"GET /ok HTTP/1.1\r\n\r\n") == 200
+        });
+        assert!(ok);
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::error::Error;
+use crate::tasks::{CancelReason, CancelToken};
+use crate::types::Job;
+use crate::worker::ResultItem;
+
+// Length of the job id prefix shown to users and accepted by `!cancel`.
+pub const SHORT_ID_LEN: usize = 8;
+
+// Finished jobs kept for `!jobs` / `!status`; the oldest are dropped first.
+const HISTORY: usize = 500;
+
+pub fn short_id(id: &str) -> &str {
+    &id[..id.len().min(SHORT_ID_LEN)]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Canceled,
+}
+
+impl JobState {
+    pub fn is_active(self) -> bool {
+        matches!(self, JobState::Queued | JobState::Running)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Succeeded => "succeeded",
+            JobState::Failed => "failed",
+            JobState::Canceled => "canceled",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct JobEntry {
+    pub id: String,
+    pub task_name: String,
+    pub source: String,
+    pub user_id: String,
+    pub cancel: CancelToken,
+    pub state: JobState,
+    pub worker_id: Option<usize>,
+    pub attempt: u32,
+    pub created_at: SystemTime,
+    pub not_before: Option<SystemTime>,
+    pub started_at: Option<SystemTime>,
+    pub finished_at: Option<SystemTime>,
+    pub dur: Option<Duration>,
+    pub err: Option<Error>,
+    seq: u64,
+}
+
+impl JobEntry {
+    // One-line summary for `!jobs`.
+    pub fn summary(&self) -> String {
+        let id = short_id(&self.id);
+        let state = self.state.as_str();
+        match self.state {
+            JobState::Queued if self.due_in().is_some() => format!(
+                "{id} {} scheduled in {}",
+                self.task_name,
+                fmt_dur(self.due_in().unwrap_or_default())
+            ),
+            JobState::Queued => format!(
+                "{id} {} {state} {} ago",
+                self.task_name,
+                fmt_dur(since(self.created_at))
+            ),
+            JobState::Running => format!(
+                "{id} {} {state} {} (worker {})",
+                self.task_name,
+                fmt_dur(since(self.started_at.unwrap_or(self.created_at))),
+                self.worker_id.unwrap_or(0)
+            ),
+            _ => format!(
+                "{id} {} {state} in {}",
+                self.task_name,
+                fmt_dur(self.dur.unwrap_or_default())
+            ),
+        }
+    }
+
+    // Multi-line report for `!status`.
+    pub fn details(&self) -> String {
+        let mut lines = vec![
+            format!("job {} ({})", short_id(&self.id), self.task_name),
+            format!("state: {}", self.state.as_str()),
+            format!("queued: {} ago", fmt_dur(since(self.created_at))),
+        ];
+        if let Some(d) = self.due_in() {
+            lines.push(format!("due in: {}", fmt_dur(d)));
+        }
+        if let Some(w) = self.worker_id {
+            lines.push(format!("worker: {w}"));
+        }
+        if self.attempt > 1 {
+            lines.push(format!("attempt: {}", self.attempt));
+        }
+        if let Some(t) = self.started_at {
+            lines.push(format!("started: {} ago", fmt_dur(since(t))));
+        }
+        if let Some(t) = self.finished_at {
+            lines.push(format!("finished: {} ago", fmt_dur(since(t))));
+        }
+        if let Some(d) = self.dur {
+            lines.push(format!("duration: {}", fmt_dur(d)));
+        }
+        if let Some(e) = &self.err {
+            lines.push(format!("error: {}", e.user_message()));
+        }
+        lines.join("\n")
+    }
+
+    // Time left before a delayed job may run; None once it is due.
+    fn due_in(&self) -> Option<Duration> {
+        if self.state != JobState::Queued {
+            return None;
+        }
+        self.not_before?
+            .duration_since(SystemTime::now())
+            .ok()
+            .filter(|d| !d.is_zero())
+    }
+}
+
+// JobTable records every job's lifecycle (queued -> running -> finished) so
+// users can refer to and inspect jobs after submission.
+#[derive(Default)]
+pub struct JobTable {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    jobs: HashMap<String, JobEntry>,
+    seq: u64,
+}
+
+impl Inner {
+    fn entry(&mut self, job: &Job) -> &mut JobEntry {
+        self.seq += 1;
+        let seq = self.seq;
+        self.jobs.entry(job.id.clone()).or_insert_with(|| JobEntry {
+            id: job.id.clone(),
+            task_name: job.task_name.clone(),
+            source: job.source.clone(),
+            user_id: job.user_id.clone(),
+            cancel: CancelToken::default(),
+            state: JobState::Queued,
+            worker_id: None,
+            attempt: job.attempt,
+            created_at: job.created_at,
+            not_before: job.not_before,
+            started_at: None,
+            finished_at: None,
+            dur: None,
+            err: None,
+            seq,
+        })
+    }
+
+    fn prune(&mut self) {
+        let mut done: Vec<(u64, String)> = self
+            .jobs
+            .values()
+            .filter(|e| !e.state.is_active())
+            .map(|e| (e.seq, e.id.clone()))
+            .collect();
+        if done.len() <= HISTORY {
+            return;
+        }
+        done.sort();
+        for (_, id) in &done[..done.len() - HISTORY] {
+            self.jobs.remove(id);
+        }
+    }
+}
+
+impl JobTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Registers a newly queued job.
+    pub fn track(&self, job: &Job) {
+        if let Ok(mut g) = self.inner.lock() {
+            g.entry(job);
+        }
+    }
+
+    // Drops a job that never made it into the queue.
+    pub fn forget(&self, job_id: &str) {
+        if let Ok(mut g) = self.inner.lock() {
+            g.jobs.remove(job_id);
+        }
+    }
+
+    // Marks `job` as running on `worker_id` and returns its cancel token.
+    // Jobs replayed from the journal are registered here on first sight.
+    pub fn start(&self, job: &Job, worker_id: usize) -> CancelToken {
+        let Ok(mut g) = self.inner.lock() else {
+            return CancelToken::default();
+        };
+        let e = g.entry(job);
+        e.state = JobState::Running;
+        e.worker_id = Some(worker_id);
+        e.attempt = job.attempt;
+        e.started_at = Some(SystemTime::now());
+        e.cancel.clone()
+    }
+
+    pub fn finish(&self, res: &ResultItem) {
+        let Ok(mut g) = self.inner.lock() else { return };
+        let e = g.entry(&res.job);
+        e.state = if matches!(
+            e.cancel.reason(),
+            Some(CancelReason::User | CancelReason::Shutdown)
+        ) {
+            JobState::Canceled
+        } else if res.err.is_some() {
+            JobState::Failed
+        } else {
+            JobState::Succeeded
+        };
+        e.finished_at = Some(res.finished_at);
+        e.dur = Some(res.dur);
+        e.err = res.err.clone();
+        g.prune();
+    }
+
+    // Marks a failed job as waiting for another attempt at `not_before`.
+    pub fn retry(&self, job_id: &str, err: &Error, not_before: SystemTime) {
+        if let Ok(mut g) = self.inner.lock()
+            && let Some(e) = g.jobs.get_mut(job_id)
+        {
+            e.state = JobState::Queued;
+            e.not_before = Some(not_before);
+            e.worker_id = None;
+            e.err = Some(err.clone());
+        }
+    }
+
+    // Marks a job that was removed from the queue before it ran.
+    pub fn cancel_queued(&self, job_id: &str) {
+        if let Ok(mut g) = self.inner.lock()
+            && let Some(e) = g.jobs.get_mut(job_id)
+        {
+            e.cancel.cancel();
+            e.state = JobState::Canceled;
+            e.finished_at = Some(SystemTime::now());
+            g.prune();
+        }
+    }
+
+    // Finds one of `owner`'s jobs (anyone's for None) by full id or
+    // unambiguous prefix; "last" selects the most recently submitted job.
+    // `owner` is (source, user id), as user ids are only unique per adapter.
+    pub fn find(
+        &self,
+        owner: Option<(&str, &str)>,
+        id: &str,
+        active_only: bool,
+    ) -> Result<JobEntry, String> {
+        let g = self
+            .inner
+            .lock()
+            .map_err(|_| "job table unavailable".to_string())?;
+        let own = g
+            .jobs
+            .values()
+            .filter(|e| owner.is_none_or(|(s, u)| e.source == s && e.user_id == u))
+            .filter(|e| !active_only || e.state.is_active());
+
+        if id == "last" {
+            return own.max_by_key(|e| e.seq).cloned().ok_or_else(|| {
+                if active_only {
+                    "you have no queued or running jobs".to_string()
+                } else {
+                    "you have no jobs".to_string()
+                }
+            });
+        }
+
+        let matches: Vec<&JobEntry> = own.filter(|e| e.id.starts_with(id)).collect();
+        match matches.as_slice() {
+            [] if active_only => Err(format!("no queued or running job {id}")),
+            [] => Err(format!("no job {id}")),
+            [e] => Ok((*e).clone()),
+            _ => Err(format!("job id {id} is ambiguous; use more characters")),
+        }
+    }
+
+    pub fn running(&self) -> Vec<JobEntry> {
+        let Ok(g) = self.inner.lock() else {
+            return Vec::new();
+        };
+        g.jobs
+            .values()
+            .filter(|e| e.state == JobState::Running)
+            .cloned()
+            .collect()
+    }
+
+    // The most recent jobs from `source` and `user_id` (any for None),
+    // newest first.
+    pub fn list(&self, source: Option<&str>, user_id: Option<&str>, limit: usize) -> Vec<JobEntry> {
+        let Ok(g) = self.inner.lock() else {
+            return Vec::new();
+        };
+        let mut own: Vec<JobEntry> = g
+            .jobs
+            .values()
+            .filter(|e| source.is_none_or(|s| e.source == s))
+            .filter(|e| user_id.is_none_or(|u| e.user_id == u))
+            .cloned()
+            .collect();
+        own.sort_by_key(|e| std::cmp::Reverse(e.seq));
+        own.truncate(limit);
+        own
+    }
+}
+
+fn since(t: SystemTime) -> Duration {
+    t.elapsed().unwrap_or_default()
+}
+
+pub fn fmt_dur(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs == 0 {
+        format!("{}ms", d.as_millis())
+    } else if secs < 60 {
+        format!("{:.1}s", d.as_secs_f64())
+    } else if secs < 3600 {
+        format!("{}m{}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn users_on_different_adapters_do_not_share_jobs() {
+        let t = JobTable::new();
+        for (id, source) in [("a1", "telegram"), ("b1", "discord")] {
+            t.track(&Job {
+                source: source.to_string(),
+                ..Job::test(id, "echo", "42")
+            });
+        }
+        assert_eq!(
+            t.find(Some(("telegram", "42")), "last", false).unwrap().id,
+            "a1"
+        );
+        assert!(t.find(Some(("telegram", "42")), "b1", false).is_err());
+        assert_eq!(t.find(None, "b1", false).unwrap().id, "b1");
+        let ids = |source| -> Vec<String> {
+            t.list(source, Some("42"), 10)
+                .into_iter()
+                .map(|e| e.id)
+                .collect()
+        };
+        assert_eq!(ids(Some("discord")), ["b1"]);
+        assert_eq!(ids(None), ["b1", "a1"]);
+    }
+}
@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::router::parse_args;
+use crate::types::{Attachment, Job, Priority, TaskInput};
+
+const LOG_FILE: &str = "queue.log";
+
+// The log is compacted again once this many records are obsolete (acked, or
+// replaced by a later record for the same job).
+const COMPACT_AFTER: usize = 1000;
+
+// Journal is an append-only write-ahead log of queued jobs.
+//
+// Each line is one record:
+// - `E\t<key=value>...`: job enqueued (a later record for the same id replaces it)
+// - `A\t<id>`: job acknowledged (result delivered), no longer replayed
+//
+// On open the log is replayed and compacted down to the unacknowledged jobs,
+// and while running it is compacted the same way every `COMPACT_AFTER`
+// obsolete records.
+pub struct Journal {
+    dir: PathBuf,
+    path: PathBuf,
+    log: Mutex<Log>,
+}
+
+struct Log {
+    file: File,
+    // Latest record of each unacknowledged job, with its first sequence
+    // number so compaction keeps the original order.
+    live: HashMap<String, (u64, String)>,
+    seq: u64,
+    obsolete: usize,
+}
+
+impl Journal {
+    // Opens (or creates) the journal in `dir` and returns the jobs that were
+    // enqueued but never acknowledged, in their original order.
+    pub fn open(dir: &Path) -> Result<(Self, Vec<Job>), String> {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("journal: create dir {}: {e}", dir.display()))?;
+        let path = dir.join(LOG_FILE);
+
+        let pending = if path.exists() {
+            replay(&path)?
+        } else {
+            Vec::new()
+        };
+
+        let live: HashMap<String, (u64, String)> = pending
+            .iter()
+            .enumerate()
+            .map(|(i, job)| {
+                (
+                    job.id.clone(),
+                    (i as u64, format!("E\t{}", encode_job(job))),
+                )
+            })
+            .collect();
+        let file = rewrite(dir, &path, &live)?;
+
+        Ok((
+            Self {
+                dir: dir.to_path_buf(),
+                path,
+                log: Mutex::new(Log {
+                    file,
+                    seq: live.len() as u64,
+                    live,
+                    obsolete: 0,
+                }),
+            },
+            pending,
+        ))
+    }
+
+    pub fn append(&self, job: &Job) -> Result<(), String> {
+        let line = format!("E\t{}", encode_job(job));
+        self.write_record(&line, |log| {
+            let seq = log.seq;
+            log.seq += 1;
+            match log.live.get_mut(&job.id) {
+                Some(rec) => {
+                    rec.1 = line.clone();
+                    log.obsolete += 1;
+                }
+                None => {
+                    log.live.insert(job.id.clone(), (seq, line.clone()));
+                }
+            }
+        })
+    }
+
+    pub fn ack(&self, job_id: &str) -> Result<(), String> {
+        self.write_record(&format!("A\t{}", escape(job_id)), |log| {
+            // The job's record and the ack itself.
+            if log.live.remove(job_id).is_some() {
+                log.obsolete += 2;
+            }
+        })
+    }
+
+    // Appends `line`, then lets `update` account for it and compacts if
+    // enough of the file is obsolete.
+    fn write_record(&self, line: &str, update: impl FnOnce(&mut Log)) -> Result<(), String> {
+        let mut log = self
+            .log
+            .lock()
+            .map_err(|_| "journal: poisoned lock".to_string())?;
+        writeln!(log.file, "{line}")
+            .map_err(|e| format!("journal: write {}: {e}", self.path.display()))?;
+        log.file
+            .sync_data()
+            .map_err(|e| format!("journal: sync {}: {e}", self.path.display()))?;
+        update(&mut log);
+        if log.obsolete >= COMPACT_AFTER {
+            log.file = rewrite(&self.dir, &self.path, &log.live)?;
+            log.obsolete = 0;
+        }
+        Ok(())
+    }
+}
+
+// Rewrites the log as just the `live` records, swaps it in and returns it
+// opened for appending.
+fn rewrite(dir: &Path, path: &Path, live: &HashMap<String, (u64, String)>) -> Result<File, String> {
+    let mut records: Vec<&(u64, String)> = live.values().collect();
+    records.sort_by_key(|r| r.0);
+    let tmp = dir.join(format!("{LOG_FILE}.tmp"));
+    {
+        let mut f =
+            File::create(&tmp).map_err(|e| format!("journal: create {}: {e}", tmp.display()))?;
+        for (_, line) in records {
+            writeln!(f, "{line}").map_err(|e| format!("journal: write {}: {e}", tmp.display()))?;
+        }
+        f.sync_all()
+            .map_err(|e| format!("journal: sync {}: {e}", tmp.display()))?;
+    }
+    fs::rename(&tmp, path).map_err(|e| format!("journal: rename {}: {e}", path.display()))?;
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("journal: open {}: {e}", path.display()))
+}
+
+fn replay(path: &Path) -> Result<Vec<Job>, String> {
+    let f = File::open(path).map_err(|e| format!("journal: open {}: {e}", path.display()))?;
+
+    let mut order: Vec<String> = Vec::new();
+    let mut jobs: HashMap<String, Job> = HashMap::new();
+    for line in BufReader::new(f).lines() {
+        let line = line.map_err(|e| format!("journal: read {}: {e}", path.display()))?;
+        if let Some(rest) = line.strip_prefix("E\t") {
+            // A torn final line from a crash mid-write is skipped rather than fatal.
+            let Some(job) = decode_job(rest) else {
+                continue;
+            };
+            if !jobs.contains_key(&job.id) {
+                order.push(job.id.clone());
+            }
+            jobs.insert(job.id.clone(), job);
+        } else if let Some(rest) = line.strip_prefix("A\t") {
+            jobs.remove(&unescape(rest));
+        }
+    }
+
+    Ok(order
+        .into_iter()
+        .filter_map(|id| jobs.remove(&id))
+        .collect())
+}
+
+pub fn encode_job(job: &Job) -> String {
+    let mut fields = vec![
+        ("id", job.id.clone()),
+        ("task", job.task_name.clone()),
+        ("prio", job.priority.as_str().to_string()),
+        ("user", job.user_id.clone()),
+        ("channel", job.channel_id.clone()),
+        ("source", job.source.clone()),
+        ("created", unix_millis(job.created_at).to_string()),
+        ("attempt", job.attempt.to_string()),
+    ];
+    if let Some(t) = job.not_before {
+        fields.push(("not_before", unix_millis(t).to_string()));
+    }
+    // Arguments are stored as typed and split again on replay; attachment i
+    // becomes `att<i>_name`, `att<i>_mime` and `att<i>_url`.
+    let mut att_keys: Vec<(String, String)> = Vec::new();
+    match &job.input {
+        TaskInput::Empty => {}
+        TaskInput::Text(t) => fields.push(("text", t.clone())),
+        TaskInput::Args(a) => {
+            fields.push(("args", a.raw.clone()));
+            for (i, f) in a.attachments.iter().enumerate() {
+                att_keys.push((format!("att{i}_name"), f.name.clone()));
+                att_keys.push((format!("att{i}_mime"), f.mime.clone()));
+                att_keys.push((format!("att{i}_url"), f.url.clone()));
+            }
+        }
+    }
+    // Metadata key k becomes `meta_<k>`.
+    let mut meta: Vec<(String, String)> = job
+        .metadata
+        .iter()
+        .map(|(k, v)| (format!("meta_{k}"), v.clone()))
+        .collect();
+    meta.sort();
+    fields.extend(att_keys.iter().map(|(k, v)| (k.as_str(), v.clone())));
+    fields.extend(meta.iter().map(|(k, v)| (k.as_str(), v.clone())));
+    encode_fields(&fields)
+}
+
+pub fn decode_job(s: &str) -> Option<Job> {
+    let f = decode_fields(s);
+    let id = f.get("id")?.clone();
+    let task_name = f.get("task")?.clone();
+    if id.is_empty() || task_name.is_empty() {
+        return None;
+    }
+    Some(Job {
+        id,
+        task_name,
+        input: match (f.get("args"), f.get("text")) {
+            (Some(raw), _) => TaskInput::Args(parse_args(raw, decode_attachments(&f))),
+            (None, Some(t)) => TaskInput::Text(t.clone()),
+            (None, None) => TaskInput::Empty,
+        },
+        priority: f
+            .get("prio")
+            .and_then(|v| Priority::parse(v))
+            .unwrap_or_default(),
+        user_id: f.get("user").cloned().unwrap_or_default(),
+        channel_id: f.get("channel").cloned().unwrap_or_default(),
+        source: f.get("source").cloned().unwrap_or_default(),
+        created_at: f
+            .get("created")
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(from_unix_millis)
+            .unwrap_or_else(SystemTime::now),
+        attempt: f
+            .get("attempt")
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(1),
+        not_before: f
+            .get("not_before")
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(from_unix_millis),
+        metadata: f
+            .iter()
+            .filter_map(|(k, v)| Some((k.strip_prefix("meta_")?.to_string(), v.clone())))
+            .collect(),
+    })
+}
+
+// Fields are tab-separated `key=value` pairs; unknown keys are ignored on
+// decode so records stay readable across versions.
+pub fn encode_fields(fields: &[(&str, String)]) -> String {
+    fields
+        .iter()
+        .map(|(k, v)| format!("{k}={}", escape(v)))
+        .collect::<Vec<_>>()
+        .join("\t")
+}
+
+pub fn decode_fields(s: &str) -> HashMap<String, String> {
+    s.split('\t')
+        .filter_map(|kv| kv.split_once('='))
+        .map(|(k, v)| (k.to_string(), unescape(v)))
+        .collect()
+}
+
+fn decode_attachments(f: &HashMap<String, String>) -> Vec<Attachment> {
+    let mut out = Vec::new();
+    while let Some(url) = f.get(&format!("att{}_url", out.len())) {
+        let i = out.len();
+        out.push(Attachment {
+            name: f.get(&format!("att{i}_name")).cloned().unwrap_or_default(),
+            mime: f.get(&format!("att{i}_mime")).cloned().unwrap_or_default(),
+            url: url.clone(),
+        });
+    }
+    out
+}
+
+pub fn unix_millis(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+pub fn from_unix_millis(ms: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(ms)
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut it = s.chars();
+    while let Some(c) = it.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match it.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("crabplane-journal-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn replays_unacked_jobs_in_order() {
+        let dir = temp_dir("replay");
+        let att = Attachment {
+            name: "a.png".to_string(),
+            mime: "image/png".to_string(),
+            url: "telegram:abc".to_string(),
+        };
+        let rich = Job {
+            input: TaskInput::Args(parse_args("say \"hi\tthere\"\n--loud", vec![att])),
+            priority: Priority::High,
+            not_before: Some(from_unix_millis(1_800_000_000_000)),
+            metadata: HashMap::from([("message_id".to_string(), "42".to_string())]),
+            ..Job::test("j2", "echo", "u")
+        };
+        {
+            let (j, pending) = Journal::open(&dir).unwrap();
+            assert!(pending.is_empty());
+            j.append(&Job::test("j1", "ping", "u")).unwrap();
+            j.append(&rich).unwrap();
+            j.append(&Job::test("j3", "ping", "u")).unwrap();
+            j.ack("j1").unwrap();
+            // A retry replaces the job's record but keeps its place.
+            j.append(&Job {
+                attempt: 2,
+                ..Job::test("j2", "echo", "u")
+            })
+            .unwrap();
+            j.append(&rich).unwrap();
+        }
+        let (_, pending) = Journal::open(&dir).unwrap();
+        let ids: Vec<&str> = pending.iter().map(|j| j.id.as_str()).collect();
+        assert_eq!(ids, ["j2", "j3"]);
+        let j2 = &pending[0];
+        assert_eq!(j2.priority, Priority::High);
+        assert_eq!(j2.not_before, rich.not_before);
+        assert_eq!(j2.metadata, rich.metadata);
+        let TaskInput::Args(a) = &j2.input else {
+            panic!("expected args");
+        };
+        assert_eq!(a.raw, "say \"hi\tthere\"\n--loud");
+        assert_eq!(a.positional, ["say", "hi\tthere"]);
+        assert_eq!(a.attachments[0].url, "telegram:abc");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn skips_a_torn_last_line() {
+        let dir = temp_dir("torn");
+        fs::create_dir_all(&dir).unwrap();
+        let good = format!("E\t{}\n", encode_job(&Job::test("j1", "ping", "u")));
+        fs::write(dir.join(LOG_FILE), format!("{good}E\tid=j2\tta")).unwrap();
+        let (_, pending) = Journal::open(&dir).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, "j1");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn compacts_while_running() {
+        let dir = temp_dir("compact");
+        let (j, _) = Journal::open(&dir).unwrap();
+        j.append(&Job::test("keep", "ping", "u")).unwrap();
+        for i in 0..COMPACT_AFTER {
+            let id = format!("done{i}");
+            j.append(&Job::test(&id, "ping", "u")).unwrap();
+            j.ack(&id).unwrap();
+        }
+        let lines = fs::read_to_string(dir.join(LOG_FILE)).unwrap();
+        assert!(lines.lines().count() < COMPACT_AFTER);
+        drop(j);
+        let (_, pending) = Journal::open(&dir).unwrap();
+        let ids: Vec<&str> = pending.iter().map(|j| j.id.as_str()).collect();
+        assert_eq!(ids, ["keep"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
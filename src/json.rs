@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+
+// Just enough JSON for logs and the HTTP API: string escaping, and parsing
+// flat objects whose values are strings, numbers, booleans or null.
+
+// `s` as a quoted JSON string.
+pub fn quote(s: &str) -> String {
+    format!("\"{}\"", escape(s))
+}
+
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// Parses `{"key": value, ...}`. Non-string values are returned as their
+// literal text (`42`, `true`); null values are left out.
+pub fn parse_object(s: &str) -> Result<HashMap<String, String>, String> {
+    let mut p = Parser {
+        s: s.as_bytes(),
+        i: 0,
+    };
+    let mut out = HashMap::new();
+    p.expect(b'{')?;
+    if p.peek() == Some(b'}') {
+        p.i += 1;
+    } else {
+        loop {
+            let key = p.string()?;
+            p.expect(b':')?;
+            if let Some(v) = p.value()? {
+                out.insert(key, v);
+            }
+            match p.next() {
+                Some(b',') => continue,
+                Some(b'}') => break,
+                _ => return Err(p.error("expected ',' or '}'")),
+            }
+        }
+    }
+    if p.peek().is_some() {
+        return Err(p.error("trailing data"));
+    }
+    Ok(out)
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    i: usize,
+}
+
+impl Parser<'_> {
+    fn skip_ws(&mut self) {
+        while self.s.get(self.i).is_some_and(u8::is_ascii_whitespace) {
+            self.i += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_ws();
+        self.s.get(self.i).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.i += 1;
+        Some(c)
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        match self.next() {
+            Some(got) if got == c => Ok(()),
+            _ => Err(self.error(&format!("expected '{}'", c as char))),
+        }
+    }
+
+    fn error(&self, msg: &str) -> String {
+        format!("invalid JSON at byte {}: {msg}", self.i)
+    }
+
+    fn value(&mut self) -> Result<Option<String>, String> {
+        match self.peek() {
+            Some(b'"') => self.string().map(Some),
+            Some(b'{' | b'[') => Err(self.error("nested values are not supported")),
+            Some(_) => {
+                let start = self.i;
+                while self
+                    .s
+                    .get(self.i)
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, b'-' | b'+' | b'.'))
+                {
+                    self.i += 1;
+                }
+                let lit = String::from_utf8_lossy(&self.s[start..self.i]).to_string();
+                match lit.as_str() {
+                    "" => Err(self.error("expected a value")),
+                    "null" => Ok(None),
+                    "true" | "false" => Ok(Some(lit)),
+                    _ if lit.parse::<f64>().is_ok() => Ok(Some(lit)),
+                    _ => Err(self.error("expected a value")),
+                }
+            }
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let Some(&c) = self.s.get(self.i) else {
+                return Err(self.error("unterminated string"));
+            };
+            self.i += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let Some(&e) = self.s.get(self.i) else {
+                        return Err(self.error("unterminated string"));
+                    };
+                    self.i += 1;
+                    let ch = match e {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{0008}',
+                        b'f' => '\u{000c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return Err(self.error("bad escape")),
+                    };
+                    let mut buf = [0u8; 4];
+                    out.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+                }
+                c => out.push(c),
+            }
+        }
+        String::from_utf8(out).map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    // The `XXXX` after `\u`, combining surrogate pairs.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let hi = self.hex4()?;
+        if (0xD800..0xDC00).contains(&hi) && self.s.get(self.i..self.i + 2) == Some(b"\\u") {
+            self.i += 2;
+            let lo = self.hex4()?;
+            let c = 0x10000 + ((hi - 0xD800) << 10) + (lo.wrapping_sub(0xDC00) & 0x3FF);
+            return Ok(char::from_u32(c).unwrap_or('\u{FFFD}'));
+        }
+        Ok(char::from_u32(hi).unwrap_or('\u{FFFD}'))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let h = self
+            .s
+            .get(self.i..self.i + 4)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u32::from_str_radix(h, 16).ok())
+            .ok_or_else(|| self.error("bad \\u escape"))?;
+        self.i += 4;
+        Ok(h)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_strings() {
+        assert_eq!(quote("a\"b\\c\nd\u{1}"), r#""a\"b\\c\nd\u0001""#);
+    }
+
+    #[test]
+    fn parses_flat_objects() {
+        let o = parse_object(
+            r#" { "text": "hi \"there\"\n", "n": -1.5e3, "ok": true, "gone": null, "u": "é🦀" } "#,
+        )
+        .unwrap();
+        assert_eq!(o["text"], "hi \"there\"\n");
+        assert_eq!(o["n"], "-1.5e3");
+        assert_eq!(o["ok"], "true");
+        assert!(!o.contains_key("gone"));
+        assert_eq!(o["u"], "é🦀");
+        assert!(parse_object("{}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn round_trips_quoted_strings() {
+        let s = "tab\tquote\" slash\\ crab🦀 bell\u{7}";
+        let o = parse_object(&format!("{{\"k\": {}}}", quote(s))).unwrap();
+        assert_eq!(o["k"], s);
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for s in [
+            "",
+            "[]",
+            r#"{"a": {"b": 1}}"#,
+            r#"{"a": 1,}"#,
+            r#"{"a": 1} x"#,
+            r#"{"a": "open}"#,
+            r#"{"a": nope}"#,
+            r#"{"a" 1}"#,
+        ] {
+            assert!(parse_object(s).is_err(), "{s}");
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt::Display;
+use std::io::Write;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::SystemTime;
+
+use crate::clock;
+use crate::json;
+
+// Log lines go to stderr, one event per line, either as
+//   INFO job started job_id=... task=ask worker=2
+// or, with `--log-format=json`, as
+//   {"ts":"2026-10-16T09:00:00.000Z","level":"info","msg":"job started","job_id":"...",...}
+// Fields with empty values are left out.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug = 0,
+    Info = 1,
+    Warn = 2,
+    Error = 3,
+}
+
+impl Level {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "debug" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warn" | "warning" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text = 0,
+    Json = 1,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" => Some(Format::Text),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static FORMAT: AtomicU8 = AtomicU8::new(Format::Text as u8);
+
+pub fn init(format: Format, level: Level) {
+    FORMAT.store(format as u8, Ordering::Relaxed);
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn debug(msg: &str, fields: &[(&str, &dyn Display)]) {
+    log(Level::Debug, msg, fields);
+}
+
+pub fn info(msg: &str, fields: &[(&str, &dyn Display)]) {
+    log(Level::Info, msg, fields);
+}
+
+pub fn warn(msg: &str, fields: &[(&str, &dyn Display)]) {
+    log(Level::Warn, msg, fields);
+}
+
+pub fn error(msg: &str, fields: &[(&str, &dyn Display)]) {
+    log(Level::Error, msg, fields);
+}
+
+pub fn log(level: Level, msg: &str, fields: &[(&str, &dyn Display)]) {
+    if (level as u8) < LEVEL.load(Ordering::Relaxed) {
+        return;
+    }
+    let fields: Vec<(&str, String)> = fields
+        .iter()
+        .map(|(k, v)| (*k, v.to_string()))
+        .filter(|(_, v)| !v.is_empty())
+        .collect();
+    let line = if FORMAT.load(Ordering::Relaxed) == Format::Json as u8 {
+        json_line(level, msg, &fields)
+    } else {
+        text_line(level, msg, &fields)
+    };
+    let _ = writeln!(std::io::stderr().lock(), "{line}");
+}
+
+fn text_line(level: Level, msg: &str, fields: &[(&str, String)]) -> String {
+    let mut line = format!("{} {msg}", level.as_str().to_ascii_uppercase());
+    for (k, v) in fields {
+        if v.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+            line.push_str(&format!(" {k}={v:?}"));
+        } else {
+            line.push_str(&format!(" {k}={v}"));
+        }
+    }
+    line
+}
+
+// Fields written as JSON numbers: counts and durations (`*_ms`). Everything
+// else is a string, even when it looks like a number (user "007").
+const NUMERIC_KEYS: [&str; 6] = ["attempt", "bytes", "count", "keys", "replayed", "worker"];
+
+fn is_numeric_key(k: &str) -> bool {
+    k.ends_with("_ms") || NUMERIC_KEYS.contains(&k)
+}
+
+fn json_line(level: Level, msg: &str, fields: &[(&str, String)]) -> String {
+    let mut line = format!(
+        "{{\"ts\":\"{}\",\"level\":\"{}\",\"msg\":\"{}\"",
+        clock::rfc3339(SystemTime::now()),
+        level.as_str(),
+        json::escape(msg)
+    );
+    for (k, v) in fields {
+        let number = Some(v)
+            .filter(|_| is_numeric_key(k))
+            .and_then(|v| v.parse::<u64>().ok());
+        match number {
+            Some(n) => line.push_str(&format!(",\"{}\":{n}", json::escape(k))),
+            None => line.push_str(&format!(",\"{}\":\"{}\"", json::escape(k), json::escape(v))),
+        }
+    }
+    line.push('}');
+    line
+}
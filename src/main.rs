@@ -1,38 +1,68 @@
+mod access;
 mod adapters;
+mod clock;
+mod deadletter;
+mod dispatch;
 mod engine;
+mod error;
+mod http;
+mod jobs;
+mod journal;
+mod json;
+mod log;
+mod markdown;
+mod metrics;
+mod middleware;
 mod queue;
 mod registry;
 mod router;
+mod scheduler;
+mod state;
 mod tasks;
 mod types;
 mod unix_signal;
 
+use std::collections::HashMap;
 use std::env;
 use std::io::IsTerminal;
+use std::path::Path;
 use std::sync::{Arc, atomic::AtomicBool};
+use std::thread::{self, JoinHandle};
 use std::time::Duration;
 
-use adapters::{cli, discord, telegram, whatsapp};
-use engine::{Core, Engine, ResultSink};
+use access::{Access, parse_grants};
+use adapters::{api, cli, discord, telegram, whatsapp};
+use clock::parse_duration;
+use deadletter::DeadLetters;
+use engine::{Core, Engine, ResultSink, RoutingSink};
+use middleware::{CommandMetrics, RateLimit};
 use queue::Queue;
 use registry::Registry;
 use router::PrefixRouter;
-use tasks::{EchoTask, OnboardingTask, OpenAiTask, PingTask, Task};
+use scheduler::Scheduler;
+use state::StateStore;
+use tasks::{Config, EchoTask, OnboardingTask, OpenAiTask, PingTask, RemindTask, Task};
+use types::Role;
 use unix_signal::install_unix_signal_handlers;
-use worker::Pool;
+use worker::{Pool, TaskEnv, TaskLimits};
 
 mod worker;
 
 struct LogSink;
 
 impl ResultSink for LogSink {
-    fn deliver(&self, job: &types::Job, resp: &types::Response) -> Result<(), String> {
+    fn deliver(&self, job: &types::Job, resp: &types::Response) -> Result<(), error::Error> {
         if resp.text.is_empty() {
             return Ok(());
         }
-        eprintln!(
-            "INFO job result job_id={} task={} text={}",
-            job.id, job.task_name, resp.text
+        log::info(
+            "job result",
+            &[
+                ("job_id", &job.id),
+                ("task", &job.task_name),
+                ("text", &resp.text),
+                ("error_id", &resp.error.as_ref().map(|e| e.id.as_str()).unwrap_or_default()),
+            ],
         );
         Ok(())
     }
@@ -40,94 +70,266 @@ impl ResultSink for LogSink {
 
 #[derive(Clone, Debug)]
 struct Args {
-    mode: String, // auto|cli|discord|telegram|whatsapp|daemon
+    mode: String, // auto|cli|discord|telegram|whatsapp|daemon, or a comma-separated list
     queue_size: usize,
+    queue_dir: Option<String>,
+    schedule_file: Option<String>,
+    max_pending_per_user: usize,
     shutdown_timeout: Duration,
+    log_format: log::Format,
+    log_level: log::Level,
+    metrics_addr: Option<String>,
+    api_addr: Option<String>,
 }
 
 fn main() {
     let args = parse_args();
+    log::init(args.log_format, args.log_level);
 
     let stop = Arc::new(AtomicBool::new(false));
     install_unix_signal_handlers(&stop);
 
     let conc = env_int("CRABPLANE_CONCURRENCY", 4).max(1) as usize;
 
+    let config = Config::from_env();
     let reg = Arc::new(Registry::new());
     must(reg.register(Arc::new(PingTask::new()) as Arc<dyn Task>));
     must(reg.register(Arc::new(EchoTask::new()) as Arc<dyn Task>));
-    must(reg.register(Arc::new(OpenAiTask::new()) as Arc<dyn Task>));
+    must(reg.register(Arc::new(OpenAiTask::new(&config)) as Arc<dyn Task>));
     must(reg.register(Arc::new(OnboardingTask::new()) as Arc<dyn Task>));
+    must(reg.register(Arc::new(RemindTask::new()) as Arc<dyn Task>));
 
-    let q = Arc::new(Queue::new(args.queue_size));
-    let (pool, results_rx) = Pool::new(Arc::clone(&reg), Arc::clone(&q), conc);
+    let mut q = match &args.queue_dir {
+        Some(dir) => {
+            let q = must(Queue::open(args.queue_size, Path::new(dir)));
+            log::info("durable queue", &[("dir", dir), ("replayed", &q.pending())]);
+            q
+        }
+        None => Queue::new(args.queue_size),
+    };
+    q.set_user_limit(args.max_pending_per_user);
+    let q = Arc::new(q);
+    let limits = TaskLimits {
+        max_concurrency: env_map("CRABPLANE_TASK_CONCURRENCY")
+            .into_iter()
+            .filter_map(|(k, v)| Some((k, v.parse::<usize>().ok()?)))
+            .collect(),
+        groups: env_map("CRABPLANE_TASK_GROUPS"),
+        group_workers: env_map("CRABPLANE_GROUP_WORKERS")
+            .into_iter()
+            .filter_map(|(k, v)| Some((k, v.parse::<usize>().ok()?)))
+            .collect(),
+        timeouts: env_map("CRABPLANE_TASK_TIMEOUTS")
+            .into_iter()
+            .filter_map(|(k, v)| Some((k, parse_duration(&v)?)))
+            .collect(),
+    };
+    // Task state lives next to the queue journal, or in memory without one.
+    let state = Arc::new(match &args.queue_dir {
+        Some(dir) => {
+            let s = must(StateStore::open(Path::new(dir)));
+            log::info("task state", &[("dir", dir), ("keys", &s.len())]);
+            s
+        }
+        None => StateStore::new(),
+    });
+    let env = TaskEnv { config, state };
+    let (pool, results_rx) = Pool::new(Arc::clone(&reg), Arc::clone(&q), conc, limits, env);
 
-    let router = Arc::new(PrefixRouter::new());
+    if let Some(addr) = &args.metrics_addr {
+        let q = Arc::clone(&q);
+        let jobs = pool.jobs();
+        let workers = pool.workers();
+        must(metrics::serve(addr, move || {
+            let (queue_ready, queue_delayed) = q.depth();
+            metrics::Gauges {
+                queue_ready,
+                queue_delayed,
+                workers,
+                workers_busy: jobs.running().len(),
+            }
+        }));
+        log::info("metrics listening", &[("addr", addr)]);
+    }
 
-    let selected = select_mode(&args.mode);
-    match selected.as_str() {
-        "cli" => {
-            let sink = Arc::new(cli::Sink::new());
-            let core = Core::new(router, reg, pool, results_rx, Some(sink));
-            let eng: Arc<dyn Engine> = core.clone();
-            let a = cli::Adapter::new(eng);
-            let _ = a.run(&stop);
-            graceful_shutdown(&stop, args.shutdown_timeout, &core);
+    // Schedules live next to the queue journal unless a file is given.
+    let schedule_file = args.schedule_file.clone().or_else(|| {
+        args.queue_dir
+            .as_ref()
+            .map(|d| Path::new(d).join("schedules").display().to_string())
+    });
+    let scheduler = Arc::new(match &schedule_file {
+        Some(f) => {
+            let s = must(Scheduler::open(Path::new(f)));
+            log::info("schedules", &[("file", f)]);
+            s
         }
-        "discord" => {
-            let token = env::var("DISCORD_TOKEN").unwrap_or_default();
-            // Create engine first, then attach the Discord adapter as a ResultSink.
-            let core = Core::new(router, reg, pool, results_rx, None);
-            let eng: Arc<dyn Engine> = core.clone();
-            let a = Arc::new(discord::Adapter::new(token, eng));
-            core.set_sink(Some(a.clone()));
-            let _ = discord::Adapter::run(&*a);
-            let _ = discord::Adapter::close(&*a);
-            graceful_shutdown(&stop, args.shutdown_timeout, &core);
+        None => Scheduler::new(),
+    });
+
+    let dead = Arc::new(match &args.queue_dir {
+        Some(dir) => {
+            let d = must(DeadLetters::open(Path::new(dir)));
+            if d.len() > 0 {
+                log::warn(
+                    "undelivered results waiting (see !undelivered)",
+                    &[("count", &d.len())],
+                );
+            }
+            d
         }
-        "telegram" => {
-            let token = env::var("TELEGRAM_BOT_TOKEN").unwrap_or_default();
-            // Create engine first, then attach the Telegram adapter as a ResultSink.
-            let core = Core::new(router, reg, pool, results_rx, None);
-            let eng: Arc<dyn Engine> = core.clone();
-            let a = Arc::new(telegram::Adapter::new(token, eng));
-            core.set_sink(Some(a.clone()));
-            let _ = telegram::Adapter::run(&*a, &stop);
-            let _ = telegram::Adapter::close(&*a);
-            graceful_shutdown(&stop, args.shutdown_timeout, &core);
+        None => DeadLetters::new(),
+    });
+
+    let access = must(access_from_env(Arc::clone(&reg)));
+    let router = Arc::new(PrefixRouter::new());
+
+    let modes = select_modes(&args.mode);
+    if let Some(bad) = modes.iter().find(|m| !MODES.contains(&m.as_str())) {
+        log::error("invalid mode", &[("mode", bad)]);
+        std::process::exit(2);
+    }
+
+    // Every adapter shares one engine; results go back to the adapter the
+    // request came from.
+    let sinks = Arc::new(RoutingSink::new());
+    let core = Core::new(router, reg, pool, results_rx, Some(sinks.clone()), scheduler, dead);
+    // Cross-cutting checks run around every message, in this order.
+    core.add_middleware(Arc::new(CommandMetrics));
+    match access {
+        Some(a) => core.add_middleware(Arc::new(a)),
+        None if modes.iter().any(|m| matches!(m.as_str(), "discord" | "telegram" | "whatsapp")) => {
+            log::warn(
+                "access control is off; anyone who can message the bot may run every command (see CRABPLANE_MEMBERS)",
+                &[],
+            );
         }
-        "whatsapp" => {
-            let account_sid = env::var("TWILIO_ACCOUNT_SID").unwrap_or_default();
-            let auth_token = env::var("TWILIO_AUTH_TOKEN").unwrap_or_default();
-            let from_number = env::var("TWILIO_WHATSAPP_NUMBER").unwrap_or_default();
-            // Create engine first, then attach the WhatsApp adapter as a ResultSink.
-            let core = Core::new(router, reg, pool, results_rx, None);
-            let eng: Arc<dyn Engine> = core.clone();
-            let a = Arc::new(whatsapp::Adapter::new(account_sid, auth_token, from_number, eng));
-            core.set_sink(Some(a.clone()));
-            let _ = whatsapp::Adapter::run(&*a, &stop);
-            let _ = whatsapp::Adapter::close(&*a);
-            graceful_shutdown(&stop, args.shutdown_timeout, &core);
+        None => {}
+    }
+    if let Ok(v) = env::var("CRABPLANE_RATE_LIMIT")
+        && !v.trim().is_empty()
+    {
+        match RateLimit::parse(&v) {
+            Some(r) => core.add_middleware(Arc::new(r)),
+            None => {
+                log::error("invalid CRABPLANE_RATE_LIMIT", &[("value", &v)]);
+                std::process::exit(2);
+            }
         }
-        "daemon" => {
-            let sink = Arc::new(LogSink);
-            let core = Core::new(router, reg, pool, results_rx, Some(sink));
-            while !stop.load(std::sync::atomic::Ordering::Relaxed) {
-                std::thread::sleep(Duration::from_millis(200));
+    }
+    let eng: Arc<dyn Engine> = core.clone();
+
+    let mut adapters: Vec<JoinHandle<()>> = Vec::new();
+    for mode in &modes {
+        match mode.as_str() {
+            "cli" => {
+                let downloads = env::var("CRABPLANE_DOWNLOAD_DIR")
+                    .unwrap_or_else(|_| "downloads".to_string());
+                sinks.add(cli::SOURCE, Arc::new(cli::Sink::new(downloads.into())));
+                let a = cli::Adapter::new(eng.clone());
+                let stop = Arc::clone(&stop);
+                let h = thread::spawn(move || {
+                    if let Err(e) = a.run(&stop) {
+                        log::error("adapter stopped", &[("adapter", &"cli"), ("err", &e)]);
+                    }
+                });
+                adapters.push(h);
+            }
+            "discord" => {
+                let token = env::var("DISCORD_TOKEN").unwrap_or_default();
+                let a = Arc::new(discord::Adapter::new(token, eng.clone()));
+                sinks.add(discord::SOURCE, a.clone());
+                let h = thread::spawn(move || {
+                    if let Err(e) = discord::Adapter::run(&a) {
+                        log::error("adapter stopped", &[("adapter", &"discord"), ("err", &e)]);
+                    }
+                    let _ = discord::Adapter::close(&a);
+                });
+                adapters.push(h);
             }
-            graceful_shutdown(&stop, args.shutdown_timeout, &core);
+            "telegram" => {
+                let token = env::var("TELEGRAM_BOT_TOKEN").unwrap_or_default();
+                let a = Arc::new(telegram::Adapter::new(token, eng.clone()));
+                sinks.add(telegram::SOURCE, a.clone());
+                let stop = Arc::clone(&stop);
+                let h = thread::spawn(move || {
+                    if let Err(e) = telegram::Adapter::run(&a, &stop) {
+                        log::error("adapter stopped", &[("adapter", &"telegram"), ("err", &e)]);
+                    }
+                    let _ = telegram::Adapter::close(&a);
+                });
+                adapters.push(h);
+            }
+            "whatsapp" => {
+                let account_sid = env::var("TWILIO_ACCOUNT_SID").unwrap_or_default();
+                let auth_token = env::var("TWILIO_AUTH_TOKEN").unwrap_or_default();
+                let from_number = env::var("TWILIO_WHATSAPP_NUMBER").unwrap_or_default();
+                let a = Arc::new(whatsapp::Adapter::new(
+                    account_sid,
+                    auth_token,
+                    from_number,
+                    eng.clone(),
+                ));
+                sinks.add(whatsapp::SOURCE, a.clone());
+                let stop = Arc::clone(&stop);
+                let h = thread::spawn(move || {
+                    if let Err(e) = whatsapp::Adapter::run(&a, &stop) {
+                        log::error("adapter stopped", &[("adapter", &"whatsapp"), ("err", &e)]);
+                    }
+                    let _ = whatsapp::Adapter::close(&a);
+                });
+                adapters.push(h);
+            }
+            // No adapter: results (e.g. from schedules) are only logged.
+            _ => sinks.add("daemon", Arc::new(LogSink)),
         }
-        _ => {
-            eprintln!("FATAL invalid mode mode={}", selected);
+    }
+
+    // The admin API runs next to the chat adapters; its token comes from the
+    // environment so it does not show up in `ps`.
+    if let Some(addr) = &args.api_addr {
+        let token = env::var("CRABPLANE_API_TOKEN").unwrap_or_default();
+        let a = Arc::new(api::Adapter::new(token, core.clone()));
+        sinks.add(api::SOURCE, a.clone());
+        if let Err(e) = api::Adapter::serve(&a, addr) {
+            log::error("api failed to start", &[("addr", addr), ("err", &e)]);
             std::process::exit(2);
         }
+        log::info("api listening", &[("addr", addr)]);
+    }
+    log::info("started", &[("adapters", &modes.join(","))]);
+
+    // Run until a signal arrives, or until every adapter has stopped (e.g. end
+    // of CLI input). `daemon` keeps the process up until a signal.
+    let keep_alive = modes.iter().any(|m| m == "daemon");
+    while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+        if !keep_alive && adapters.iter().all(|h| h.is_finished()) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(200));
     }
+    graceful_shutdown(&stop, args.shutdown_timeout, &core);
 }
 
-fn graceful_shutdown(_stop: &AtomicBool, _timeout: Duration, core: &Arc<Core>) {
-    // v0: tasks are simple and workers are cooperative; shutdown is best-effort.
-    // The timeout is accepted for CLI parity, but we don't force-terminate threads.
-    core.shutdown();
+fn graceful_shutdown(_stop: &AtomicBool, timeout: Duration, core: &Arc<Core>) {
+    core.shutdown(timeout);
+}
+
+const MODES: [&str; 5] = ["cli", "discord", "telegram", "whatsapp", "daemon"];
+
+// `--mode` is one mode or a comma-separated list (`telegram,whatsapp,cli`).
+fn select_modes(mode: &str) -> Vec<String> {
+    let mut modes: Vec<String> = Vec::new();
+    for m in mode.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+        let m = select_mode(m);
+        if !modes.contains(&m) {
+            modes.push(m);
+        }
+    }
+    if modes.is_empty() {
+        modes.push(select_mode("auto"));
+    }
+    modes
 }
 
 fn select_mode(mode: &str) -> String {
@@ -148,6 +350,42 @@ fn select_mode(mode: &str) -> String {
     selected
 }
 
+// Access control is on once any user list or a default role is set.
+fn access_from_env(reg: Arc<Registry>) -> Result<Option<Access>, String> {
+    let mut grants = Vec::new();
+    let mut configured = false;
+    for (key, role) in [
+        ("CRABPLANE_ADMINS", Role::Admin),
+        ("CRABPLANE_MEMBERS", Role::Member),
+        ("CRABPLANE_GUESTS", Role::Guest),
+    ] {
+        let v = env::var(key).unwrap_or_default();
+        configured |= !v.trim().is_empty();
+        grants.extend(parse_grants(&v, role).map_err(|e| format!("{key}: {e}"))?);
+    }
+    // Users not listed get this role; "none" (the default) keeps them out.
+    let default_role = match env::var("CRABPLANE_DEFAULT_ROLE").unwrap_or_default().trim() {
+        "" => None,
+        "none" => {
+            configured = true;
+            None
+        }
+        r => {
+            configured = true;
+            Some(Role::parse(r).ok_or_else(|| format!("CRABPLANE_DEFAULT_ROLE: unknown role {r}"))?)
+        }
+    };
+    let mut task_roles = HashMap::new();
+    for (task, r) in env_map("CRABPLANE_TASK_ROLES") {
+        let role = Role::parse(&r).ok_or_else(|| format!("CRABPLANE_TASK_ROLES: unknown role {r}"))?;
+        task_roles.insert(task, role);
+    }
+    if !configured {
+        return Ok(None);
+    }
+    Ok(Some(Access::new(reg, grants, default_role, task_roles)))
+}
+
 fn env_int(key: &str, def: i64) -> i64 {
     match env::var(key) {
         Ok(v) => v.parse::<i64>().unwrap_or(def),
@@ -155,7 +393,17 @@ fn env_int(key: &str, def: i64) -> i64 {
     }
 }
 
-fn must<T>(r: Result<T, String>) -> T {
+// Parses `key=value,key=value` lists such as `CRABPLANE_TASK_CONCURRENCY=ask=2,echo=1`.
+fn env_map(key: &str) -> HashMap<String, String> {
+    let raw = env::var(key).unwrap_or_default();
+    raw.split(',')
+        .filter_map(|kv| kv.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .filter(|(k, _)| !k.is_empty())
+        .collect()
+}
+
+fn must<T, E: std::fmt::Display>(r: Result<T, E>) -> T {
     match r {
         Ok(v) => v,
         Err(e) => panic!("{e}"),
@@ -165,7 +413,14 @@ fn must<T>(r: Result<T, String>) -> T {
 fn parse_args() -> Args {
     let mut mode = "auto".to_string();
     let mut queue_size: usize = 128;
+    let mut queue_dir: Option<String> = None;
+    let mut schedule_file: Option<String> = None;
+    let mut max_pending_per_user: usize = 0;
     let mut shutdown_timeout = Duration::from_secs(10);
+    let mut log_format = log::Format::Text;
+    let mut log_level = log::Level::Info;
+    let mut metrics_addr: Option<String> = None;
+    let mut api_addr: Option<String> = None;
 
     let mut it = env::args().skip(1);
     while let Some(a) = it.next() {
@@ -173,18 +428,46 @@ fn parse_args() -> Args {
             ("--mode", Some(v.to_string()))
         } else if let Some(v) = a.strip_prefix("--queue-size=") {
             ("--queue-size", Some(v.to_string()))
+        } else if let Some(v) = a.strip_prefix("--queue-dir=") {
+            ("--queue-dir", Some(v.to_string()))
+        } else if let Some(v) = a.strip_prefix("--schedule-file=") {
+            ("--schedule-file", Some(v.to_string()))
+        } else if let Some(v) = a.strip_prefix("--max-pending-per-user=") {
+            ("--max-pending-per-user", Some(v.to_string()))
         } else if let Some(v) = a.strip_prefix("--shutdown-timeout=") {
             ("--shutdown-timeout", Some(v.to_string()))
+        } else if let Some(v) = a.strip_prefix("--log-format=") {
+            ("--log-format", Some(v.to_string()))
+        } else if let Some(v) = a.strip_prefix("--log-level=") {
+            ("--log-level", Some(v.to_string()))
+        } else if let Some(v) = a.strip_prefix("--metrics-addr=") {
+            ("--metrics-addr", Some(v.to_string()))
+        } else if let Some(v) = a.strip_prefix("--api-addr=") {
+            ("--api-addr", Some(v.to_string()))
         } else if a == "-mode" || a == "--mode" {
             ("--mode", it.next())
         } else if a == "-queue-size" || a == "--queue-size" {
             ("--queue-size", it.next())
+        } else if a == "-queue-dir" || a == "--queue-dir" {
+            ("--queue-dir", it.next())
+        } else if a == "-schedule-file" || a == "--schedule-file" {
+            ("--schedule-file", it.next())
+        } else if a == "-max-pending-per-user" || a == "--max-pending-per-user" {
+            ("--max-pending-per-user", it.next())
         } else if a == "-shutdown-timeout" || a == "--shutdown-timeout" {
             ("--shutdown-timeout", it.next())
+        } else if a == "-log-format" || a == "--log-format" {
+            ("--log-format", it.next())
+        } else if a == "-log-level" || a == "--log-level" {
+            ("--log-level", it.next())
+        } else if a == "-metrics-addr" || a == "--metrics-addr" {
+            ("--metrics-addr", it.next())
+        } else if a == "-api-addr" || a == "--api-addr" {
+            ("--api-addr", it.next())
         } else if a == "-h" || a == "--help" {
             print_help_and_exit();
         } else {
-            eprintln!("WARN unknown arg: {}", a);
+            log::warn("unknown arg", &[("arg", &a)]);
             continue;
         };
 
@@ -193,9 +476,30 @@ fn parse_args() -> Args {
             ("--queue-size", Some(v)) => {
                 queue_size = v.parse::<usize>().unwrap_or(queue_size);
             }
+            ("--queue-dir", Some(v)) => {
+                queue_dir = Some(v).filter(|v| !v.trim().is_empty());
+            }
+            ("--schedule-file", Some(v)) => {
+                schedule_file = Some(v).filter(|v| !v.trim().is_empty());
+            }
+            ("--max-pending-per-user", Some(v)) => {
+                max_pending_per_user = v.parse::<usize>().unwrap_or(max_pending_per_user);
+            }
             ("--shutdown-timeout", Some(v)) => {
                 shutdown_timeout = parse_duration(&v).unwrap_or(shutdown_timeout);
             }
+            ("--log-format", Some(v)) => {
+                log_format = log::Format::parse(&v).unwrap_or(log_format);
+            }
+            ("--log-level", Some(v)) => {
+                log_level = log::Level::parse(&v).unwrap_or(log_level);
+            }
+            ("--metrics-addr", Some(v)) => {
+                metrics_addr = Some(v).filter(|v| !v.trim().is_empty());
+            }
+            ("--api-addr", Some(v)) => {
+                api_addr = Some(v).filter(|v| !v.trim().is_empty());
+            }
             _ => {}
         }
     }
@@ -203,33 +507,28 @@ fn parse_args() -> Args {
     Args {
         mode,
         queue_size,
+        queue_dir,
+        schedule_file,
+        max_pending_per_user,
         shutdown_timeout,
+        log_format,
+        log_level,
+        metrics_addr,
+        api_addr,
     }
 }
 
-fn parse_duration(s: &str) -> Option<Duration> {
-    let s = s.trim();
-    if let Some(v) = s.strip_suffix("ms") {
-        return v.trim().parse::<u64>().ok().map(Duration::from_millis);
-    }
-    if let Some(v) = s.strip_suffix('s') {
-        return v.trim().parse::<u64>().ok().map(Duration::from_secs);
-    }
-    if let Some(v) = s.strip_suffix('m') {
-        return v
-            .trim()
-            .parse::<u64>()
-            .ok()
-            .map(|mins| Duration::from_secs(mins * 60));
-    }
-    // If no suffix, treat as seconds.
-    s.parse::<u64>().ok().map(Duration::from_secs)
-}
-
 fn print_help_and_exit() -> ! {
     println!("clawplane v0 (rust port)");
-    println!("  -mode auto|cli|discord|telegram|whatsapp|daemon (default: auto)");
+    println!("  -mode auto|cli|discord|telegram|whatsapp|daemon, or a list like telegram,cli (default: auto)");
     println!("  -queue-size N (default: 128)");
+    println!("  -queue-dir DIR (persist queued jobs across restarts; default: in-memory)");
+    println!("  -schedule-file PATH (recurring schedules; default: DIR/schedules with -queue-dir, else in-memory)");
+    println!("  -max-pending-per-user N (0 = unlimited; default: 0)");
     println!("  -shutdown-timeout 10s|500ms|1m (default: 10s)");
+    println!("  -log-format text|json (default: text)");
+    println!("  -log-level debug|info|warn|error (default: info)");
+    println!("  -metrics-addr HOST:PORT (serve Prometheus metrics at /metrics; default: off)");
+    println!("  -api-addr HOST:PORT (admin HTTP API, needs CRABPLANE_API_TOKEN; default: off)");
     std::process::exit(0);
 }
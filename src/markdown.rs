@@ -0,0 +1,259 @@
+// The Markdown subset tasks may use in rich results, rendered per chat:
+//
+//   **bold**, *italic* or _italic_, `code`, [text](url),
+//   "# heading" lines, "- item" / "* item" bullets and ``` fenced blocks.
+//
+// Anything else is passed through as text. Unclosed markers are literal.
+
+// Telegram's `parse_mode=HTML`.
+pub fn to_html(md: &str) -> String {
+    render(md, &Html)
+}
+
+// WhatsApp's native *bold*, _italic_ and ```monospace```.
+pub fn to_whatsapp(md: &str) -> String {
+    render(md, &WhatsApp)
+}
+
+// Plain text for terminals and logs.
+pub fn to_plain(md: &str) -> String {
+    render(md, &Plain)
+}
+
+trait Style {
+    fn text(&self, s: &str) -> String;
+    fn bold(&self, s: &str) -> String;
+    fn italic(&self, s: &str) -> String;
+    fn code(&self, s: &str) -> String;
+    fn pre(&self, s: &str) -> String;
+    fn link(&self, text: &str, url: &str) -> String;
+}
+
+struct Html;
+struct WhatsApp;
+struct Plain;
+
+impl Style for Html {
+    fn text(&self, s: &str) -> String {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    }
+    fn bold(&self, s: &str) -> String {
+        format!("<b>{s}</b>")
+    }
+    fn italic(&self, s: &str) -> String {
+        format!("<i>{s}</i>")
+    }
+    fn code(&self, s: &str) -> String {
+        format!("<code>{}</code>", self.text(s))
+    }
+    fn pre(&self, s: &str) -> String {
+        format!("<pre>{}</pre>", self.text(s))
+    }
+    fn link(&self, text: &str, url: &str) -> String {
+        format!(
+            "<a href=\"{}\">{text}</a>",
+            self.text(url).replace('"', "&quot;")
+        )
+    }
+}
+
+impl Style for WhatsApp {
+    fn text(&self, s: &str) -> String {
+        s.to_string()
+    }
+    fn bold(&self, s: &str) -> String {
+        format!("*{s}*")
+    }
+    fn italic(&self, s: &str) -> String {
+        format!("_{s}_")
+    }
+    fn code(&self, s: &str) -> String {
+        format!("`{s}`")
+    }
+    fn pre(&self, s: &str) -> String {
+        format!("```{s}```")
+    }
+    fn link(&self, text: &str, url: &str) -> String {
+        plain_link(text, url)
+    }
+}
+
+impl Style for Plain {
+    fn text(&self, s: &str) -> String {
+        s.to_string()
+    }
+    fn bold(&self, s: &str) -> String {
+        s.to_string()
+    }
+    fn italic(&self, s: &str) -> String {
+        s.to_string()
+    }
+    fn code(&self, s: &str) -> String {
+        s.to_string()
+    }
+    fn pre(&self, s: &str) -> String {
+        s.to_string()
+    }
+    fn link(&self, text: &str, url: &str) -> String {
+        plain_link(text, url)
+    }
+}
+
+fn plain_link(text: &str, url: &str) -> String {
+    if text == url {
+        url.to_string()
+    } else {
+        format!("{text} ({url})")
+    }
+}
+
+fn render(md: &str, st: &dyn Style) -> String {
+    let mut out: Vec<String> = Vec::new();
+    let mut fence: Option<Vec<&str>> = None;
+    for line in md.lines() {
+        if line.trim_start().starts_with("```") {
+            match fence.take() {
+                Some(block) => out.push(st.pre(&block.join("\n"))),
+                None => fence = Some(Vec::new()),
+            }
+            continue;
+        }
+        if let Some(block) = fence.as_mut() {
+            block.push(line);
+            continue;
+        }
+        out.push(render_line(line, st));
+    }
+    // An unclosed fence runs to the end of the text.
+    if let Some(block) = fence {
+        out.push(st.pre(&block.join("\n")));
+    }
+    out.join("\n")
+}
+
+fn render_line(line: &str, st: &dyn Style) -> String {
+    let trimmed = line.trim_start();
+    let hashes = trimmed.bytes().take_while(|&b| b == b'#').count();
+    if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+        return st.bold(&inline(trimmed[hashes..].trim(), st));
+    }
+    for bullet in ["- ", "* "] {
+        if let Some(item) = trimmed.strip_prefix(bullet) {
+            let indent = &line[..line.len() - trimmed.len()];
+            return format!("{indent}• {}", inline(item, st));
+        }
+    }
+    inline(line, st)
+}
+
+fn inline(s: &str, st: &dyn Style) -> String {
+    let mut out = String::new();
+    // Start of the text not yet written to `out`.
+    let mut plain = 0;
+    let mut i = 0;
+    while i < s.len() {
+        let rest = &s[i..];
+        let span = if rest.starts_with('`') {
+            closing(s, i + 1, "`").map(|end| (st.code(&s[i + 1..end]), end + 1))
+        } else if rest.starts_with("**") {
+            closing(s, i + 2, "**").map(|end| (st.bold(&inline(&s[i + 2..end], st)), end + 2))
+        } else if rest.starts_with('*') || rest.starts_with('_') {
+            emphasis(s, i).map(|end| (st.italic(&inline(&s[i + 1..end], st)), end + 1))
+        } else if rest.starts_with('[') {
+            link(s, i).map(|(text, url, end)| (st.link(&inline(text, st), url), end))
+        } else {
+            None
+        };
+        match span {
+            Some((rendered, end)) => {
+                out.push_str(&st.text(&s[plain..i]));
+                out.push_str(&rendered);
+                i = end;
+                plain = end;
+            }
+            None => i += rest.chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    out.push_str(&st.text(&s[plain..]));
+    out
+}
+
+// Byte offset of the `marker` closing a span whose content starts at `from`;
+// spans must not be empty or start or end with a space.
+fn closing(s: &str, from: usize, marker: &str) -> Option<usize> {
+    let end = from + s[from..].find(marker)?;
+    let inner = &s[from..end];
+    if inner.is_empty() || inner.starts_with(' ') || inner.ends_with(' ') {
+        return None;
+    }
+    Some(end)
+}
+
+// `*x*` or `_x_` at `i`. An underscore inside a word (snake_case) is not a
+// marker.
+fn emphasis(s: &str, i: usize) -> Option<usize> {
+    let marker = &s[i..i + 1];
+    if marker == "_"
+        && s[..i]
+            .chars()
+            .next_back()
+            .is_some_and(char::is_alphanumeric)
+    {
+        return None;
+    }
+    let end = closing(s, i + 1, marker)?;
+    if marker == "_"
+        && s[end + 1..]
+            .chars()
+            .next()
+            .is_some_and(char::is_alphanumeric)
+    {
+        return None;
+    }
+    Some(end)
+}
+
+// `[text](url)` at `i`: (text, url, offset after the closing paren).
+fn link(s: &str, i: usize) -> Option<(&str, &str, usize)> {
+    let close = i + s[i..].find("](")?;
+    let text = &s[i + 1..close];
+    let url_start = close + 2;
+    let url_end = url_start + s[url_start..].find(')')?;
+    let url = &s[url_start..url_end];
+    if text.is_empty() || text.contains('[') || url.is_empty() || url.contains(char::is_whitespace)
+    {
+        return None;
+    }
+    Some((text, url, url_end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_html_for_telegram() {
+        assert_eq!(
+            to_html("**bold** and *it* with `a<b` & [docs](https://x.io/?a=1&b=\"2\")"),
+            "<b>bold</b> and <i>it</i> with <code>a&lt;b</code> &amp; <a href=\"https://x.io/?a=1&amp;b=&quot;2&quot;\">docs</a>"
+        );
+        assert_eq!(to_html("```\n<tag>\n```"), "<pre>&lt;tag&gt;</pre>");
+    }
+
+    #[test]
+    fn renders_whatsapp_and_plain() {
+        assert_eq!(to_whatsapp("**bold** _it_"), "*bold* _it_");
+        assert_eq!(
+            to_plain("**bold** [docs](https://x.io)"),
+            "bold docs (https://x.io)"
+        );
+    }
+
+    #[test]
+    fn unclosed_and_inner_markers_stay_literal() {
+        assert_eq!(to_plain("2 * 3 and **open"), "2 * 3 and **open");
+        assert_eq!(to_plain("snake_case_name"), "snake_case_name");
+    }
+}
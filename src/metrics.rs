@@ -0,0 +1,241 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use crate::http::{self, Reply};
+
+// Upper bounds (seconds) of the job duration histogram buckets; AI backends
+// routinely take tens of seconds, so the buckets reach a few minutes.
+const BUCKETS: [f64; 14] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0,
+];
+
+// Process-wide counters, updated by the workers and the result dispatcher
+// and rendered in the Prometheus text format by `serve`.
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+#[derive(Default)]
+struct Metrics {
+    // By (task, outcome).
+    jobs: Mutex<BTreeMap<(String, String), u64>>,
+    // By task.
+    durations: Mutex<BTreeMap<String, Histogram>>,
+    // By (adapter, task).
+    commands: Mutex<BTreeMap<(String, String), u64>>,
+    // By adapter.
+    delivery_failures: Mutex<BTreeMap<String, u64>>,
+    undeliverable: Mutex<BTreeMap<String, u64>>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    counts: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        for (i, le) in BUCKETS.iter().enumerate() {
+            if secs <= *le {
+                self.counts[i] += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+}
+
+// Point-in-time values read when the endpoint is scraped.
+pub struct Gauges {
+    pub queue_ready: usize,
+    pub queue_delayed: usize,
+    pub workers: usize,
+    pub workers_busy: usize,
+}
+
+// Counts one run of `task` that ended with `outcome` (succeeded, failed,
+// canceled, timed_out or retried) after `dur`.
+pub fn job_finished(task: &str, outcome: &str, dur: Duration) {
+    if let Ok(mut g) = METRICS.jobs.lock() {
+        *g.entry((task.to_string(), outcome.to_string()))
+            .or_default() += 1;
+    }
+    if let Ok(mut g) = METRICS.durations.lock() {
+        g.entry(task.to_string())
+            .or_default()
+            .observe(dur.as_secs_f64());
+    }
+}
+
+// Counts one command received through `adapter` and routed to `task`.
+pub fn command(adapter: &str, task: &str) {
+    if let Ok(mut g) = METRICS.commands.lock() {
+        *g.entry((adapter.to_string(), task.to_string()))
+            .or_default() += 1;
+    }
+}
+
+// Counts one failed `ResultSink::deliver` attempt through `adapter`.
+pub fn delivery_failed(adapter: &str) {
+    if let Ok(mut g) = METRICS.delivery_failures.lock() {
+        *g.entry(adapter.to_string()).or_default() += 1;
+    }
+}
+
+// Counts a result given up on and moved to the dead letters.
+pub fn undeliverable(adapter: &str) {
+    if let Ok(mut g) = METRICS.undeliverable.lock() {
+        *g.entry(adapter.to_string()).or_default() += 1;
+    }
+}
+
+// Serves `GET /metrics` on `addr`; `gauges` is called on every scrape.
+pub fn serve(
+    addr: &str,
+    gauges: impl Fn() -> Gauges + Send + Sync + 'static,
+) -> Result<(), String> {
+    http::serve("metrics", addr, move |req| {
+        match (req.method.as_str(), req.path.as_str()) {
+            ("GET", "/metrics") => Reply {
+                status: 200,
+                content_type: "text/plain; version=0.0.4; charset=utf-8",
+                body: render(&gauges()),
+            },
+            (_, "/metrics") => Reply::text(405, "method not allowed\n"),
+            _ => Reply::text(404, "not found\n"),
+        }
+    })
+}
+
+fn render(g: &Gauges) -> String {
+    let mut out = String::new();
+
+    header(
+        &mut out,
+        "crabplane_queue_length",
+        "gauge",
+        "Jobs waiting in the queue.",
+    );
+    let _ = writeln!(
+        out,
+        "crabplane_queue_length{{state=\"ready\"}} {}",
+        g.queue_ready
+    );
+    let _ = writeln!(
+        out,
+        "crabplane_queue_length{{state=\"delayed\"}} {}",
+        g.queue_delayed
+    );
+
+    header(&mut out, "crabplane_workers", "gauge", "Worker threads.");
+    let _ = writeln!(out, "crabplane_workers {}", g.workers);
+    header(
+        &mut out,
+        "crabplane_workers_busy",
+        "gauge",
+        "Workers running a job.",
+    );
+    let _ = writeln!(out, "crabplane_workers_busy {}", g.workers_busy);
+
+    header(
+        &mut out,
+        "crabplane_jobs_total",
+        "counter",
+        "Job runs by task and outcome.",
+    );
+    if let Ok(jobs) = METRICS.jobs.lock() {
+        for ((task, outcome), n) in jobs.iter() {
+            let _ = writeln!(
+                out,
+                "crabplane_jobs_total{{task=\"{}\",outcome=\"{}\"}} {n}",
+                escape(task),
+                escape(outcome)
+            );
+        }
+    }
+
+    header(
+        &mut out,
+        "crabplane_commands_total",
+        "counter",
+        "Commands received, by adapter and task.",
+    );
+    if let Ok(commands) = METRICS.commands.lock() {
+        for ((adapter, task), n) in commands.iter() {
+            let _ = writeln!(
+                out,
+                "crabplane_commands_total{{adapter=\"{}\",task=\"{}\"}} {n}",
+                escape(adapter),
+                escape(task)
+            );
+        }
+    }
+
+    header(
+        &mut out,
+        "crabplane_job_duration_seconds",
+        "histogram",
+        "Time spent running jobs, by task.",
+    );
+    if let Ok(durations) = METRICS.durations.lock() {
+        for (task, h) in durations.iter() {
+            let task = escape(task);
+            for (le, n) in BUCKETS.iter().zip(h.counts) {
+                let _ = writeln!(
+                    out,
+                    "crabplane_job_duration_seconds_bucket{{task=\"{task}\",le=\"{le}\"}} {n}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "crabplane_job_duration_seconds_bucket{{task=\"{task}\",le=\"+Inf\"}} {}",
+                h.count
+            );
+            let _ = writeln!(
+                out,
+                "crabplane_job_duration_seconds_sum{{task=\"{task}\"}} {}",
+                h.sum
+            );
+            let _ = writeln!(
+                out,
+                "crabplane_job_duration_seconds_count{{task=\"{task}\"}} {}",
+                h.count
+            );
+        }
+    }
+
+    for (name, help, counts) in [
+        (
+            "crabplane_delivery_failures_total",
+            "Failed result delivery attempts, by adapter.",
+            &METRICS.delivery_failures,
+        ),
+        (
+            "crabplane_undeliverable_total",
+            "Results moved to the dead letters, by adapter.",
+            &METRICS.undeliverable,
+        ),
+    ] {
+        header(&mut out, name, "counter", help);
+        if let Ok(counts) = counts.lock() {
+            for (adapter, n) in counts.iter() {
+                let _ = writeln!(out, "{name}{{adapter=\"{}\"}} {n}", escape(adapter));
+            }
+        }
+    }
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+// Escapes a label value.
+fn escape(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
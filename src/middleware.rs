@@ -0,0 +1,125 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::clock::parse_duration;
+use crate::jobs::fmt_dur;
+use crate::metrics;
+use crate::router::Route;
+use crate::types::{Job, Message, Response};
+
+// Middleware wraps message handling in `Core`, for concerns that apply to
+// every message: auth, rate limits, audit logs, redaction, metrics. Each hook
+// may change what it is given; the `before_*` and `after_route` hooks may
+// also answer the message themselves by returning a response, which stops it
+// there. Middleware runs in the order it was added, and the defaults pass
+// everything through.
+pub trait Middleware: Send + Sync {
+    // Named in the log when the middleware stops a message.
+    fn name(&self) -> &'static str;
+
+    // The message as the adapter sent it, command or not.
+    fn before_route(&self, _msg: &mut Message) -> Option<Response> {
+        None
+    }
+
+    // A command, routed to a task or a built-in command such as `!jobs`.
+    fn after_route(&self, _msg: &Message, _route: &mut Route) -> Option<Response> {
+        None
+    }
+
+    // A job about to be queued.
+    fn before_submit(&self, _job: &mut Job) -> Option<Response> {
+        None
+    }
+
+    // A finished job's result before it is delivered. Streamed chunks and
+    // progress notes do not pass through here.
+    fn after_result(&self, _job: &Job, _resp: &mut Response) {}
+}
+
+// CommandMetrics counts routed commands by adapter and task.
+pub struct CommandMetrics;
+
+impl Middleware for CommandMetrics {
+    fn name(&self) -> &'static str {
+        "metrics"
+    }
+
+    fn after_route(&self, msg: &Message, route: &mut Route) -> Option<Response> {
+        metrics::command(&msg.source, &route.task_name);
+        None
+    }
+}
+
+// RateLimit lets each user run at most `max` commands per `window`; further
+// commands are answered with how long to wait. Users are told apart per
+// adapter.
+pub struct RateLimit {
+    max: usize,
+    window: Duration,
+    // Times of each user's recent commands, oldest first.
+    seen: Mutex<HashMap<(String, String), VecDeque<Instant>>>,
+}
+
+impl RateLimit {
+    pub fn new(max: usize, window: Duration) -> Self {
+        Self {
+            max: max.max(1),
+            window,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // `N/DURATION`, e.g. `20/1m`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (n, window) = s.trim().split_once('/')?;
+        let n = n.trim().parse::<usize>().ok().filter(|n| *n > 0)?;
+        let window = parse_duration(window.trim()).filter(|d| !d.is_zero())?;
+        Some(Self::new(n, window))
+    }
+}
+
+impl Middleware for RateLimit {
+    fn name(&self) -> &'static str {
+        "rate_limit"
+    }
+
+    fn after_route(&self, msg: &Message, _route: &mut Route) -> Option<Response> {
+        let now = Instant::now();
+        let mut seen = self.seen.lock().ok()?;
+        // Forget users who have been quiet for a whole window.
+        seen.retain(|_, times| {
+            times
+                .back()
+                .is_some_and(|t| now.duration_since(*t) < self.window)
+        });
+        let times = seen
+            .entry((msg.source.clone(), msg.user_id.clone()))
+            .or_default();
+        while times
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= self.window)
+        {
+            times.pop_front();
+        }
+        if times.len() < self.max {
+            times.push_back(now);
+            return None;
+        }
+        let wait = times
+            .front()
+            .map(|t| self.window.saturating_sub(now.duration_since(*t)))
+            .unwrap_or(self.window);
+        Some(Response {
+            text: format!(
+                "slow down: at most {} commands per {}; try again in {}",
+                self.max,
+                fmt_dur(self.window),
+                fmt_dur(wait)
+            ),
+            ephemeral: true,
+            ..Default::default()
+        })
+    }
+}
@@ -1,22 +1,239 @@
-use std::collections::VecDeque;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::path::Path;
 use std::sync::{Condvar, Mutex};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
-use crate::types::Job;
+use crate::journal::Journal;
+use crate::types::{Job, Priority};
+
+const LANES: usize = Priority::ALL.len();
+
+// A waiting lane is served anyway once this many jobs from higher lanes have
+// been dequeued ahead of it, so low-priority work cannot starve.
+const STARVATION_LIMIT: usize = 8;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum QueueError {
     Closed,
     Canceled,
+    Journal,
+    // The submitting user already has this many jobs waiting.
+    UserLimit(usize),
+}
+
+// Lane is one priority level. Jobs are grouped per user and users take turns
+// (round-robin), so one user's burst cannot monopolise the workers; each
+// user's own jobs stay FIFO.
+#[derive(Default)]
+struct Lane {
+    turns: VecDeque<String>,
+    jobs: HashMap<String, VecDeque<Job>>,
+    len: usize,
+}
+
+impl Lane {
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn push(&mut self, key: String, job: Job) {
+        let q = self.jobs.entry(key.clone()).or_default();
+        if q.is_empty() {
+            self.turns.push_back(key);
+        }
+        q.push_back(job);
+        self.len += 1;
+    }
+
+    // Takes the first job `accept` agrees to run, giving each user one turn in
+    // rotation order. Users whose jobs are all rejected keep their place.
+    fn pop_where(&mut self, accept: &mut dyn FnMut(&Job) -> bool) -> Option<Job> {
+        for turn in 0..self.turns.len() {
+            let key = &self.turns[turn];
+            let Some(q) = self.jobs.get_mut(key) else {
+                continue;
+            };
+            let Some(idx) = q.iter().position(&mut *accept) else {
+                continue;
+            };
+            let job = q.remove(idx);
+            let key = self.turns.remove(turn)?;
+            if q.is_empty() {
+                self.jobs.remove(&key);
+            } else {
+                self.turns.push_back(key);
+            }
+            self.len -= 1;
+            return job;
+        }
+        None
+    }
+}
+
+impl Lane {
+    fn remove(&mut self, key: &str, job_id: &str) -> Option<Job> {
+        let q = self.jobs.get_mut(key)?;
+        let idx = q.iter().position(|j| j.id == job_id)?;
+        let job = q.remove(idx);
+        if q.is_empty() {
+            self.jobs.remove(key);
+            self.turns.retain(|k| k != key);
+        }
+        self.len -= 1;
+        job
+    }
+}
+
+// A job held back until `at`; ordered by due time, then arrival.
+struct Delayed {
+    at: SystemTime,
+    seq: u64,
+    job: Job,
+}
+
+impl PartialEq for Delayed {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Delayed {}
+
+impl PartialOrd for Delayed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Delayed {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.seq).cmp(&(other.at, other.seq))
+    }
 }
 
 struct Inner {
-    buf: VecDeque<Job>,
+    lanes: [Lane; LANES],
+    // Jobs with a future `not_before`, soonest first. They count towards
+    // capacity and per-user limits like the jobs in the lanes.
+    delayed: BinaryHeap<Reverse<Delayed>>,
+    delayed_seq: u64,
+    // Per lane: dequeues served from higher lanes while this one was waiting.
+    skipped: [usize; LANES],
+    // Waiting jobs per fairness key, across all lanes and the delayed jobs.
+    per_user: HashMap<String, usize>,
     closed: bool,
 }
 
+impl Inner {
+    fn len(&self) -> usize {
+        self.lanes.iter().map(|l| l.len).sum()
+    }
+
+    // Ready and delayed jobs together.
+    fn waiting(&self) -> usize {
+        self.len() + self.delayed.len()
+    }
+
+    fn push(&mut self, job: Job) {
+        *self.per_user.entry(fair_key(&job)).or_default() += 1;
+        self.place(job);
+    }
+
+    // Puts an already counted job in its lane, or with the delayed jobs if it
+    // is not due yet.
+    fn place(&mut self, job: Job) {
+        if let Some(at) = job.not_before
+            && at > SystemTime::now()
+        {
+            self.delayed_seq += 1;
+            let seq = self.delayed_seq;
+            self.delayed.push(Reverse(Delayed { at, seq, job }));
+            return;
+        }
+        self.lanes[job.priority.lane()].push(fair_key(&job), job);
+    }
+
+    // Moves delayed jobs that are now due into their lanes.
+    fn promote_due(&mut self) {
+        let now = SystemTime::now();
+        while self.delayed.peek().is_some_and(|d| d.0.at <= now) {
+            if let Some(Reverse(d)) = self.delayed.pop() {
+                let mut job = d.job;
+                job.not_before = None;
+                self.place(job);
+            }
+        }
+    }
+
+    fn pop_where(&mut self, accept: &mut dyn FnMut(&Job) -> bool) -> Option<Job> {
+        self.promote_due();
+        let starved = (0..LANES)
+            .rev()
+            .filter(|&l| !self.lanes[l].is_empty() && self.skipped[l] >= STARVATION_LIMIT);
+        let order: Vec<usize> = starved.chain(0..LANES).collect();
+        let (lane, job) = order
+            .into_iter()
+            .find_map(|l| self.lanes[l].pop_where(accept).map(|j| (l, j)))?;
+        for l in lane + 1..LANES {
+            if !self.lanes[l].is_empty() {
+                self.skipped[l] += 1;
+            }
+        }
+        self.skipped[lane] = 0;
+        self.uncount(&job);
+        Some(job)
+    }
+
+    fn remove(&mut self, job_id: &str) -> Option<Job> {
+        if self.delayed.iter().any(|d| d.0.job.id == job_id) {
+            let (hit, keep): (Vec<_>, Vec<_>) = std::mem::take(&mut self.delayed)
+                .into_vec()
+                .into_iter()
+                .partition(|d| d.0.job.id == job_id);
+            self.delayed = keep.into();
+            let job = hit.into_iter().next()?.0.job;
+            self.uncount(&job);
+            return Some(job);
+        }
+        let job = self.lanes.iter_mut().find_map(|lane| {
+            let key = lane
+                .jobs
+                .iter()
+                .find(|(_, q)| q.iter().any(|j| j.id == job_id))
+                .map(|(k, _)| k.clone())?;
+            lane.remove(&key, job_id)
+        })?;
+        self.uncount(&job);
+        Some(job)
+    }
+
+    fn uncount(&mut self, job: &Job) {
+        let key = fair_key(job);
+        if let Some(n) = self.per_user.get_mut(&key) {
+            *n -= 1;
+            if *n == 0 {
+                self.per_user.remove(&key);
+            }
+        }
+    }
+}
+
+// Jobs are grouped by user; messages without a user id (e.g. some webhooks)
+// fall back to their channel.
+fn fair_key(job: &Job) -> String {
+    if job.user_id.is_empty() {
+        format!("channel:{}", job.channel_id)
+    } else {
+        format!("user:{}", job.user_id)
+    }
+}
+
 pub struct Queue {
     cap: usize,
+    // Max waiting jobs per user; 0 means unlimited.
+    user_limit: usize,
+    journal: Option<Journal>,
     inner: Mutex<Inner>,
     not_empty: Condvar,
     not_full: Condvar,
@@ -27,8 +244,14 @@ impl Queue {
         let cap = if size == 0 { 64 } else { size };
         Self {
             cap,
+            user_limit: 0,
+            journal: None,
             inner: Mutex::new(Inner {
-                buf: VecDeque::with_capacity(cap),
+                lanes: std::array::from_fn(|_| Lane::default()),
+                delayed: BinaryHeap::new(),
+                delayed_seq: 0,
+                skipped: [0; LANES],
+                per_user: HashMap::new(),
                 closed: false,
             }),
             not_empty: Condvar::new(),
@@ -36,6 +259,37 @@ impl Queue {
         }
     }
 
+    // Opens a durable queue backed by a journal in `dir`. Jobs left over from a
+    // previous run (enqueued but never acknowledged) are queued again, ahead of
+    // anything new; the capacity limit does not apply to them.
+    pub fn open(size: usize, dir: &Path) -> Result<Self, String> {
+        let (journal, pending) = Journal::open(dir)?;
+        let mut q = Self::new(size);
+        q.journal = Some(journal);
+        if let Ok(g) = q.inner.get_mut() {
+            for job in pending {
+                g.push(job);
+            }
+        }
+        Ok(q)
+    }
+
+    pub fn set_user_limit(&mut self, limit: usize) {
+        self.user_limit = limit;
+    }
+
+    pub fn pending(&self) -> usize {
+        self.inner.lock().map(|g| g.waiting()).unwrap_or(0)
+    }
+
+    // Waiting jobs as (ready to run, delayed until their `not_before`).
+    pub fn depth(&self) -> (usize, usize) {
+        self.inner
+            .lock()
+            .map(|g| (g.len(), g.delayed.len()))
+            .unwrap_or((0, 0))
+    }
+
     pub fn enqueue(
         &self,
         job: Job,
@@ -49,8 +303,17 @@ impl Queue {
             if canceled.load(std::sync::atomic::Ordering::Relaxed) {
                 return Err(QueueError::Canceled);
             }
-            if g.buf.len() < self.cap {
-                g.buf.push_back(job);
+            if self.user_limit > 0 {
+                let waiting = g.per_user.get(&fair_key(&job)).copied().unwrap_or(0);
+                if waiting >= self.user_limit {
+                    return Err(QueueError::UserLimit(waiting));
+                }
+            }
+            if g.waiting() < self.cap {
+                if let Some(j) = &self.journal {
+                    j.append(&job).map_err(|_| QueueError::Journal)?;
+                }
+                g.push(job);
                 self.not_empty.notify_one();
                 return Ok(());
             }
@@ -62,10 +325,32 @@ impl Queue {
         }
     }
 
-    pub fn dequeue(&self, canceled: &std::sync::atomic::AtomicBool) -> Result<Job, QueueError> {
+    // Puts a job that already passed admission back in line (e.g. for a
+    // retry). Capacity and per-user limits do not apply.
+    pub fn requeue(&self, job: Job) -> Result<(), QueueError> {
+        let mut g = self.inner.lock().map_err(|_| QueueError::Closed)?;
+        if g.closed {
+            return Err(QueueError::Closed);
+        }
+        if let Some(j) = &self.journal {
+            j.append(&job).map_err(|_| QueueError::Journal)?;
+        }
+        g.push(job);
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    // Blocks until a job that `accept` agrees to run is available. `accept` is
+    // called under the queue lock and the job is removed as soon as it returns
+    // true, so it may reserve capacity for the job.
+    pub fn dequeue(
+        &self,
+        canceled: &std::sync::atomic::AtomicBool,
+        mut accept: impl FnMut(&Job) -> bool,
+    ) -> Result<Job, QueueError> {
         let mut g = self.inner.lock().map_err(|_| QueueError::Closed)?;
         loop {
-            if let Some(job) = g.buf.pop_front() {
+            if let Some(job) = g.pop_where(&mut accept) {
                 self.not_full.notify_one();
                 return Ok(job);
             }
@@ -83,6 +368,28 @@ impl Queue {
         }
     }
 
+    // Removes a job that is still waiting. None if it was already dequeued.
+    pub fn remove(&self, job_id: &str) -> Option<Job> {
+        let mut g = self.inner.lock().ok()?;
+        let job = g.remove(job_id)?;
+        self.not_full.notify_one();
+        Some(job)
+    }
+
+    // Marks a dequeued job as done so it is not replayed after a restart.
+    // No-op for in-memory queues.
+    pub fn ack(&self, job_id: &str) -> Result<(), String> {
+        match &self.journal {
+            Some(j) => j.ack(job_id),
+            None => Ok(()),
+        }
+    }
+
+    // Wakes blocked consumers so they re-check jobs they previously rejected.
+    pub fn notify(&self) {
+        self.not_empty.notify_all();
+    }
+
     pub fn close(&self) {
         if let Ok(mut g) = self.inner.lock() {
             g.closed = true;
@@ -91,3 +398,102 @@ impl Queue {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    fn job(id: &str, user: &str, priority: Priority) -> Job {
+        Job {
+            priority,
+            ..Job::test(id, "echo", user)
+        }
+    }
+
+    fn drain(q: &Queue) -> Vec<String> {
+        let canceled = AtomicBool::new(false);
+        (0..q.depth().0)
+            .map(|_| q.dequeue(&canceled, |_| true).unwrap().id)
+            .collect()
+    }
+
+    #[test]
+    fn users_take_turns_within_a_lane() {
+        let q = Queue::new(16);
+        let canceled = AtomicBool::new(false);
+        for (id, user) in [
+            ("a1", "a"),
+            ("a2", "a"),
+            ("a3", "a"),
+            ("b1", "b"),
+            ("c1", "c"),
+        ] {
+            q.enqueue(job(id, user, Priority::Normal), &canceled)
+                .unwrap();
+        }
+        assert_eq!(drain(&q), ["a1", "b1", "c1", "a2", "a3"]);
+    }
+
+    #[test]
+    fn higher_lanes_go_first_but_lower_ones_are_not_starved() {
+        let q = Queue::new(32);
+        let canceled = AtomicBool::new(false);
+        q.enqueue(job("low", "a", Priority::Low), &canceled)
+            .unwrap();
+        q.enqueue(job("normal", "a", Priority::Normal), &canceled)
+            .unwrap();
+        for i in 0..STARVATION_LIMIT + 2 {
+            q.enqueue(job(&format!("h{i}"), "b", Priority::High), &canceled)
+                .unwrap();
+        }
+        // Both lower lanes waited behind STARVATION_LIMIT high jobs, so they
+        // cut in, lowest first.
+        let high = |r: std::ops::Range<usize>| r.map(|i| format!("h{i}"));
+        let mut want: Vec<String> = high(0..STARVATION_LIMIT).collect();
+        want.extend(["low".to_string(), "normal".to_string()]);
+        want.extend(high(STARVATION_LIMIT..STARVATION_LIMIT + 2));
+        assert_eq!(drain(&q), want);
+    }
+
+    #[test]
+    fn per_user_limit_counts_delayed_jobs() {
+        let mut q = Queue::new(16);
+        q.set_user_limit(2);
+        let canceled = AtomicBool::new(false);
+        let later = Job {
+            not_before: Some(SystemTime::now() + Duration::from_secs(3600)),
+            ..job("a1", "a", Priority::Normal)
+        };
+        q.enqueue(later, &canceled).unwrap();
+        q.enqueue(job("a2", "a", Priority::Normal), &canceled)
+            .unwrap();
+        assert_eq!(
+            q.enqueue(job("a3", "a", Priority::Normal), &canceled),
+            Err(QueueError::UserLimit(2))
+        );
+        q.enqueue(job("b1", "b", Priority::Normal), &canceled)
+            .unwrap();
+        assert_eq!(q.depth(), (2, 1));
+
+        // Taking a job frees a place for its user.
+        assert_eq!(drain(&q), ["a2", "b1"]);
+        q.enqueue(job("a3", "a", Priority::Normal), &canceled)
+            .unwrap();
+        assert_eq!(q.remove("a1").map(|j| j.id), Some("a1".to_string()));
+        assert_eq!(q.pending(), 1);
+    }
+
+    #[test]
+    fn rejected_jobs_keep_their_place() {
+        let q = Queue::new(8);
+        let canceled = AtomicBool::new(false);
+        for (id, user) in [("a1", "a"), ("b1", "b")] {
+            q.enqueue(job(id, user, Priority::Normal), &canceled)
+                .unwrap();
+        }
+        let j = q.dequeue(&canceled, |j| j.user_id == "b").unwrap();
+        assert_eq!(j.id, "b1");
+        assert_eq!(drain(&q), ["a1"]);
+    }
+}
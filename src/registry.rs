@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
+use crate::error::Error;
 use crate::tasks::Task;
 
 #[derive(Default)]
@@ -13,18 +14,20 @@ impl Registry {
         Self::default()
     }
 
-    pub fn register(&self, t: Arc<dyn Task>) -> Result<(), String> {
+    pub fn register(&self, t: Arc<dyn Task>) -> Result<(), Error> {
         let name = t.name();
         if name.is_empty() {
-            return Err("registry: task name is empty".to_string());
+            return Err(Error::internal("registry: task name is empty"));
         }
 
         let mut g = self
             .tasks
             .write()
-            .map_err(|_| "registry: poisoned lock".to_string())?;
+            .map_err(|_| Error::internal("registry: poisoned lock"))?;
         if g.contains_key(name) {
-            return Err(format!("registry: task already registered: {name}"));
+            return Err(Error::internal(format!(
+                "registry: task already registered: {name}"
+            )));
         }
         g.insert(name.to_string(), t);
         Ok(())
@@ -34,4 +37,11 @@ impl Registry {
         let g = self.tasks.read().ok()?;
         g.get(name).cloned()
     }
+
+    pub fn list(&self) -> Vec<Arc<dyn Task>> {
+        match self.tasks.read() {
+            Ok(g) => g.values().cloned().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
 }
@@ -1,16 +1,24 @@
-use crate::types::{Message, TaskInput};
+use std::time::SystemTime;
+
+use crate::clock;
+use crate::error::Error;
+use crate::types::{Args, Attachment, Message, Priority, TaskInput};
 
 #[derive(Clone, Debug)]
 pub struct Route {
     pub task_name: String,
     pub input: TaskInput,
+    // None: use the task's declared default.
+    pub priority: Option<Priority>,
+    // Hold the job until this time.
+    pub not_before: Option<SystemTime>,
 }
 
 pub trait Router: Send + Sync {
     // Ok(None): not a command
     // Ok(Some(Route)): valid command -> route
-    // Err(String): command-like input but invalid/unknown
-    fn route(&self, msg: &Message) -> Result<Option<Route>, String>;
+    // Err(Error): command-like input but invalid/unknown
+    fn route(&self, msg: &Message) -> Result<Option<Route>, Error>;
 }
 
 // PrefixRouter implements v0 prefix-based routing:
@@ -18,7 +26,12 @@ pub trait Router: Send + Sync {
 // - !echo <text>
 // - !ask <prompt>
 // - !onboard [chat|ai|all]
-// - any other non-empty message -> default ask task (selected backend)
+// - !remind <10m|2h|14:30|2026-10-20 09:00> <text>
+// - !cancel <job-id|last>, !jobs, !status [job-id|last] (handled by the engine, not queued)
+// - any other non-empty message -> default ask task (selected backend), low priority
+//
+// Arguments become `TaskInput::Args` (see `parse_args`), carrying the
+// message's attachments.
 #[derive(Clone, Debug, Default)]
 pub struct PrefixRouter;
 
@@ -29,8 +42,10 @@ impl PrefixRouter {
 }
 
 impl Router for PrefixRouter {
-    fn route(&self, msg: &Message) -> Result<Option<Route>, String> {
+    fn route(&self, msg: &Message) -> Result<Option<Route>, Error> {
         let text = msg.text.trim();
+        // Commands come from the text, so a photo or file sent without a
+        // caption has nothing to run and is ignored.
         if text.is_empty() {
             return Ok(None);
         }
@@ -39,28 +54,103 @@ impl Router for PrefixRouter {
             return Ok(Some(Route {
                 task_name: "ping".to_string(),
                 input: TaskInput::Empty,
+                priority: None,
+                not_before: None,
             }));
         }
 
         if let Some(rest) = text.strip_prefix("!echo") {
             let rest = rest.trim();
             if rest.is_empty() {
-                return Err("usage: !echo <text>".to_string());
+                return Err(Error::user("usage: !echo <text>"));
             }
             return Ok(Some(Route {
                 task_name: "echo".to_string(),
-                input: TaskInput::Text(rest.to_string()),
+                input: args_input(rest, msg),
+                priority: None,
+                not_before: None,
             }));
         }
 
         if let Some(rest) = text.strip_prefix("!ask") {
             let rest = rest.trim();
             if rest.is_empty() {
-                return Err("usage: !ask <prompt>".to_string());
+                return Err(Error::user("usage: !ask <prompt>"));
             }
             return Ok(Some(Route {
                 task_name: "ask".to_string(),
-                input: TaskInput::Text(rest.to_string()),
+                input: args_input(rest, msg),
+                priority: None,
+                not_before: None,
+            }));
+        }
+
+        if let Some(rest) = text.strip_prefix("!remind") {
+            let usage = "usage: !remind <10m|2h|14:30|2026-10-20 09:00> <text>";
+            let Some((at, rest)) = clock::parse_when(rest, SystemTime::now()) else {
+                return Err(Error::user(usage));
+            };
+            if rest.trim().is_empty() {
+                return Err(Error::user(usage));
+            }
+            return Ok(Some(Route {
+                task_name: "remind".to_string(),
+                input: args_input(rest.trim(), msg),
+                priority: None,
+                not_before: Some(at),
+            }));
+        }
+
+        if let Some(rest) = text.strip_prefix("!cancel") {
+            let rest = rest.trim();
+            if rest.is_empty() || rest.contains(char::is_whitespace) {
+                return Err(Error::user("usage: !cancel <job-id|last>"));
+            }
+            return Ok(Some(Route {
+                task_name: "cancel".to_string(),
+                input: args_input(rest, msg),
+                priority: None,
+                not_before: None,
+            }));
+        }
+
+        if text == "!jobs" {
+            return Ok(Some(Route {
+                task_name: "jobs".to_string(),
+                input: TaskInput::Empty,
+                priority: None,
+                not_before: None,
+            }));
+        }
+
+        if let Some(rest) = text.strip_prefix("!status") {
+            let rest = rest.trim();
+            if rest.contains(char::is_whitespace) {
+                return Err(Error::user("usage: !status [job-id|last]"));
+            }
+            return Ok(Some(Route {
+                task_name: "status".to_string(),
+                input: args_input(if rest.is_empty() { "last" } else { rest }, msg),
+                priority: None,
+                not_before: None,
+            }));
+        }
+
+        if let Some(rest) = text.strip_prefix("!undelivered") {
+            return Ok(Some(Route {
+                task_name: "undelivered".to_string(),
+                input: args_input(rest.trim(), msg),
+                priority: None,
+                not_before: None,
+            }));
+        }
+
+        if let Some(rest) = text.strip_prefix("!schedule") {
+            return Ok(Some(Route {
+                task_name: "schedule".to_string(),
+                input: args_input(rest.trim(), msg),
+                priority: None,
+                not_before: None,
             }));
         }
 
@@ -68,17 +158,191 @@ impl Router for PrefixRouter {
             let rest = rest.trim();
             return Ok(Some(Route {
                 task_name: "onboard".to_string(),
-                input: if rest.is_empty() {
-                    TaskInput::Empty
-                } else {
-                    TaskInput::Text(rest.to_string())
-                },
+                input: args_input(rest, msg),
+                priority: None,
+                not_before: None,
             }));
         }
 
         Ok(Some(Route {
             task_name: "ask".to_string(),
-            input: TaskInput::Text(text.to_string()),
+            input: args_input(text, msg),
+            priority: Some(Priority::Low),
+            not_before: None,
         }))
     }
 }
+
+fn args_input(rest: &str, msg: &Message) -> TaskInput {
+    if rest.is_empty() && msg.attachments.is_empty() {
+        return TaskInput::Empty;
+    }
+    TaskInput::Args(parse_args(rest, msg.attachments.clone()))
+}
+
+// Splits `raw` into words and sorts them into positionals and flags.
+//
+// Quoting is forgiving, since most arguments are chat text: a `"` or `'` at
+// the start of a word (or right after `=` in a flag) quotes up to the
+// matching quote, and `\"` escapes a double quote inside double quotes. A
+// quote without a partner, or inside a word as in "don't", is kept as is.
+// `--key=value` and `--key` are flags unless quoted; a lone `--` makes the
+// rest positional.
+pub fn parse_args(raw: &str, attachments: Vec<Attachment>) -> Args {
+    let mut args = Args {
+        raw: raw.to_string(),
+        attachments,
+        ..Args::default()
+    };
+    let mut flags_done = false;
+    for (word, quoted) in split_words(raw) {
+        if !quoted && !flags_done {
+            if word == "--" {
+                flags_done = true;
+                continue;
+            }
+            if let Some(flag) = word.strip_prefix("--") {
+                let (k, v) = flag.split_once('=').unwrap_or((flag, "true"));
+                args.flags.insert(k.to_string(), v.to_string());
+                continue;
+            }
+        }
+        args.positional.push(word);
+    }
+    args
+}
+
+// Words of `s` and whether each started with a quote.
+fn split_words(s: &str) -> Vec<(String, bool)> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut words = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let mut word = String::new();
+        let mut quoted = false;
+        let start = i;
+        while i < chars.len() && !chars[i].is_whitespace() {
+            let c = chars[i];
+            let opens = matches!(c, '"' | '\'') && (word.is_empty() || word.ends_with('='));
+            if opens && let Some((text, end)) = quoted_at(&chars, i) {
+                quoted |= i == start;
+                word.push_str(&text);
+                i = end;
+                continue;
+            }
+            word.push(c);
+            i += 1;
+        }
+        words.push((word, quoted));
+    }
+    words
+}
+
+// The text of the quote opening at `chars[start]` and the index just past
+// its closing quote; None if it is never closed.
+fn quoted_at(chars: &[char], start: usize) -> Option<(String, usize)> {
+    let q = chars[start];
+    let mut text = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            c if c == q => return Some((text, i + 1)),
+            '\\' if q == '"' && matches!(chars.get(i + 1), Some('"' | '\\')) => {
+                text.push(chars[i + 1]);
+                i += 2;
+            }
+            c => {
+                text.push(c);
+                i += 1;
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn msg(text: &str) -> Message {
+        Message {
+            source: "cli".to_string(),
+            user_id: "u".to_string(),
+            channel: "c".to_string(),
+            text: text.to_string(),
+            attachments: Vec::new(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    fn route(text: &str) -> Option<Route> {
+        PrefixRouter::new().route(&msg(text)).unwrap()
+    }
+
+    #[test]
+    fn splits_positionals_and_flags() {
+        let a = parse_args("one --n=3 --dry two", Vec::new());
+        assert_eq!(a.positional, ["one", "two"]);
+        assert_eq!(a.flags["n"], "3");
+        assert_eq!(a.flags["dry"], "true");
+        assert_eq!(a.raw, "one --n=3 --dry two");
+    }
+
+    #[test]
+    fn quotes_group_words() {
+        let a = parse_args(
+            r#""hello world" 'x y' --msg="a b" "--not-a-flag""#,
+            Vec::new(),
+        );
+        assert_eq!(a.positional, ["hello world", "x y", "--not-a-flag"]);
+        assert_eq!(a.flags["msg"], "a b");
+
+        let a = parse_args(r#""say \"hi\"" don't "open"#, Vec::new());
+        assert_eq!(a.positional, ["say \"hi\"", "don't", "\"open"]);
+    }
+
+    #[test]
+    fn double_dash_ends_flags() {
+        let a = parse_args("--x -- --y z", Vec::new());
+        assert_eq!(a.flags.len(), 1);
+        assert_eq!(a.positional, ["--y", "z"]);
+    }
+
+    #[test]
+    fn routes_commands() {
+        assert_eq!(route("!ping").unwrap().task_name, "ping");
+        assert_eq!(route("   ").map(|r| r.task_name), None);
+
+        let r = route("what is rust?").unwrap();
+        assert_eq!(r.task_name, "ask");
+        assert_eq!(r.priority, Some(Priority::Low));
+        assert_eq!(r.input.text(), "what is rust?");
+
+        let r = route("!remind 10m stretch").unwrap();
+        assert_eq!(r.task_name, "remind");
+        assert_eq!(r.input.text(), "stretch");
+        assert!(r.not_before.is_some());
+
+        assert_eq!(route("!status").unwrap().input.text(), "last");
+    }
+
+    #[test]
+    fn rejects_bad_usage() {
+        let router = PrefixRouter::new();
+        for text in [
+            "!echo",
+            "!ask  ",
+            "!remind later",
+            "!remind 10m",
+            "!cancel a b",
+        ] {
+            let err = router.route(&msg(text)).unwrap_err();
+            assert!(err.msg.starts_with("usage:"), "{text}: {}", err.msg);
+        }
+    }
+}
@@ -0,0 +1,450 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::clock;
+use crate::journal::{decode_fields, encode_fields};
+
+// Cron is a parsed five-field cron expression (minute, hour, day of month,
+// month, day of week) evaluated in `CRABPLANE_UTC_OFFSET` local time.
+#[derive(Clone, Debug)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // Classic cron: when both day fields are restricted, either may match.
+    any_day: bool,
+}
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+impl Cron {
+    // Accepts `*`, lists (`1,15`), ranges (`1-5`), steps (`*/15`, `0-30/10`),
+    // month and weekday names (`jan`, `mon`) and the aliases `@hourly`,
+    // `@daily`/`@midnight`, `@weekly`, `@monthly`, `@yearly`/`@annually`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let expanded = match spec.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            s => s,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [min, hour, dom, mon, dow] = fields.as_slice() else {
+            return Err(format!("cron expression needs 5 fields: {spec}"));
+        };
+        let mut weekdays = parse_field(dow, 0, 7, &WEEKDAYS)?;
+        // Both 0 and 7 mean Sunday.
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            minutes: parse_field(min, 0, 59, &[])?,
+            hours: parse_field(hour, 0, 23, &[])?,
+            days: parse_field(dom, 1, 31, &[])?,
+            months: parse_field(mon, 1, 12, &MONTHS)?,
+            weekdays,
+            any_day: *dom != "*" && *dow != "*",
+        })
+    }
+
+    // First matching minute strictly after `t`. None if nothing matches within
+    // the next eight years (e.g. `0 0 30 2 *`).
+    pub fn next_after(&self, t: SystemTime) -> Option<SystemTime> {
+        let (today, minute) = clock::local_minute(t);
+        for day in today..today + 8 * 366 {
+            if !self.matches_day(day) {
+                continue;
+            }
+            let from = if day == today { minute + 1 } else { 0 };
+            for m in from..24 * 60 {
+                if bit(self.hours, m / 60) && bit(self.minutes, m % 60) {
+                    return clock::from_local_minute(day, m);
+                }
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, day: i64) -> bool {
+        let (_, month, dom, dow) = clock::local_date(day);
+        if !bit(self.months, month) {
+            return false;
+        }
+        let (d, w) = (bit(self.days, dom), bit(self.weekdays, dow));
+        if self.any_day { d || w } else { d && w }
+    }
+}
+
+fn bit(set: u64, n: u32) -> bool {
+    set & (1 << n) != 0
+}
+
+fn parse_field(field: &str, lo: u32, hi: u32, names: &[&str]) -> Result<u64, String> {
+    let value = |s: &str| -> Result<u32, String> {
+        let lower = s.to_ascii_lowercase();
+        if let Some(i) = names.iter().position(|n| *n == lower) {
+            // Month names count from 1, weekday names from 0.
+            return Ok(i as u32 + lo);
+        }
+        let n = s
+            .parse::<u32>()
+            .map_err(|_| format!("invalid cron value: {s}"))?;
+        if n < lo || n > hi {
+            return Err(format!("cron value {n} out of range {lo}-{hi}"));
+        }
+        Ok(n)
+    };
+
+    let mut set = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((r, s)) => (
+                r,
+                s.parse::<u32>()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .ok_or_else(|| format!("invalid cron step: {part}"))?,
+            ),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (lo, hi),
+            r => match r.split_once('-') {
+                Some((a, b)) => (value(a)?, value(b)?),
+                // `5/10` means every 10 starting at 5.
+                None if step > 1 => (value(r)?, hi),
+                None => {
+                    let v = value(r)?;
+                    (v, v)
+                }
+            },
+        };
+        if start > end {
+            return Err(format!("invalid cron range: {part}"));
+        }
+        for n in (start..=end).step_by(step as usize) {
+            set |= 1 << n;
+        }
+    }
+    Ok(set)
+}
+
+// Schedule is one recurring job: `task_name` runs with `text` as input and its
+// result goes to `channel_id` on the `source` adapter.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    pub id: String,
+    pub spec: String,
+    pub task_name: String,
+    pub text: String,
+    pub channel_id: String,
+    pub source: String,
+    pub user_id: String,
+    pub next: Option<SystemTime>,
+    cron: Cron,
+}
+
+impl Schedule {
+    // One-line summary for `!schedule list`.
+    pub fn summary(&self) -> String {
+        let mut line = format!("{} [{}] {}", self.id, self.spec, self.task_name);
+        if !self.text.is_empty() {
+            line.push(' ');
+            line.push_str(&self.text);
+        }
+        line
+    }
+
+    // Schedules without a source belong to whichever adapter is running.
+    fn delivers_to(&self, source: &str, channel_id: &str) -> bool {
+        self.channel_id == channel_id && (self.source.is_empty() || self.source == source)
+    }
+}
+
+// Scheduler holds the recurring schedules and, when given a file, keeps it in
+// sync so schedules survive restarts.
+//
+// The file has one schedule per line as tab-separated `key=value` fields
+// (`id`, `cron`, `task`, `channel`, optional `source`, `user` and `text`;
+// `source` may be left out when only one adapter is running); blank lines
+// and lines starting with `#` are ignored. Added schedules are appended to
+// it and removed ones have their line deleted; everything else in the file,
+// comments included, is left as written.
+pub struct Scheduler {
+    path: Option<PathBuf>,
+    entries: Mutex<Vec<Schedule>>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            path: None,
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Loads schedules from `path` (a missing file means none yet). Runs missed
+    // while the process was down are skipped, as with cron.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let raw = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("schedule: read {}: {e}", path.display())),
+        };
+        let now = SystemTime::now();
+        let mut entries = Vec::new();
+        for (n, line) in raw.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let f = decode_fields(line);
+            let field = |k: &str| f.get(k).cloned().unwrap_or_default();
+            let (id, spec, task_name, channel_id) =
+                (field("id"), field("cron"), field("task"), field("channel"));
+            if id.is_empty() || task_name.is_empty() || channel_id.is_empty() {
+                return Err(format!(
+                    "schedule: {}:{}: id, cron, task and channel are required",
+                    path.display(),
+                    n + 1
+                ));
+            }
+            let cron = Cron::parse(&spec)
+                .map_err(|e| format!("schedule: {}:{}: {e}", path.display(), n + 1))?;
+            entries.push(Schedule {
+                id,
+                next: cron.next_after(now),
+                spec,
+                task_name,
+                text: field("text"),
+                channel_id,
+                source: field("source"),
+                user_id: field("user"),
+                cron,
+            });
+        }
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            entries: Mutex::new(entries),
+        })
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        self.path.is_some()
+    }
+
+    pub fn add(
+        &self,
+        spec: &str,
+        task_name: &str,
+        text: &str,
+        channel_id: &str,
+        source: &str,
+        user_id: &str,
+    ) -> Result<Schedule, String> {
+        let cron = Cron::parse(spec)?;
+        let next = cron.next_after(SystemTime::now());
+        if next.is_none() {
+            return Err(format!("cron expression never fires: {spec}"));
+        }
+        let mut g = self
+            .entries
+            .lock()
+            .map_err(|_| "scheduler unavailable".to_string())?;
+        let id = g
+            .iter()
+            .filter_map(|s| s.id.parse::<u64>().ok())
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let s = Schedule {
+            id: id.to_string(),
+            spec: spec.trim().to_string(),
+            task_name: task_name.to_string(),
+            text: text.to_string(),
+            channel_id: channel_id.to_string(),
+            source: source.to_string(),
+            user_id: user_id.to_string(),
+            next,
+            cron,
+        };
+        self.append(&s)?;
+        g.push(s.clone());
+        Ok(s)
+    }
+
+    // Schedules that deliver to `channel_id` on `source`.
+    pub fn list(&self, source: &str, channel_id: &str) -> Vec<Schedule> {
+        let Ok(g) = self.entries.lock() else {
+            return Vec::new();
+        };
+        g.iter()
+            .filter(|s| s.delivers_to(source, channel_id))
+            .cloned()
+            .collect()
+    }
+
+    // Removes a schedule of this chat. With an `owner`, only a schedule that
+    // user added may be removed.
+    pub fn remove(
+        &self,
+        source: &str,
+        channel_id: &str,
+        id: &str,
+        owner: Option<&str>,
+    ) -> Result<Schedule, String> {
+        let mut g = self
+            .entries
+            .lock()
+            .map_err(|_| "scheduler unavailable".to_string())?;
+        let idx = g
+            .iter()
+            .position(|s| s.id == id && s.delivers_to(source, channel_id))
+            .ok_or_else(|| format!("no schedule {id} in this chat"))?;
+        if owner.is_some_and(|u| g[idx].user_id != u) {
+            return Err(format!("schedule {id} was added by someone else"));
+        }
+        self.delete_line(id)?;
+        Ok(g.remove(idx))
+    }
+
+    // Returns the schedules due at `now` and moves each to its next run.
+    pub fn due(&self, now: SystemTime) -> Vec<Schedule> {
+        let Ok(mut g) = self.entries.lock() else {
+            return Vec::new();
+        };
+        let mut due = Vec::new();
+        for s in g.iter_mut() {
+            if s.next.is_some_and(|t| t <= now) {
+                due.push(s.clone());
+                s.next = s.cron.next_after(now);
+            }
+        }
+        due
+    }
+
+    fn append(&self, s: &Schedule) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut fields = vec![
+            ("id", s.id.clone()),
+            ("cron", s.spec.clone()),
+            ("task", s.task_name.clone()),
+            ("channel", s.channel_id.clone()),
+        ];
+        if !s.source.is_empty() {
+            fields.push(("source", s.source.clone()));
+        }
+        if !s.user_id.is_empty() {
+            fields.push(("user", s.user_id.clone()));
+        }
+        if !s.text.is_empty() {
+            fields.push(("text", s.text.clone()));
+        }
+        // Hand-written files may not end with a newline.
+        let sep = match fs::read(path) {
+            Ok(b) if b.last().is_some_and(|c| *c != b'\n') => "\n",
+            _ => "",
+        };
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("schedule: open {}: {e}", path.display()))?;
+        writeln!(f, "{sep}{}", encode_fields(&fields))
+            .map_err(|e| format!("schedule: write {}: {e}", path.display()))?;
+        f.sync_data()
+            .map_err(|e| format!("schedule: sync {}: {e}", path.display()))
+    }
+
+    // Rewrites the file without the line of schedule `id`.
+    fn delete_line(&self, id: &str) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let raw = fs::read_to_string(path)
+            .map_err(|e| format!("schedule: read {}: {e}", path.display()))?;
+        let tmp = path.with_extension("tmp");
+        {
+            let mut f = File::create(&tmp)
+                .map_err(|e| format!("schedule: create {}: {e}", tmp.display()))?;
+            for line in raw.lines() {
+                let l = line.trim();
+                if !l.is_empty()
+                    && !l.starts_with('#')
+                    && decode_fields(l).get("id").is_some_and(|v| v == id)
+                {
+                    continue;
+                }
+                writeln!(f, "{line}")
+                    .map_err(|e| format!("schedule: write {}: {e}", tmp.display()))?;
+            }
+            f.sync_all()
+                .map_err(|e| format!("schedule: sync {}: {e}", tmp.display()))?;
+        }
+        fs::rename(&tmp, path).map_err(|e| format!("schedule: rename {}: {e}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    // These assume `CRABPLANE_UTC_OFFSET` is unset, i.e. local time is UTC.
+    fn next(spec: &str, after: u64) -> Option<SystemTime> {
+        Cron::parse(spec).unwrap().next_after(at(after))
+    }
+
+    #[test]
+    fn rejects_bad_expressions() {
+        for spec in [
+            "* * * *",
+            "61 * * * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "* * * foo *",
+        ] {
+            assert!(Cron::parse(spec).is_err(), "{spec}");
+        }
+    }
+
+    #[test]
+    fn finds_the_next_run() {
+        // Saturday 2026-10-17 10:07 UTC.
+        let sat = 1_792_231_620;
+        assert_eq!(next("*/15 * * * *", sat), Some(at(sat + 8 * 60)));
+        // Monday 2026-10-19 09:00.
+        assert_eq!(next("0 9 * * mon-fri", sat), Some(at(1_792_400_400)));
+        assert_eq!(next("0 9 * * 1-5", sat), Some(at(1_792_400_400)));
+        // Sunday 2026-10-18 00:00; 7 is Sunday too.
+        assert_eq!(next("@weekly", sat), next("0 0 * * 7", sat));
+        assert_eq!(next("@weekly", sat), Some(at(1_792_281_600)));
+        // Strictly after: a run due right now is the next one's problem.
+        assert_eq!(next("7 10 * * *", sat), Some(at(sat + 86_400)));
+    }
+
+    #[test]
+    fn either_day_field_may_match() {
+        // From Tuesday 2026-10-20, Monday the 26th comes before the 1st.
+        assert_eq!(next("0 0 1 * mon", 1_792_490_400), Some(at(1_792_972_800)));
+        // With only the day of month restricted, it alone decides.
+        assert_eq!(next("0 0 1 * *", 1_792_490_400), Some(at(1_793_491_200)));
+        assert_eq!(next("0 0 30 2 *", 1_792_490_400), None);
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::error::Error;
+use crate::journal::{decode_fields, encode_fields};
+
+const LOG_FILE: &str = "state.log";
+
+// The log is compacted again once it holds this many records and more than
+// twice as many as there are values.
+const COMPACT_AFTER: usize = 1000;
+
+type Values = HashMap<(String, String), String>;
+
+// StateStore holds the key-value state tasks keep per user and per channel,
+// keyed by (scope, key). With a directory it is backed by an append-only log
+// (`S` sets a value, `X` deletes one) compacted on open, like the dead
+// letters, and again as it grows; otherwise values only live in memory.
+pub struct StateStore {
+    log: Option<(PathBuf, Mutex<LogFile>)>,
+    values: Mutex<Values>,
+}
+
+struct LogFile {
+    file: File,
+    records: usize,
+}
+
+impl StateStore {
+    pub fn new() -> Self {
+        Self {
+            log: None,
+            values: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn open(dir: &Path) -> Result<Self, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("state: create dir {}: {e}", dir.display()))?;
+        let path = dir.join(LOG_FILE);
+        let values = if path.exists() {
+            replay(&path)?
+        } else {
+            HashMap::new()
+        };
+
+        let file = rewrite(&path, &values)?;
+        let records = values.len();
+
+        Ok(Self {
+            log: Some((path, Mutex::new(LogFile { file, records }))),
+            values: Mutex::new(values),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.lock().map(|g| g.len()).unwrap_or(0)
+    }
+
+    fn get(&self, scope: &str, key: &str) -> Option<String> {
+        let g = self.values.lock().ok()?;
+        g.get(&(scope.to_string(), key.to_string())).cloned()
+    }
+
+    // An empty value deletes the key.
+    fn set(&self, scope: &str, key: &str, value: &str) -> Result<(), String> {
+        let record = if value.is_empty() {
+            format!("X\t{}", encode(scope, key, ""))
+        } else {
+            format!("S\t{}", encode(scope, key, value))
+        };
+        // The log stays locked until the value is updated, so a compaction
+        // never misses a record.
+        let mut log = match &self.log {
+            Some((path, file)) => {
+                let f = file
+                    .lock()
+                    .map_err(|_| "state: poisoned lock".to_string())?;
+                Some((path, f))
+            }
+            None => None,
+        };
+        if let Some((path, f)) = &mut log {
+            writeln!(f.file, "{record}")
+                .map_err(|e| format!("state: write {}: {e}", path.display()))?;
+            f.file
+                .sync_data()
+                .map_err(|e| format!("state: sync {}: {e}", path.display()))?;
+            f.records += 1;
+        }
+        let mut g = self
+            .values
+            .lock()
+            .map_err(|_| "state unavailable".to_string())?;
+        let k = (scope.to_string(), key.to_string());
+        if value.is_empty() {
+            g.remove(&k);
+        } else {
+            g.insert(k, value.to_string());
+        }
+        if let Some((path, f)) = &mut log
+            && f.records >= COMPACT_AFTER
+            && f.records > 2 * g.len()
+        {
+            f.file = rewrite(path, &g)?;
+            f.records = g.len();
+        }
+        Ok(())
+    }
+}
+
+// Rewrites the log at `path` as one `S` record per value, swaps it in and
+// returns it opened for appending.
+fn rewrite(path: &Path, values: &Values) -> Result<File, String> {
+    let tmp = path.with_extension("log.tmp");
+    {
+        let mut f =
+            File::create(&tmp).map_err(|e| format!("state: create {}: {e}", tmp.display()))?;
+        for ((scope, key), value) in values {
+            writeln!(f, "S\t{}", encode(scope, key, value))
+                .map_err(|e| format!("state: write {}: {e}", tmp.display()))?;
+        }
+        f.sync_all()
+            .map_err(|e| format!("state: sync {}: {e}", tmp.display()))?;
+    }
+    fs::rename(&tmp, path).map_err(|e| format!("state: rename {}: {e}", path.display()))?;
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("state: open {}: {e}", path.display()))
+}
+
+// State is a task's handle on one scope of the store: one user's values or
+// one channel's. Users and channels of different adapters never share one.
+#[derive(Clone)]
+pub struct State {
+    store: Arc<StateStore>,
+    scope: String,
+}
+
+impl State {
+    pub fn user(store: &Arc<StateStore>, source: &str, user_id: &str) -> Self {
+        Self {
+            store: Arc::clone(store),
+            scope: format!("user:{source}:{user_id}"),
+        }
+    }
+
+    pub fn channel(store: &Arc<StateStore>, source: &str, channel_id: &str) -> Self {
+        Self {
+            store: Arc::clone(store),
+            scope: format!("channel:{source}:{channel_id}"),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.store.get(&self.scope, key)
+    }
+
+    // Setting an empty value removes the key.
+    pub fn set(&self, key: &str, value: &str) -> Result<(), Error> {
+        self.store
+            .set(&self.scope, key, value)
+            .map_err(Error::internal)
+    }
+}
+
+fn replay(path: &Path) -> Result<Values, String> {
+    let f = File::open(path).map_err(|e| format!("state: open {}: {e}", path.display()))?;
+    let mut values = HashMap::new();
+    for line in BufReader::new(f).lines() {
+        let line = line.map_err(|e| format!("state: read {}: {e}", path.display()))?;
+        let (set, rest) = match line.split_once('\t') {
+            Some(("S", rest)) => (true, rest),
+            Some(("X", rest)) => (false, rest),
+            _ => continue,
+        };
+        let mut f = decode_fields(rest);
+        let (Some(scope), Some(key)) = (f.remove("scope"), f.remove("key")) else {
+            continue;
+        };
+        match f.remove("value") {
+            Some(v) if set => values.insert((scope, key), v),
+            _ => values.remove(&(scope, key)),
+        };
+    }
+    Ok(values)
+}
+
+fn encode(scope: &str, key: &str, value: &str) -> String {
+    encode_fields(&[
+        ("scope", scope.to_string()),
+        ("key", key.to_string()),
+        ("value", value.to_string()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("crabplane-state-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn set_get_delete_in_memory() {
+        let store = Arc::new(StateStore::new());
+        let alice = State::user(&store, "telegram", "1");
+        let chat = State::channel(&store, "telegram", "1");
+        alice.set("k", "v").unwrap();
+        assert_eq!(alice.get("k").as_deref(), Some("v"));
+        assert_eq!(chat.get("k"), None);
+        alice.set("k", "").unwrap();
+        assert_eq!(alice.get("k"), None);
+        assert_eq!(store.len(), 0);
+    }
+
+    #[test]
+    fn values_survive_a_reopen() {
+        let dir = temp_dir("replay");
+        {
+            let store = Arc::new(StateStore::open(&dir).unwrap());
+            let s = State::user(&store, "cli", "me");
+            s.set("keep", "a\tb\nc").unwrap();
+            s.set("gone", "x").unwrap();
+            s.set("gone", "").unwrap();
+        }
+        let store = Arc::new(StateStore::open(&dir).unwrap());
+        let s = State::user(&store, "cli", "me");
+        assert_eq!(s.get("keep").as_deref(), Some("a\tb\nc"));
+        assert_eq!(s.get("gone"), None);
+        assert_eq!(store.len(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn the_log_is_compacted_as_it_grows() {
+        let dir = temp_dir("compact");
+        let store = Arc::new(StateStore::open(&dir).unwrap());
+        let s = State::channel(&store, "cli", "c");
+        for i in 0..COMPACT_AFTER + 10 {
+            s.set("n", &i.to_string()).unwrap();
+        }
+        let log = fs::read_to_string(dir.join(LOG_FILE)).unwrap();
+        let lines = log.lines().count();
+        assert!(lines < COMPACT_AFTER, "{lines} records after compaction");
+        drop(store);
+        let store = Arc::new(StateStore::open(&dir).unwrap());
+        let last = (COMPACT_AFTER + 9).to_string();
+        assert_eq!(State::channel(&store, "cli", "c").get("n"), Some(last));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
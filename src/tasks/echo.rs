@@ -1,5 +1,6 @@
-use crate::tasks::{Task, TaskContext, TaskOutput};
-use crate::types::TaskInput;
+use crate::error::Error;
+use crate::tasks::{Rich, Task, TaskContext, TaskOutput};
+use crate::types::{FileBody, OutFile, TaskInput};
 
 #[derive(Default)]
 pub struct EchoTask;
@@ -17,16 +18,34 @@ impl Task for EchoTask {
 
     fn validate(&self, input: &TaskInput) -> Result<(), String> {
         match input {
-            TaskInput::Text(t) if !t.is_empty() => Ok(()),
-            TaskInput::Text(_) => Err("text is empty".to_string()),
-            _ => Err("invalid input".to_string()),
+            TaskInput::Empty => Err("invalid input".to_string()),
+            _ if input.text().is_empty() => Err("text is empty".to_string()),
+            _ => Ok(()),
         }
     }
 
-    fn run(&self, _ctx: &TaskContext, input: TaskInput) -> Result<TaskOutput, String> {
-        match input {
-            TaskInput::Text(t) => Ok(TaskOutput::Text(t)),
-            _ => Err("invalid input".to_string()),
+    // Echoes the text as typed, quotes and all, and sends attachments back.
+    fn run(&self, _ctx: &TaskContext, input: TaskInput) -> Result<TaskOutput, Error> {
+        let text = input.text().to_string();
+        let files: Vec<OutFile> = match &input {
+            TaskInput::Args(a) => a
+                .attachments
+                .iter()
+                .map(|f| OutFile {
+                    name: f.name.clone(),
+                    mime: f.mime.clone(),
+                    body: FileBody::Url(f.url.clone()),
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        if files.is_empty() {
+            return Ok(TaskOutput::Text(text));
         }
+        Ok(TaskOutput::Rich(Rich {
+            text,
+            files,
+            ..Rich::default()
+        }))
     }
 }
@@ -2,24 +2,266 @@ mod echo;
 mod onboarding;
 mod openai;
 mod ping;
+mod process;
+mod remind;
 
-use crate::types::TaskInput;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::state::State;
+use crate::types::{Button, META_DISPLAY_NAME, META_USERNAME, OutFile, Priority, Role, TaskInput};
 
 pub use echo::EchoTask;
 pub use onboarding::OnboardingTask;
 pub use openai::OpenAiTask;
 pub use ping::PingTask;
+pub use remind::RemindTask;
 
 #[derive(Clone, Debug)]
 pub enum TaskOutput {
     None,
     Text(String),
+    // Formatted text with files and buttons.
+    Rich(Rich),
+    // Partial output of a job that is still running (see `TaskContext::emit`).
+    // Sinks append it to what they already showed; the final result follows.
+    Chunk(String),
+    // An interim note about a running job (see `TaskContext::progress`).
+    Progress(String),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Rich {
+    pub text: String,
+    // `text` uses the Markdown subset described in `markdown`.
+    pub markdown: bool,
+    pub files: Vec<OutFile>,
+    pub buttons: Vec<Button>,
+}
+
+impl Rich {
+    pub fn markdown(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            markdown: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn button(mut self, label: &str, command: &str) -> Self {
+        self.buttons.push(Button {
+            label: label.to_string(),
+            command: command.to_string(),
+        });
+        self
+    }
+}
+
+// Why a job was told to stop.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CancelReason {
+    User = 1,
+    Timeout = 2,
+    Shutdown = 3,
+}
+
+// CancelToken is set when a running job must stop early (user request,
+// timeout, shutdown). Long-running tasks should poll it and return early;
+// child processes started through `process::output` are killed automatically.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicU8>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.cancel_with(CancelReason::User);
+    }
+
+    // The first reason wins; later calls are no-ops.
+    pub fn cancel_with(&self, reason: CancelReason) {
+        let _ = self
+            .0
+            .compare_exchange(0, reason as u8, Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    pub fn is_canceled(&self) -> bool {
+        self.0.load(Ordering::Relaxed) != 0
+    }
+
+    pub fn reason(&self) -> Option<CancelReason> {
+        match self.0.load(Ordering::Relaxed) {
+            1 => Some(CancelReason::User),
+            2 => Some(CancelReason::Timeout),
+            3 => Some(CancelReason::Shutdown),
+            _ => None,
+        }
+    }
+}
+
+// Config is the read-only configuration tasks see, a snapshot of the
+// environment taken at startup. Tasks read it instead of `std::env`, so a test
+// can hand them exactly the settings it wants.
+#[derive(Clone, Debug, Default)]
+pub struct Config(Arc<HashMap<String, String>>);
+
+impl Config {
+    pub fn new(vars: HashMap<String, String>) -> Self {
+        Self(Arc::new(vars))
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(env::vars().collect())
+    }
+
+    // The trimmed value; None when unset or blank.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|v| v.trim()).filter(|v| !v.is_empty())
+    }
+
+    pub fn get_or<'a>(&'a self, key: &str, default: &'a str) -> &'a str {
+        self.get(key).unwrap_or(default)
+    }
+
+    pub fn is_set(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
 }
 
-pub struct TaskContext;
+// TaskContext is everything a running task gets besides its input: who asked
+// and where, configuration, state that outlives the job, and ways to report
+// progress and notice cancellation.
+#[derive(Clone)]
+pub struct TaskContext {
+    pub job_id: String,
+    pub user_id: String,
+    pub channel_id: String,
+    // Adapter the request came from, e.g. "telegram".
+    pub source: String,
+    // 1 on the first run, counting up on retries.
+    pub attempt: u32,
+    // The job's `metadata` (see the `META_*` keys).
+    pub metadata: HashMap<String, String>,
+    pub config: Config,
+    // Values kept for this user, or for this channel, across jobs.
+    pub user_state: State,
+    pub channel_state: State,
+    pub cancel: CancelToken,
+    pub stream: Stream,
+}
+
+impl TaskContext {
+    // How the user would like to be addressed, if the platform said.
+    pub fn user_name(&self) -> Option<&str> {
+        [META_DISPLAY_NAME, META_USERNAME]
+            .iter()
+            .filter_map(|k| self.metadata.get(*k))
+            .map(|v| v.trim())
+            .find(|v| !v.is_empty())
+    }
+
+    // Pushes partial output to the user while the task keeps running. The
+    // task must still return its complete output at the end.
+    pub fn emit(&self, chunk: &str) {
+        if !chunk.is_empty() && !self.cancel.is_canceled() {
+            self.stream.send(TaskOutput::Chunk(chunk.to_string()));
+        }
+    }
+
+    // Sends a short interim message ("fetching 3 files...") while the task
+    // keeps running. Unlike `emit` it is not part of the result; chats that
+    // can delete messages remove it once the result is out.
+    pub fn progress(&self, note: &str) {
+        if !note.trim().is_empty() && !self.cancel.is_canceled() {
+            self.stream.send(TaskOutput::Progress(note.trim().to_string()));
+        }
+    }
+}
+
+// Stream forwards emitted chunks and progress notes to the result
+// dispatcher. The default one drops them.
+#[derive(Clone, Default)]
+pub struct Stream(Option<Arc<OutputFn>>);
+
+type OutputFn = dyn Fn(TaskOutput) + Send + Sync;
+
+impl Stream {
+    pub fn new(f: impl Fn(TaskOutput) + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(f)))
+    }
+
+    fn send(&self, out: TaskOutput) {
+        if let Some(f) = &self.0 {
+            f(out);
+        }
+    }
+}
+
+// RetryPolicy tells the worker pool how to re-run a failed job: up to
+// `max_attempts` runs in total, waiting `base_delay * 2^n` (capped at
+// `max_delay`) between them, and only for errors `retryable` accepts.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub retryable: fn(&Error) -> bool,
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            retryable: |_| false,
+        }
+    }
+
+    // Delay before running attempt `attempt + 1`, after `attempt` failures.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32 << attempt.saturating_sub(1).min(16);
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
 
 pub trait Task: Send + Sync {
     fn name(&self) -> &'static str;
+    // The message is shown to the user as is (`Kind::UserInput`).
     fn validate(&self, input: &TaskInput) -> Result<(), String>;
-    fn run(&self, ctx: &TaskContext, input: TaskInput) -> Result<TaskOutput, String>;
+    fn run(&self, ctx: &TaskContext, input: TaskInput) -> Result<TaskOutput, Error>;
+
+    // Lane used when the router does not pick one for the job.
+    fn priority(&self) -> Priority {
+        Priority::Normal
+    }
+
+    // Max jobs of this task running at once; 0 means no limit.
+    fn max_concurrency(&self) -> usize {
+        0
+    }
+
+    // Named worker group that runs this task exclusively; None runs it on the
+    // shared workers.
+    fn worker_group(&self) -> Option<&'static str> {
+        None
+    }
+
+    // Wall-clock limit for one run; the job is canceled and reported as
+    // failed once it is exceeded. None means no limit.
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::none()
+    }
+
+    // Least role allowed to run the task when access control is on;
+    // `CRABPLANE_TASK_ROLES` overrides it.
+    fn role(&self) -> Role {
+        Role::Member
+    }
 }
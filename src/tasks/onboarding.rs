@@ -1,7 +1,10 @@
-use std::env;
+use crate::error::Error;
+use crate::state::State;
+use crate::tasks::{Config, Rich, Task, TaskContext, TaskOutput};
+use crate::types::{META_CHAT_TYPE, Priority, TaskInput};
 
-use crate::tasks::{Task, TaskContext, TaskOutput};
-use crate::types::TaskInput;
+// The section last asked for, shown again by a bare `!onboard`.
+const SCOPE_KEY: &str = "onboard.scope";
 
 #[derive(Default)]
 pub struct OnboardingTask;
@@ -18,50 +21,110 @@ impl Task for OnboardingTask {
     }
 
     fn validate(&self, input: &TaskInput) -> Result<(), String> {
-        match input {
-            TaskInput::Empty => Ok(()),
-            TaskInput::Text(_) => Ok(()),
-        }
+        scope_of(input).map(|_| ())
     }
 
-    fn run(&self, _ctx: &TaskContext, input: TaskInput) -> Result<TaskOutput, String> {
-        let scope = match input {
-            TaskInput::Empty => String::new(),
-            TaskInput::Text(t) => t.trim().to_ascii_lowercase(),
+    fn run(&self, ctx: &TaskContext, input: TaskInput) -> Result<TaskOutput, Error> {
+        let state = scope_state(ctx);
+        let scope = match scope_of(&input).map_err(Error::user)? {
+            Some(scope) => {
+                // Losing the choice only costs the shortcut, so a failed
+                // write doesn't fail the checklist.
+                let _ = state.set(SCOPE_KEY, scope.as_str());
+                scope
+            }
+            None => state
+                .get(SCOPE_KEY)
+                .and_then(|s| parse_scope(&s))
+                .unwrap_or(Scope::All),
         };
-
-        let include_chat = scope.is_empty()
-            || scope == "all"
-            || scope.contains("chat")
-            || scope.contains("tool");
-        let include_ai =
-            scope.is_empty() || scope == "all" || scope.contains("ai") || scope.contains("provider");
-
-        if !scope.is_empty() && !include_chat && !include_ai {
-            return Err("usage: !onboard [chat|ai|all]".to_string());
-        }
+        let include_chat = matches!(scope, Scope::All | Scope::Chat);
+        let include_ai = matches!(scope, Scope::All | Scope::Ai);
 
         let mut lines = vec![
-            "Crabplane onboarding".to_string(),
-            "Use `!onboard chat`, `!onboard ai`, or `!onboard all`.".to_string(),
+            "# Crabplane onboarding".to_string(),
+            "Use `!onboard chat`, `!onboard ai`, or `!onboard all`; `!onboard` alone shows the last one picked.".to_string(),
         ];
 
         if include_chat {
-            append_chat_section(&mut lines);
+            append_chat_section(&mut lines, &ctx.config);
         }
         if include_ai {
-            append_ai_section(&mut lines);
+            append_ai_section(&mut lines, &ctx.config);
+        }
+
+        let mut out = Rich::markdown(lines.join("\n"));
+        if matches!(scope, Scope::All) {
+            out = out
+                .button("Chat tools", "!onboard chat")
+                .button("AI providers", "!onboard ai");
+        }
+        Ok(TaskOutput::Rich(out))
+    }
+
+    fn priority(&self) -> Priority {
+        Priority::High
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Scope {
+    All,
+    Chat,
+    Ai,
+}
+
+impl Scope {
+    fn as_str(self) -> &'static str {
+        match self {
+            Scope::All => "all",
+            Scope::Chat => "chat",
+            Scope::Ai => "ai",
+        }
+    }
+}
+
+// `!onboard [chat|ai|all]`; "tools" and "providers" are accepted as aliases.
+// None: no section was named.
+fn scope_of(input: &TaskInput) -> Result<Option<Scope>, String> {
+    let usage = || "usage: !onboard [chat|ai|all]".to_string();
+    let scope = match input {
+        TaskInput::Empty => return Ok(None),
+        TaskInput::Text(t) => t.trim().to_ascii_lowercase(),
+        TaskInput::Args(a) => {
+            a.check(1, &[]).map_err(|e| format!("{e}\n{}", usage()))?;
+            a.get(0).unwrap_or_default().to_ascii_lowercase()
         }
+    };
+    if scope.is_empty() {
+        return Ok(None);
+    }
+    parse_scope(&scope).map(Some).ok_or_else(usage)
+}
 
-        Ok(TaskOutput::Text(lines.join("\n")))
+fn parse_scope(s: &str) -> Option<Scope> {
+    match s {
+        "all" => Some(Scope::All),
+        "chat" | "tools" | "tool" => Some(Scope::Chat),
+        "ai" | "providers" | "provider" => Some(Scope::Ai),
+        _ => None,
     }
 }
 
-fn append_chat_section(lines: &mut Vec<String>) {
+// In a group the section is remembered for the chat, since everyone there
+// is setting up the same bot; elsewhere for the user.
+fn scope_state(ctx: &TaskContext) -> &State {
+    match ctx.metadata.get(META_CHAT_TYPE).map(String::as_str) {
+        None | Some("private") => &ctx.user_state,
+        Some(_) => &ctx.channel_state,
+    }
+}
+
+fn append_chat_section(lines: &mut Vec<String>, cfg: &Config) {
     lines.push(String::new());
-    lines.push("Chat tools".to_string());
+    lines.push("## Chat tools".to_string());
 
-    if env_set("DISCORD_TOKEN") {
+    if cfg.is_set("DISCORD_TOKEN") {
         lines.push("- discord: configured (`DISCORD_TOKEN` set)".to_string());
     } else {
         lines.push(
@@ -69,7 +132,7 @@ fn append_chat_section(lines: &mut Vec<String>) {
         );
     }
 
-    if env_set("TELEGRAM_BOT_TOKEN") {
+    if cfg.is_set("TELEGRAM_BOT_TOKEN") {
         lines.push("- telegram: configured (`TELEGRAM_BOT_TOKEN` set)".to_string());
     } else {
         lines.push(
@@ -77,8 +140,9 @@ fn append_chat_section(lines: &mut Vec<String>) {
         );
     }
 
-    let wa_ready =
-        env_set("TWILIO_ACCOUNT_SID") && env_set("TWILIO_AUTH_TOKEN") && env_set("TWILIO_WHATSAPP_NUMBER");
+    let wa_ready = cfg.is_set("TWILIO_ACCOUNT_SID")
+        && cfg.is_set("TWILIO_AUTH_TOKEN")
+        && cfg.is_set("TWILIO_WHATSAPP_NUMBER");
     if wa_ready {
         lines.push(
             "- whatsapp: configured (`TWILIO_ACCOUNT_SID`, `TWILIO_AUTH_TOKEN`, `TWILIO_WHATSAPP_NUMBER` set)"
@@ -86,13 +150,13 @@ fn append_chat_section(lines: &mut Vec<String>) {
         );
     } else {
         let mut missing = Vec::new();
-        if !env_set("TWILIO_ACCOUNT_SID") {
+        if !cfg.is_set("TWILIO_ACCOUNT_SID") {
             missing.push("TWILIO_ACCOUNT_SID");
         }
-        if !env_set("TWILIO_AUTH_TOKEN") {
+        if !cfg.is_set("TWILIO_AUTH_TOKEN") {
             missing.push("TWILIO_AUTH_TOKEN");
         }
-        if !env_set("TWILIO_WHATSAPP_NUMBER") {
+        if !cfg.is_set("TWILIO_WHATSAPP_NUMBER") {
             missing.push("TWILIO_WHATSAPP_NUMBER");
         }
         lines.push(format!(
@@ -102,51 +166,62 @@ fn append_chat_section(lines: &mut Vec<String>) {
     }
 }
 
-fn append_ai_section(lines: &mut Vec<String>) {
+fn append_ai_section(lines: &mut Vec<String>, cfg: &Config) {
     lines.push(String::new());
-    lines.push("AI providers".to_string());
+    lines.push("## AI providers".to_string());
 
-    let backend = env::var("CRABPLANE_AI_BACKEND").unwrap_or_else(|_| "codex".to_string());
-    let selected = backend.trim().to_ascii_lowercase();
-    lines.push(format!("- selected backend: `{}`", backend.trim()));
+    let backend = cfg.get_or("CRABPLANE_AI_BACKEND", "codex");
+    let selected = backend.to_ascii_lowercase();
+    lines.push(format!("- selected backend: `{backend}`"));
     lines.push("- supported backends: `openai`, `openai-codex-api`, `anthropic`, `codex`, `claude-code`".to_string());
 
     match selected.as_str() {
         "openai" => {
-            lines.push(req_line("OPENAI_API_KEY", "required for OpenAI Responses API"));
+            lines.push(req_line(
+                cfg,
+                "OPENAI_API_KEY",
+                "required for OpenAI Responses API",
+            ));
             lines.push(opt_line(
+                cfg,
                 "OPENAI_MODEL",
                 "optional model override (default: `gpt-5.3-codex`)",
             ));
         }
         "openai-codex-api" | "openai_codex_api" | "codex-api" | "codex_api" => {
             lines.push(req_line(
+                cfg,
                 "OPENAI_API_KEY",
                 "required for OpenAI Codex API backend",
             ));
             lines.push(opt_line(
+                cfg,
                 "OPENAI_CODEX_MODEL",
                 "optional model override (default: `gpt-5.3-codex`)",
             ));
         }
         "anthropic" | "claude-api" | "claude_api" => {
             lines.push(req_line(
+                cfg,
                 "ANTHROPIC_API_KEY",
                 "required for Anthropic Messages API",
             ));
             lines.push(opt_line(
+                cfg,
                 "ANTHROPIC_MODEL",
                 "optional model override (default: `claude-3-5-sonnet-latest`)",
             ));
         }
         "codex" => {
             lines.push(opt_line(
+                cfg,
                 "CRABPLANE_CODEX_CMD",
                 "optional codex CLI command (default: `codex exec --skip-git-repo-check`)",
             ));
         }
         "claude-code" | "claude_code" => {
             lines.push(opt_line(
+                cfg,
                 "CRABPLANE_CLAUDE_CODE_CMD",
                 "optional claude CLI command (default: `claude -p`)",
             ));
@@ -160,25 +235,83 @@ fn append_ai_section(lines: &mut Vec<String>) {
     }
 }
 
-fn req_line(key: &str, detail: &str) -> String {
-    if env_set(key) {
+fn req_line(cfg: &Config, key: &str, detail: &str) -> String {
+    if cfg.is_set(key) {
         format!("- `{key}`: configured ({detail})")
     } else {
         format!("- `{key}`: missing ({detail})")
     }
 }
 
-fn opt_line(key: &str, detail: &str) -> String {
-    if env_set(key) {
+fn opt_line(cfg: &Config, key: &str, detail: &str) -> String {
+    if cfg.is_set(key) {
         format!("- `{key}`: set ({detail})")
     } else {
         format!("- `{key}`: not set ({detail})")
     }
 }
 
-fn env_set(key: &str) -> bool {
-    env::var(key)
-        .ok()
-        .map(|v| !v.trim().is_empty())
-        .unwrap_or(false)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::parse_args;
+    use crate::state::StateStore;
+    use crate::tasks::{CancelToken, Stream};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn ctx(store: &Arc<StateStore>, user: &str, chat_type: &str) -> TaskContext {
+        TaskContext {
+            job_id: "j".to_string(),
+            user_id: user.to_string(),
+            channel_id: "group".to_string(),
+            source: "telegram".to_string(),
+            attempt: 1,
+            metadata: HashMap::from([(META_CHAT_TYPE.to_string(), chat_type.to_string())]),
+            config: Config::default(),
+            user_state: State::user(store, "telegram", user),
+            channel_state: State::channel(store, "telegram", "group"),
+            cancel: CancelToken::default(),
+            stream: Stream::default(),
+        }
+    }
+
+    fn args(s: &str) -> TaskInput {
+        TaskInput::Args(parse_args(s, Vec::new()))
+    }
+
+    fn shown(ctx: &TaskContext, input: TaskInput) -> String {
+        match OnboardingTask::new().run(ctx, input).unwrap() {
+            TaskOutput::Rich(r) => r.text,
+            _ => panic!("expected rich output"),
+        }
+    }
+
+    #[test]
+    fn bare_onboard_repeats_the_last_section() {
+        let store = Arc::new(StateStore::new());
+        let me = ctx(&store, "1", "private");
+        assert!(shown(&me, TaskInput::Empty).contains("## Chat tools"));
+        shown(&me, args("ai"));
+        let text = shown(&me, TaskInput::Empty);
+        assert!(text.contains("## AI providers") && !text.contains("## Chat tools"));
+        // Another user's direct chat is unaffected.
+        assert!(shown(&ctx(&store, "2", "private"), TaskInput::Empty).contains("## Chat tools"));
+    }
+
+    #[test]
+    fn groups_share_the_last_section() {
+        let store = Arc::new(StateStore::new());
+        shown(&ctx(&store, "1", "group"), args("chat"));
+        let text = shown(&ctx(&store, "2", "group"), TaskInput::Empty);
+        assert!(text.contains("## Chat tools") && !text.contains("## AI providers"));
+    }
+
+    #[test]
+    fn rejects_unknown_sections() {
+        let task = OnboardingTask::new();
+        assert!(task.validate(&args("ai")).is_ok());
+        assert!(task.validate(&args("nope")).is_err());
+        assert!(task.validate(&args("ai chat")).is_err());
+    }
 }
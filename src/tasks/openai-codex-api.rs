@@ -1,6 +1,10 @@
-pub(super) fn ask_openai_codex_api(prompt: &str) -> Result<String, String> {
+use crate::error::Error;
+use crate::tasks::TaskContext;
+
+pub(super) fn ask_openai_codex_api(prompt: &str, ctx: &TaskContext) -> Result<String, Error> {
     super::ask_openai_responses(
         prompt,
+        ctx,
         "OPENAI_CODEX_MODEL",
         "gpt-5.3-codex",
         "openai codex api",
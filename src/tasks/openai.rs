@@ -1,18 +1,31 @@
-use std::env;
 use std::process::Command;
+use std::time::Duration;
 
-use crate::tasks::{Task, TaskContext, TaskOutput};
-use crate::types::TaskInput;
+use crate::error::{Error, Kind};
+use crate::tasks::{Config, Rich, RetryPolicy, Task, TaskContext, TaskOutput, process};
+use crate::types::{FileBody, OutFile, TaskInput};
 
 #[path = "openai-codex-api.rs"]
 mod openai_codex_api;
 
-#[derive(Default)]
-pub struct OpenAiTask;
+// Longer answers are sent as `answer.md`, with their start as the message;
+// chats cap message length (Telegram at 4096 characters).
+const INLINE_ANSWER_LEN: usize = 3500;
+
+pub struct OpenAiTask {
+    // The backend is a local CLI (codex, claude-code) rather than an HTTP API.
+    local_cli: bool,
+}
 
 impl OpenAiTask {
-    pub fn new() -> Self {
-        Self
+    pub fn new(config: &Config) -> Self {
+        let backend = config.get_or("CRABPLANE_AI_BACKEND", "codex");
+        Self {
+            local_cli: matches!(
+                backend.to_ascii_lowercase().as_str(),
+                "codex" | "claude-code" | "claude_code"
+            ),
+        }
     }
 }
 
@@ -23,76 +36,147 @@ impl Task for OpenAiTask {
 
     fn validate(&self, input: &TaskInput) -> Result<(), String> {
         match input {
-            TaskInput::Text(t) if !t.trim().is_empty() => Ok(()),
-            TaskInput::Text(_) => Err("prompt is empty".to_string()),
-            _ => Err("invalid input".to_string()),
+            TaskInput::Empty => Err("invalid input".to_string()),
+            _ if input.text().trim().is_empty() => Err("prompt is empty".to_string()),
+            _ => Ok(()),
         }
     }
 
-    fn run(&self, _ctx: &TaskContext, input: TaskInput) -> Result<TaskOutput, String> {
-        let prompt = match input {
-            TaskInput::Text(t) => t,
-            _ => return Err("invalid input".to_string()),
-        };
+    // The prompt is the text as typed; flags are not interpreted. When the
+    // chat told us the user's name, the backend is asked to use it.
+    fn run(&self, ctx: &TaskContext, input: TaskInput) -> Result<TaskOutput, Error> {
+        if ctx.attempt > 1 {
+            ctx.progress(&format!(
+                "the AI backend failed; trying again (attempt {} of {})",
+                ctx.attempt,
+                self.retry_policy().max_attempts
+            ));
+        }
+        let mut prompt = String::new();
+        if let Some(name) = ctx.user_name() {
+            prompt.push_str(&format!(
+                "You are talking to {name}; address them by name where it fits.\n\n"
+            ));
+        }
+        prompt.push_str(input.text());
 
-        let backend = env::var("CRABPLANE_AI_BACKEND").unwrap_or_else(|_| "codex".to_string());
-        let out = match backend.trim().to_ascii_lowercase().as_str() {
-            "openai" => ask_openai_api(&prompt),
+        let backend = ctx.config.get_or("CRABPLANE_AI_BACKEND", "codex");
+        let out = match backend.to_ascii_lowercase().as_str() {
+            "openai" => ask_openai_api(&prompt, ctx),
             "openai-codex-api" | "openai_codex_api" | "codex-api" | "codex_api" => {
-                openai_codex_api::ask_openai_codex_api(&prompt)
+                openai_codex_api::ask_openai_codex_api(&prompt, ctx)
             }
-            "anthropic" | "claude-api" | "claude_api" => ask_anthropic_api(&prompt),
+            "anthropic" | "claude-api" | "claude_api" => ask_anthropic_api(&prompt, ctx),
             "codex" => ask_cli_backend(
                 &prompt,
+                ctx,
                 "CRABPLANE_CODEX_CMD",
                 "codex exec --skip-git-repo-check",
                 "codex",
             ),
             "claude-code" | "claude_code" => ask_cli_backend(
                 &prompt,
+                ctx,
                 "CRABPLANE_CLAUDE_CODE_CMD",
                 "claude -p",
                 "claude code",
             ),
-            other => Err(format!(
+            other => Err(Error::config(format!(
                 "unknown CRABPLANE_AI_BACKEND: {other} (expected: openai|openai-codex-api|anthropic|codex|claude-code)"
-            )),
+            ))),
         }?;
 
         let trimmed = out.trim();
         if trimmed.is_empty() {
-            return Err("backend returned empty output".to_string());
+            return Err(Error::upstream("backend returned empty output"));
+        }
+        if trimmed.chars().count() <= INLINE_ANSWER_LEN {
+            return Ok(TaskOutput::Rich(Rich::markdown(trimmed)));
+        }
+        let head: String = trimmed.chars().take(INLINE_ANSWER_LEN).collect();
+        let mut out = Rich::markdown(format!("{}...\n\n_(full answer attached)_", head.trim_end()));
+        out.files.push(OutFile {
+            name: "answer.md".to_string(),
+            mime: "text/markdown".to_string(),
+            body: FileBody::Bytes(trimmed.as_bytes().to_vec()),
+        });
+        Ok(TaskOutput::Rich(out))
+    }
+
+    // CLI backends are heavy local processes; keep room for the other tasks.
+    // The HTTP APIs are only limited by the workers.
+    fn max_concurrency(&self) -> usize {
+        if self.local_cli { 2 } else { 0 }
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        Some(Duration::from_secs(10 * 60))
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(30),
+            retryable: is_transient,
         }
-        Ok(TaskOutput::Text(trimmed.to_string()))
     }
 }
 
-fn ask_openai_api(prompt: &str) -> Result<String, String> {
-    ask_openai_responses(prompt, "OPENAI_MODEL", "gpt-5.3-codex", "openai")
+// Transient upstream failures: HTTP 429/5xx (via `--fail-with-body`) and
+// network-level curl errors (resolve, connect, timeout, TLS, empty reply, recv).
+fn is_transient(err: &Error) -> bool {
+    if err.kind != Kind::Upstream {
+        return false;
+    }
+    let err = err.msg.as_str();
+    if let Some(i) = err.find("returned error: ") {
+        let code = &err[i + "returned error: ".len()..];
+        return code.starts_with('5') || code.starts_with("429");
+    }
+    ["(6)", "(7)", "(28)", "(35)", "(52)", "(56)"]
+        .iter()
+        .any(|c| err.contains(&format!("curl: {c}")))
+}
+
+// A failed API request. A rejected key (401/403) is a configuration problem,
+// anything else an upstream one.
+fn request_failed(label: &str, stderr: &str) -> Error {
+    let msg = format!("{label} request failed: {}", stderr.trim());
+    if msg.contains("returned error: 401") || msg.contains("returned error: 403") {
+        Error::config(msg)
+    } else {
+        Error::upstream(msg)
+    }
+}
+
+fn ask_openai_api(prompt: &str, ctx: &TaskContext) -> Result<String, Error> {
+    ask_openai_responses(prompt, ctx, "OPENAI_MODEL", "gpt-5.3-codex", "openai")
 }
 
 fn ask_openai_responses(
     prompt: &str,
+    ctx: &TaskContext,
     model_env: &str,
     default_model: &str,
     label: &str,
-) -> Result<String, String> {
-    let api_key = env::var("OPENAI_API_KEY").unwrap_or_default();
-    if api_key.is_empty() {
-        return Err("OPENAI_API_KEY is empty".to_string());
-    }
-    let model = env::var(model_env).unwrap_or_else(|_| default_model.to_string());
+) -> Result<String, Error> {
+    let Some(api_key) = ctx.config.get("OPENAI_API_KEY") else {
+        return Err(Error::config("OPENAI_API_KEY is empty"));
+    };
+    let model = ctx.config.get_or(model_env, default_model);
 
     let body = format!(
         "{{\"model\":\"{}\",\"input\":\"{}\"}}",
-        escape_json(&model),
+        escape_json(model),
         escape_json(prompt)
     );
 
     let auth = format!("Authorization: Bearer {api_key}");
-    let out = Command::new("curl")
-        .args([
+    let out = process::output(
+        Command::new("curl").args([
             "-sS",
+            "--fail-with-body",
             "--max-time",
             "60",
             "https://api.openai.com/v1/responses",
@@ -102,37 +186,37 @@ fn ask_openai_responses(
             "Content-Type: application/json",
             "-d",
             &body,
-        ])
-        .output()
-        .map_err(|e| format!("failed to execute curl: {e}"))?;
+        ]),
+        &ctx.cancel,
+    )
+    .map_err(|e| Error::internal(format!("failed to execute curl: {e}")))?;
 
     if !out.status.success() {
-        let stderr = String::from_utf8_lossy(&out.stderr);
-        return Err(format!("{label} request failed: {}", stderr.trim()));
+        return Err(request_failed(label, &String::from_utf8_lossy(&out.stderr)));
     }
 
     let raw = String::from_utf8_lossy(&out.stdout);
     extract_first_text(&raw)
-        .ok_or_else(|| format!("{label} response did not include text output"))
+        .ok_or_else(|| Error::upstream(format!("{label} response did not include text output")))
 }
 
-fn ask_anthropic_api(prompt: &str) -> Result<String, String> {
-    let api_key = env::var("ANTHROPIC_API_KEY").unwrap_or_default();
-    if api_key.is_empty() {
-        return Err("ANTHROPIC_API_KEY is empty".to_string());
-    }
-    let model = env::var("ANTHROPIC_MODEL").unwrap_or_else(|_| "claude-3-5-sonnet-latest".to_string());
+fn ask_anthropic_api(prompt: &str, ctx: &TaskContext) -> Result<String, Error> {
+    let Some(api_key) = ctx.config.get("ANTHROPIC_API_KEY") else {
+        return Err(Error::config("ANTHROPIC_API_KEY is empty"));
+    };
+    let model = ctx.config.get_or("ANTHROPIC_MODEL", "claude-3-5-sonnet-latest");
 
     let body = format!(
         "{{\"model\":\"{}\",\"max_tokens\":1024,\"messages\":[{{\"role\":\"user\",\"content\":\"{}\"}}]}}",
-        escape_json(&model),
+        escape_json(model),
         escape_json(prompt)
     );
 
     let key_header = format!("x-api-key: {api_key}");
-    let out = Command::new("curl")
-        .args([
+    let out = process::output(
+        Command::new("curl").args([
             "-sS",
+            "--fail-with-body",
             "--max-time",
             "60",
             "https://api.anthropic.com/v1/messages",
@@ -144,29 +228,28 @@ fn ask_anthropic_api(prompt: &str) -> Result<String, String> {
             "content-type: application/json",
             "-d",
             &body,
-        ])
-        .output()
-        .map_err(|e| format!("failed to execute curl: {e}"))?;
+        ]),
+        &ctx.cancel,
+    )
+    .map_err(|e| Error::internal(format!("failed to execute curl: {e}")))?;
 
     if !out.status.success() {
-        let stderr = String::from_utf8_lossy(&out.stderr);
-        return Err(format!("claude request failed: {}", stderr.trim()));
+        return Err(request_failed("claude", &String::from_utf8_lossy(&out.stderr)));
     }
 
     let raw = String::from_utf8_lossy(&out.stdout);
-    extract_first_text(&raw).ok_or_else(|| "claude response did not include text output".to_string())
+    extract_first_text(&raw)
+        .ok_or_else(|| Error::upstream("claude response did not include text output"))
 }
 
 fn ask_cli_backend(
     prompt: &str,
+    ctx: &TaskContext,
     cmd_var: &str,
     default_cmd: &str,
     label: &str,
-) -> Result<String, String> {
-    let configured = env::var(cmd_var)
-        .ok()
-        .map(|v| v.trim().to_string())
-        .filter(|v| !v.is_empty());
+) -> Result<String, Error> {
+    let configured = ctx.config.get(cmd_var).map(str::to_string);
 
     let base_cmd = configured
         .as_ref()
@@ -179,7 +262,7 @@ fn ask_cli_backend(
 
     let mut last_not_found: Option<String> = None;
     for (idx, cmd) in attempts.iter().enumerate() {
-        match run_cli_command(prompt, cmd, label) {
+        match run_cli_command(prompt, ctx, cmd, label) {
             Ok(out) => return Ok(out),
             Err(err) => {
                 let has_next = idx + 1 < attempts.len();
@@ -187,28 +270,47 @@ fn ask_cli_backend(
                     last_not_found = Some(err.message);
                     continue;
                 }
-                return Err(err.message);
+                return Err(Error::new(err.kind, err.message));
             }
         }
     }
 
-    Err(last_not_found.unwrap_or_else(|| format!("{label} command failed")))
+    Err(match last_not_found {
+        Some(msg) => Error::config(msg),
+        None => Error::upstream(format!("{label} command failed")),
+    })
 }
 
 struct CliCommandError {
+    kind: Kind,
     message: String,
     stderr: String,
 }
 
-fn run_cli_command(prompt: &str, cmd: &str, label: &str) -> Result<String, CliCommandError> {
+// Stdout is streamed to the user line by line while the command runs.
+fn run_cli_command(
+    prompt: &str,
+    ctx: &TaskContext,
+    cmd: &str,
+    label: &str,
+) -> Result<String, CliCommandError> {
     let full = format!("{} '{}'", cmd, escape_single_quotes(prompt));
-    let out = Command::new("sh")
-        .args(["-lc", &full])
-        .output()
-        .map_err(|e| CliCommandError {
-            message: format!("failed to execute {label} command: {e}"),
-            stderr: String::new(),
-        })?;
+    // Wrapper scripts can tell who is asking from the environment.
+    let out = process::output_streaming(
+        Command::new("sh").args(["-lc", &full]).envs([
+            ("CRABPLANE_JOB_ID", &ctx.job_id),
+            ("CRABPLANE_USER_ID", &ctx.user_id),
+            ("CRABPLANE_CHANNEL_ID", &ctx.channel_id),
+            ("CRABPLANE_SOURCE", &ctx.source),
+        ]),
+        &ctx.cancel,
+        |line| ctx.emit(line),
+    )
+    .map_err(|e| CliCommandError {
+        kind: Kind::Internal,
+        message: format!("failed to execute {label} command: {e}"),
+        stderr: String::new(),
+    })?;
 
     if !out.status.success() {
         let stderr = String::from_utf8_lossy(&out.stderr).trim().to_string();
@@ -217,7 +319,17 @@ fn run_cli_command(prompt: &str, cmd: &str, label: &str) -> Result<String, CliCo
         } else {
             format!("{label} command failed: {stderr}")
         };
-        return Err(CliCommandError { message, stderr });
+        // A missing binary needs setting up; anything else is the backend failing.
+        let kind = if is_command_not_found(&stderr) {
+            Kind::ConfigMissing
+        } else {
+            Kind::Upstream
+        };
+        return Err(CliCommandError {
+            kind,
+            message,
+            stderr,
+        });
     }
 
     let stdout = String::from_utf8_lossy(&out.stdout).to_string();
@@ -1,5 +1,12 @@
+use std::thread;
+use std::time::Duration;
+
+use crate::error::Error;
 use crate::tasks::{Task, TaskContext, TaskOutput};
-use crate::types::TaskInput;
+use crate::types::{Priority, Role, TaskInput};
+
+// The longest `--delay` accepted, in seconds.
+const MAX_DELAY: u64 = 60;
 
 #[derive(Default)]
 pub struct PingTask;
@@ -15,11 +22,65 @@ impl Task for PingTask {
         "ping"
     }
 
-    fn validate(&self, _input: &TaskInput) -> Result<(), String> {
-        Ok(())
+    fn validate(&self, input: &TaskInput) -> Result<(), String> {
+        delay_of(input).map(|_| ())
     }
 
-    fn run(&self, _ctx: &TaskContext, _input: TaskInput) -> Result<TaskOutput, String> {
+    // `--delay` holds the reply back, which shows a slow job's place in the
+    // queue and that `!cancel` stops it.
+    fn run(&self, ctx: &TaskContext, input: TaskInput) -> Result<TaskOutput, Error> {
+        let mut left = delay_of(&input).map_err(Error::user)?;
+        while !left.is_zero() && !ctx.cancel.is_canceled() {
+            let step = left.min(Duration::from_millis(100));
+            thread::sleep(step);
+            left -= step;
+        }
         Ok(TaskOutput::Text("pong".to_string()))
     }
+
+    // Operator commands cut ahead of chat traffic.
+    fn priority(&self) -> Priority {
+        Priority::High
+    }
+
+    // Checking the bot is up costs nothing.
+    fn role(&self) -> Role {
+        Role::Guest
+    }
+}
+
+// `!ping [--delay=<seconds>]`.
+fn delay_of(input: &TaskInput) -> Result<Duration, String> {
+    let TaskInput::Args(a) = input else {
+        return Ok(Duration::ZERO);
+    };
+    a.check(0, &["delay"])?;
+    match a.flag_as::<u64>("delay")? {
+        Some(s) if s > MAX_DELAY => Err(format!("--delay is at most {MAX_DELAY} seconds")),
+        s => Ok(Duration::from_secs(s.unwrap_or(0))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::parse_args;
+
+    fn args(s: &str) -> TaskInput {
+        TaskInput::Args(parse_args(s, Vec::new()))
+    }
+
+    #[test]
+    fn validates_the_delay() {
+        let task = PingTask::new();
+        assert!(task.validate(&TaskInput::Empty).is_ok());
+        assert_eq!(delay_of(&args("--delay=2")), Ok(Duration::from_secs(2)));
+        assert_eq!(
+            task.validate(&args("--delay=soon")),
+            Err("invalid value for --delay: soon".to_string())
+        );
+        assert!(task.validate(&args("--delay")).is_err());
+        assert!(task.validate(&args("--delay=61")).is_err());
+        assert!(task.validate(&args("--count=2")).is_err());
+    }
 }
@@ -0,0 +1,142 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::{Child, Command, Output, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use crate::tasks::CancelToken;
+
+// Runs `cmd` like `Command::output`, but kills it (and anything it spawned)
+// as soon as `cancel` is set, returning an `Interrupted` error.
+pub(crate) fn output(cmd: &mut Command, cancel: &CancelToken) -> io::Result<Output> {
+    output_streaming(cmd, cancel, |_| {})
+}
+
+// Like `output`, also passing each stdout line (with its newline) to
+// `on_line` as soon as it is read.
+pub(crate) fn output_streaming(
+    cmd: &mut Command,
+    cancel: &CancelToken,
+    on_line: impl FnMut(&str) + Send,
+) -> io::Result<Output> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // Own process group, so a `sh -lc` wrapper and its children die together.
+        cmd.process_group(0);
+    }
+
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdout_pipe = child.stdout.take();
+    let stderr_pipe = child.stderr.take();
+
+    thread::scope(|s| {
+        let stdout = s.spawn(move || read_lines(stdout_pipe, on_line));
+        let stderr = s.spawn(move || read_all(stderr_pipe));
+
+        let status = loop {
+            if cancel.is_canceled() {
+                kill(&mut child);
+                let _ = child.wait();
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "canceled"));
+            }
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) => thread::sleep(Duration::from_millis(50)),
+                // Still kill it, or the readers would wait for it to exit.
+                Err(e) => {
+                    kill(&mut child);
+                    let _ = child.wait();
+                    return Err(e);
+                }
+            }
+        };
+
+        Ok(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
+    })
+}
+
+fn read_lines<R: Read>(r: Option<R>, mut on_line: impl FnMut(&str)) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let Some(r) = r else { return buf };
+    let mut r = BufReader::new(r);
+    loop {
+        let start = buf.len();
+        match r.read_until(b'\n', &mut buf) {
+            Ok(0) | Err(_) => return buf,
+            Ok(_) => on_line(&String::from_utf8_lossy(&buf[start..])),
+        }
+    }
+}
+
+fn read_all<R: Read>(r: Option<R>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    if let Some(mut r) = r {
+        let _ = r.read_to_end(&mut buf);
+    }
+    buf
+}
+
+#[cfg(unix)]
+fn kill(child: &mut Child) {
+    use std::ffi::c_int;
+
+    const SIGKILL: c_int = 9;
+
+    unsafe extern "C" {
+        fn kill(pid: c_int, sig: c_int) -> c_int;
+    }
+
+    // Safety: plain syscall wrapper; a negative pid targets the process group
+    // created for this child in `output`.
+    let rc = unsafe { kill(-(child.id() as c_int), SIGKILL) };
+    if rc != 0 {
+        let _ = child.kill();
+    }
+}
+
+#[cfg(not(unix))]
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn collects_output() {
+        let out = output(
+            Command::new("sh").args(["-c", "echo hi; echo err >&2"]),
+            &CancelToken::default(),
+        )
+        .unwrap();
+        assert!(out.status.success());
+        assert_eq!(out.stdout, b"hi\n");
+        assert_eq!(out.stderr, b"err\n");
+    }
+
+    #[test]
+    fn cancel_kills_the_process_group() {
+        let cancel = CancelToken::default();
+        let c = cancel.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            c.cancel();
+        });
+        let start = Instant::now();
+        // The grandchild `sleep` holds stdout open unless it is killed too.
+        let err = output(Command::new("sh").args(["-c", "sleep 30; true"]), &cancel).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
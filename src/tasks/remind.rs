@@ -0,0 +1,39 @@
+use crate::error::Error;
+use crate::tasks::{Task, TaskContext, TaskOutput};
+use crate::types::{Priority, TaskInput};
+
+// RemindTask echoes a note back to its channel. The router schedules the job
+// with `not_before`, so by the time it runs the reminder is due.
+#[derive(Default)]
+pub struct RemindTask;
+
+impl RemindTask {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Task for RemindTask {
+    fn name(&self) -> &'static str {
+        "remind"
+    }
+
+    fn validate(&self, input: &TaskInput) -> Result<(), String> {
+        if input.text().trim().is_empty() {
+            return Err("reminder text is empty".to_string());
+        }
+        Ok(())
+    }
+
+    fn run(&self, _ctx: &TaskContext, input: TaskInput) -> Result<TaskOutput, Error> {
+        Ok(TaskOutput::Text(format!(
+            "⏰ reminder: {}",
+            input.text().trim()
+        )))
+    }
+
+    // A reminder that waited for its time should not wait again behind chat.
+    fn priority(&self) -> Priority {
+        Priority::High
+    }
+}
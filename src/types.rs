@@ -1,26 +1,230 @@
 use std::collections::HashMap;
 use std::time::SystemTime;
 
+use crate::error::Error;
+
 #[derive(Clone, Debug)]
 pub struct Message {
+    // Name of the adapter the message arrived on, its `SOURCE` constant
+    // (`cli`, `telegram`, ...). Jobs keep it, and their results are routed
+    // back through the same adapter.
+    pub source: String,
     pub user_id: String,
     pub channel: String,
     pub text: String,
-    #[allow(dead_code)]
+    // Files sent along with the text (photos, documents, voice notes).
+    pub attachments: Vec<Attachment>,
+    // Platform facts about the message, under the `META_*` keys; carried on
+    // to the job. Adapters set what their platform provides.
     pub metadata: HashMap<String, String>,
 }
 
+// The platform's id of the message, which results reply to.
+pub const META_MESSAGE_ID: &str = "message_id";
+pub const META_USERNAME: &str = "username";
+pub const META_DISPLAY_NAME: &str = "display_name";
+// "private", "group", "supergroup" or "channel".
+pub const META_CHAT_TYPE: &str = "chat_type";
+// Id of the message this one replies to.
+pub const META_REPLY_TO: &str = "reply_to";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Attachment {
+    pub name: String,
+    pub mime: String,
+    // Where to fetch it: an http(s) URL, or `telegram:<file_id>` for Telegram
+    // files. Both Twilio and Telegram need the adapter's credentials.
+    pub url: String,
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Response {
     pub text: String,
-    #[allow(dead_code)]
+    // Only useful for a moment (acks, usage errors, command output): adapters
+    // remove or de-emphasise it where the chat allows.
     pub ephemeral: bool,
+    // Set when the message queued a job.
+    pub job_id: Option<String>,
+    // `text` is in the portable Markdown subset of `markdown`; adapters
+    // render it for their chat or strip it.
+    pub markdown: bool,
+    pub files: Vec<OutFile>,
+    pub buttons: Vec<Button>,
+    // Set when the message was rejected or the job failed; `text` then holds
+    // `Error::user_message`, and adapters may show the error their own way.
+    pub error: Option<Error>,
+}
+
+impl Response {
+    pub fn error(e: Error) -> Self {
+        Self {
+            text: e.user_message(),
+            error: Some(e),
+            ..Self::default()
+        }
+    }
+}
+
+// A file sent with a result. Images (`image/*`) are shown inline where the
+// chat supports it.
+#[derive(Clone, Debug)]
+pub struct OutFile {
+    pub name: String,
+    pub mime: String,
+    pub body: FileBody,
+}
+
+#[derive(Clone, Debug)]
+pub enum FileBody {
+    Bytes(Vec<u8>),
+    // An http(s) URL, or an incoming attachment's `telegram:<file_id>`.
+    Url(String),
+}
+
+impl OutFile {
+    pub fn is_image(&self) -> bool {
+        self.mime.starts_with("image/")
+    }
+}
+
+// A button under a result that sends `command` as if the user typed it.
+// Chats without buttons list the commands instead.
+#[derive(Clone, Debug)]
+pub struct Button {
+    pub label: String,
+    pub command: String,
 }
 
 #[derive(Clone, Debug)]
 pub enum TaskInput {
     Empty,
     Text(String),
+    // Command arguments split by the router; see `router::parse_args`.
+    Args(Args),
+}
+
+impl TaskInput {
+    // The input as typed: the text, or the raw argument string.
+    pub fn text(&self) -> &str {
+        match self {
+            TaskInput::Empty => "",
+            TaskInput::Text(t) => t,
+            TaskInput::Args(a) => &a.raw,
+        }
+    }
+}
+
+// Args are a command's arguments: positionals, `--key=value` flags (a bare
+// `--key` is "true") and attached files. `raw` keeps the text after the
+// command exactly as typed for tasks that take free text.
+#[derive(Clone, Debug, Default)]
+pub struct Args {
+    pub raw: String,
+    pub positional: Vec<String>,
+    pub flags: HashMap<String, String>,
+    pub attachments: Vec<Attachment>,
+}
+
+impl Args {
+    pub fn get(&self, i: usize) -> Option<&str> {
+        self.positional.get(i).map(String::as_str)
+    }
+
+    // `--name` parsed as `T`; None when absent.
+    pub fn flag_as<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        match self.flags.get(name) {
+            None => Ok(None),
+            Some(v) => v
+                .parse::<T>()
+                .map(Some)
+                .map_err(|_| format!("invalid value for --{name}: {v}")),
+        }
+    }
+
+    // Rejects flags outside `flags` and more than `max_positional`
+    // positionals, for use in `Task::validate`.
+    pub fn check(&self, max_positional: usize, flags: &[&str]) -> Result<(), String> {
+        let mut unknown: Vec<&str> = self
+            .flags
+            .keys()
+            .map(String::as_str)
+            .filter(|k| !flags.contains(k))
+            .collect();
+        unknown.sort();
+        if let Some(k) = unknown.first() {
+            return Err(format!("unknown option --{k}"));
+        }
+        if self.positional.len() > max_positional {
+            return Err(format!(
+                "too many arguments: {}",
+                self.positional[max_positional..].join(" ")
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Priority selects the queue lane a job waits in. Higher lanes are served
+// first; see `queue::Queue` for the starvation guard on lower lanes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    pub fn lane(self) -> usize {
+        self as usize
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "high" => Some(Priority::High),
+            "normal" => Some(Priority::Normal),
+            "low" => Some(Priority::Low),
+            _ => None,
+        }
+    }
+}
+
+// Role says what a user may run; each role may do everything the ones before
+// it may. See `access::Access`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Guest,
+    Member,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Guest => "guest",
+            Role::Member => "member",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "guest" => Some(Role::Guest),
+            "member" => Some(Role::Member),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -28,9 +232,36 @@ pub struct Job {
     pub id: String,
     pub task_name: String,
     pub input: TaskInput,
-    #[allow(dead_code)]
+    pub priority: Priority,
     pub user_id: String,
     pub channel_id: String,
-    #[allow(dead_code)]
+    // Adapter the result is delivered through; see `engine::RoutingSink`.
+    pub source: String,
     pub created_at: SystemTime,
+    // 1 for the first run; bumped each time a failed job is retried.
+    pub attempt: u32,
+    // Held back by the queue until this time (reminders, retry backoff).
+    pub not_before: Option<SystemTime>,
+    // `Message.metadata` of the message that created the job.
+    pub metadata: HashMap<String, String>,
+}
+
+#[cfg(test)]
+impl Job {
+    // A fresh job of `task` for `user_id`, in channel "c" of the CLI.
+    pub fn test(id: &str, task: &str, user_id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            task_name: task.to_string(),
+            input: TaskInput::Empty,
+            priority: Priority::Normal,
+            user_id: user_id.to_string(),
+            channel_id: "c".to_string(),
+            source: "cli".to_string(),
+            created_at: SystemTime::now(),
+            attempt: 1,
+            not_before: None,
+            metadata: HashMap::new(),
+        }
+    }
 }
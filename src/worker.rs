@@ -1,31 +1,182 @@
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, mpsc};
+use std::sync::{Arc, Mutex, mpsc};
 use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant, SystemTime};
 
+use crate::error::Error;
+use crate::jobs::{JobEntry, JobTable, fmt_dur};
+use crate::log;
+use crate::metrics;
 use crate::queue::{Queue, QueueError};
 use crate::registry::Registry;
-use crate::tasks::{TaskContext, TaskOutput};
+use crate::state::{State, StateStore};
+use crate::tasks::{
+    CancelReason, CancelToken, Config, RetryPolicy, Stream, Task, TaskContext, TaskOutput,
+};
 use crate::types::Job;
 
 #[derive(Debug)]
 pub struct ResultItem {
     pub job: Job,
     pub output: TaskOutput,
-    pub err: Option<String>,
-    #[allow(dead_code)]
+    pub err: Option<Error>,
     pub finished_at: SystemTime,
-    #[allow(dead_code)]
     pub dur: Duration,
+    // Worker that ran the job, for log correlation.
+    pub worker: usize,
+}
+
+// Operator overrides for the limits tasks declare on `Task`, keyed by task
+// (or group) name.
+#[derive(Clone, Debug, Default)]
+pub struct TaskLimits {
+    pub max_concurrency: HashMap<String, usize>,
+    pub groups: HashMap<String, String>,
+    pub group_workers: HashMap<String, usize>,
+    pub timeouts: HashMap<String, Duration>,
+}
+
+// Bulkheads decides which worker may take which job: grouped tasks only run
+// on their group's workers, and a task at its concurrency cap is left queued.
+struct Bulkheads {
+    reg: Arc<Registry>,
+    limits: TaskLimits,
+    running: Mutex<HashMap<String, usize>>,
+}
+
+impl Bulkheads {
+    fn group_of(&self, task_name: &str) -> Option<String> {
+        if let Some(g) = self.limits.groups.get(task_name) {
+            return Some(g.clone()).filter(|g| !g.is_empty());
+        }
+        self.reg
+            .lookup(task_name)
+            .and_then(|t| t.worker_group())
+            .map(str::to_string)
+    }
+
+    fn max_of(&self, task_name: &str) -> usize {
+        if let Some(n) = self.limits.max_concurrency.get(task_name) {
+            return *n;
+        }
+        self.reg
+            .lookup(task_name)
+            .map(|t| t.max_concurrency())
+            .unwrap_or(0)
+    }
+
+    fn timeout_of(&self, task: &dyn Task) -> Option<Duration> {
+        match self.limits.timeouts.get(task.name()) {
+            Some(d) if d.is_zero() => None,
+            Some(d) => Some(*d),
+            None => task.timeout(),
+        }
+    }
+
+    // Every group named by a task or by config, with its worker count.
+    fn groups(&self) -> Vec<(String, usize)> {
+        let mut names: Vec<String> = self
+            .reg
+            .list()
+            .iter()
+            .filter_map(|t| self.group_of(t.name()))
+            .chain(self.limits.groups.values().cloned())
+            .chain(self.limits.group_workers.keys().cloned())
+            .filter(|g| !g.is_empty())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+            .into_iter()
+            .map(|g| {
+                let n = self
+                    .limits
+                    .group_workers
+                    .get(&g)
+                    .copied()
+                    .unwrap_or(1)
+                    .max(1);
+                (g, n)
+            })
+            .collect()
+    }
+
+    fn try_acquire(&self, group: Option<&str>, job: &Job) -> bool {
+        if self.group_of(&job.task_name).as_deref() != group {
+            return false;
+        }
+        let Ok(mut running) = self.running.lock() else {
+            return false;
+        };
+        let n = running.entry(job.task_name.clone()).or_default();
+        let max = self.max_of(&job.task_name);
+        if max > 0 && *n >= max {
+            return false;
+        }
+        *n += 1;
+        true
+    }
+
+    fn release(&self, task_name: &str) {
+        if let Ok(mut running) = self.running.lock()
+            && let Some(n) = running.get_mut(task_name)
+        {
+            *n = n.saturating_sub(1);
+        }
+    }
+}
+
+// Slot is a job's place under its task's concurrency cap. It is shared with
+// the helper thread of a task that has a timeout and freed when the last
+// holder drops it, so a task still running after its timeout keeps counting.
+struct Slot {
+    bulkheads: Arc<Bulkheads>,
+    q: Arc<Queue>,
+    task_name: String,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.bulkheads.release(&self.task_name);
+        self.q.notify();
+    }
+}
+
+// TaskEnv is what tasks are given besides their job: configuration and the
+// state store.
+pub struct TaskEnv {
+    pub config: Config,
+    pub state: Arc<StateStore>,
+}
+
+impl TaskEnv {
+    fn context(&self, job: &Job, cancel: CancelToken, stream: Stream) -> TaskContext {
+        TaskContext {
+            job_id: job.id.clone(),
+            user_id: job.user_id.clone(),
+            channel_id: job.channel_id.clone(),
+            source: job.source.clone(),
+            attempt: job.attempt,
+            metadata: job.metadata.clone(),
+            config: self.config.clone(),
+            user_state: State::user(&self.state, &job.source, &job.user_id),
+            channel_state: State::channel(&self.state, &job.source, &job.channel_id),
+            cancel,
+            stream,
+        }
+    }
 }
 
 pub struct Pool {
     q: Arc<Queue>,
-    reg: Arc<Registry>,
     workers: usize,
+    bulkheads: Arc<Bulkheads>,
+    jobs: Arc<JobTable>,
+    env: Arc<TaskEnv>,
 
     canceled: Arc<AtomicBool>,
-    results_tx: Option<mpsc::Sender<ResultItem>>,
+    results_tx: ResultsTx,
     joins: Vec<JoinHandle<()>>,
 }
 
@@ -34,80 +185,185 @@ impl Pool {
         reg: Arc<Registry>,
         q: Arc<Queue>,
         workers: usize,
+        limits: TaskLimits,
+        env: TaskEnv,
     ) -> (Self, mpsc::Receiver<ResultItem>) {
         let workers = if workers == 0 { 4 } else { workers };
         let (tx, rx) = mpsc::channel();
         (
             Self {
                 q,
-                reg,
                 workers,
+                bulkheads: Arc::new(Bulkheads {
+                    reg,
+                    limits,
+                    running: Mutex::new(HashMap::new()),
+                }),
+                jobs: Arc::new(JobTable::new()),
+                env: Arc::new(env),
                 canceled: Arc::new(AtomicBool::new(false)),
-                results_tx: Some(tx),
+                results_tx: Arc::new(Mutex::new(Some(tx))),
                 joins: Vec::new(),
             },
             rx,
         )
     }
 
+    // Total worker threads, including those reserved for groups.
+    pub fn workers(&self) -> usize {
+        self.lineup().len()
+    }
+
+    // One entry per worker: None for general workers, else its group.
+    fn lineup(&self) -> Vec<Option<String>> {
+        let mut lineup: Vec<Option<String>> = vec![None; self.workers];
+        for (group, n) in self.bulkheads.groups() {
+            lineup.extend(std::iter::repeat_n(Some(group), n));
+        }
+        lineup
+    }
+
     pub fn start(&mut self) {
-        for worker_id in 1..=self.workers {
-            let q = Arc::clone(&self.q);
-            let reg = Arc::clone(&self.reg);
-            let canceled = Arc::clone(&self.canceled);
-            let tx = self.results_tx.as_ref().unwrap().clone();
-            self.joins.push(thread::spawn(move || {
-                run_worker(worker_id, q, reg, canceled, tx);
-            }));
+        for (idx, group) in self.lineup().into_iter().enumerate() {
+            let shared = Shared {
+                q: Arc::clone(&self.q),
+                bulkheads: Arc::clone(&self.bulkheads),
+                jobs: Arc::clone(&self.jobs),
+                env: Arc::clone(&self.env),
+                canceled: Arc::clone(&self.canceled),
+                results_tx: Arc::clone(&self.results_tx),
+            };
+            self.joins
+                .push(thread::spawn(move || run_worker(idx + 1, group, shared)));
         }
     }
 
-    pub fn submit(&self, job: Job) -> Result<(), String> {
-        self.q
-            .enqueue(job, &self.canceled)
-            .map_err(|e| format!("failed to queue job: {e:?}"))
+    pub fn submit(&self, job: Job) -> Result<(), QueueError> {
+        self.q.enqueue(job, &self.canceled)
     }
 
-    pub fn shutdown(&mut self) {
+    pub fn ack(&self, job_id: &str) -> Result<(), String> {
+        self.q.ack(job_id)
+    }
+
+    pub fn jobs(&self) -> Arc<JobTable> {
+        Arc::clone(&self.jobs)
+    }
+
+    // Drops a job that has not started yet. Returns false if it is already
+    // running (or unknown), in which case only its cancel token can stop it.
+    pub fn remove_queued(&self, job_id: &str) -> bool {
+        if self.q.remove(job_id).is_none() {
+            return false;
+        }
+        self.jobs.cancel_queued(job_id);
+        let _ = self.q.ack(job_id);
+        true
+    }
+
+    // Stops accepting jobs and waits up to `timeout` for running jobs to
+    // finish. Jobs still running at the deadline are canceled and returned;
+    // their workers are left behind rather than joined, and anything they
+    // produce afterwards is discarded.
+    pub fn shutdown(&mut self, timeout: Duration) -> Vec<JobEntry> {
         self.q.close();
         self.canceled.store(true, Ordering::Relaxed);
 
-        // Closing the sender ends the dispatch loop once workers exit.
-        self.results_tx.take();
+        let deadline = Instant::now() + timeout;
+        let mut abandoned = Vec::new();
+        while self.joins.iter().any(|j| !j.is_finished()) {
+            if Instant::now() >= deadline {
+                abandoned = self.jobs.running();
+                for e in &abandoned {
+                    e.cancel.cancel_with(CancelReason::Shutdown);
+                }
+                // Give canceled tasks a moment to kill their children.
+                thread::sleep(Duration::from_millis(200));
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
 
+        // Closing the only sender ends the dispatch loop, even with
+        // abandoned workers still running.
+        if let Ok(mut tx) = self.results_tx.lock() {
+            tx.take();
+        }
         for j in self.joins.drain(..) {
-            let _ = j.join();
+            if j.is_finished() {
+                let _ = j.join();
+            }
         }
+        abandoned
+    }
+
+    pub fn queued(&self) -> usize {
+        self.q.pending()
     }
 }
 
-fn run_worker(
-    worker_id: usize,
+// What every worker thread is handed by the pool.
+struct Shared {
     q: Arc<Queue>,
-    reg: Arc<Registry>,
+    bulkheads: Arc<Bulkheads>,
+    jobs: Arc<JobTable>,
+    env: Arc<TaskEnv>,
     canceled: Arc<AtomicBool>,
-    results_tx: mpsc::Sender<ResultItem>,
-) {
-    let ctx = TaskContext;
+    results_tx: ResultsTx,
+}
+
+fn run_worker(worker_id: usize, group: Option<String>, shared: Shared) {
+    let Shared {
+        q,
+        bulkheads,
+        jobs,
+        env,
+        canceled,
+        results_tx,
+    } = shared;
     loop {
-        let job = match q.dequeue(&canceled) {
+        let job = match q.dequeue(&canceled, |job| {
+            bulkheads.try_acquire(group.as_deref(), job)
+        }) {
             Ok(j) => j,
-            Err(QueueError::Closed | QueueError::Canceled) => return,
+            Err(_) => return,
         };
+        let slot = Arc::new(Slot {
+            bulkheads: Arc::clone(&bulkheads),
+            q: Arc::clone(&q),
+            task_name: job.task_name.clone(),
+        });
 
         let start = Instant::now();
         let mut out = TaskOutput::None;
-        let mut err: Option<String> = None;
+        let mut err: Option<Error> = None;
+        let (stream, stream_tx) = output_stream(&job, worker_id, &results_tx);
+        let ctx = env.context(&job, jobs.start(&job, worker_id), stream);
+        let waited = job.created_at.elapsed().unwrap_or_default();
+        log::info(
+            "job started",
+            &[
+                ("job_id", &job.id),
+                ("task", &job.task_name),
+                ("worker", &worker_id),
+                ("user", &job.user_id),
+                ("channel", &job.channel_id),
+                ("attempt", &job.attempt),
+                ("wait_ms", &waited.as_millis()),
+            ],
+        );
 
-        match reg.lookup(&job.task_name) {
+        match bulkheads.reg.lookup(&job.task_name) {
+            _ if ctx.cancel.is_canceled() => {}
             None => {
-                err = Some(format!("unknown task: {}", job.task_name));
+                err = Some(Error::internal(format!("unknown task: {}", job.task_name)));
             }
             Some(task) => {
                 if let Err(e) = task.validate(&job.input) {
-                    err = Some(e);
+                    err = Some(Error::user(e));
                 } else {
-                    match task.run(&ctx, job.input.clone()) {
+                    let timeout = bulkheads.timeout_of(&*task);
+                    match run_task(task, &ctx, &job, timeout, &slot) {
                         Ok(o) => out = o,
                         Err(e) => err = Some(e),
                     }
@@ -115,21 +371,259 @@ fn run_worker(
             }
         }
 
+        // Output after this point would arrive behind the result.
+        close_stream(&stream_tx);
+        match ctx.cancel.reason() {
+            None => {}
+            Some(CancelReason::User) => {
+                out = TaskOutput::None;
+                err = Some(Error::user("job canceled"));
+            }
+            // `run_task` already reported the timeout.
+            Some(CancelReason::Timeout) => {}
+            Some(CancelReason::Shutdown) => {
+                // Abandoned at shutdown: report nothing, so a journaled job is
+                // replayed on the next start instead of answered with an error.
+                log::warn(
+                    "job abandoned",
+                    &[
+                        ("job_id", &job.id),
+                        ("task", &job.task_name),
+                        ("worker", &worker_id),
+                        ("user", &job.user_id),
+                        ("channel", &job.channel_id),
+                        ("dur_ms", &start.elapsed().as_millis()),
+                    ],
+                );
+                return;
+            }
+        }
+
         let finished_at = SystemTime::now();
         let dur = start.elapsed();
+        drop(slot);
 
-        let _ = results_tx.send(ResultItem {
+        if let Some(e) = &err {
+            let policy = bulkheads
+                .reg
+                .lookup(&job.task_name)
+                .map(|t| t.retry_policy())
+                .unwrap_or_else(RetryPolicy::none);
+            let retry = !ctx.cancel.is_canceled()
+                && job.attempt < policy.max_attempts
+                && (policy.retryable)(e);
+            if retry {
+                let backoff = policy.backoff(job.attempt);
+                metrics::job_finished(&job.task_name, "retried", dur);
+                log::warn(
+                    "job retry scheduled",
+                    &[
+                        ("job_id", &job.id),
+                        ("task", &job.task_name),
+                        ("worker", &worker_id),
+                        ("user", &job.user_id),
+                        ("channel", &job.channel_id),
+                        ("attempt", &job.attempt),
+                        ("dur_ms", &dur.as_millis()),
+                        ("retry_in", &fmt_dur(backoff)),
+                        ("err", e),
+                        ("error_kind", &e.kind.as_str()),
+                        ("error_id", &e.id),
+                    ],
+                );
+                let at = SystemTime::now() + backoff;
+                jobs.retry(&job.id, e, at);
+                let mut job = job;
+                job.not_before = Some(at);
+                job.attempt += 1;
+                let _ = q.requeue(job);
+                continue;
+            }
+            if job.attempt > 1 {
+                let mut e = e.clone();
+                e.attempts = job.attempt;
+                err = Some(e);
+            }
+        }
+
+        let outcome = match (ctx.cancel.reason(), &err) {
+            (Some(CancelReason::Timeout), _) => "timed_out",
+            (Some(_), _) => "canceled",
+            (None, Some(_)) => "failed",
+            (None, None) => "succeeded",
+        };
+        metrics::job_finished(&job.task_name, outcome, dur);
+        let (err_msg, err_kind, err_id) = err
+            .as_ref()
+            .map(|e| (e.msg.as_str(), e.kind.as_str(), e.id.as_str()))
+            .unwrap_or_default();
+        log::log(
+            if err.is_some() {
+                log::Level::Warn
+            } else {
+                log::Level::Info
+            },
+            "job finished",
+            &[
+                ("job_id", &job.id),
+                ("task", &job.task_name),
+                ("worker", &worker_id),
+                ("user", &job.user_id),
+                ("channel", &job.channel_id),
+                ("attempt", &job.attempt),
+                ("outcome", &outcome),
+                ("dur_ms", &dur.as_millis()),
+                ("err", &err_msg),
+                ("error_kind", &err_kind),
+                ("error_id", &err_id),
+            ],
+        );
+        let res = ResultItem {
             job,
             output: out,
             err,
             finished_at,
             dur,
-        });
+            worker: worker_id,
+        };
+        jobs.finish(&res);
+        send_result(&results_tx, res);
 
         // Avoid busy looping in case something goes wrong; tiny backoff is fine for v0.
         if canceled.load(Ordering::Relaxed) {
             return;
         }
-        let _ = worker_id; // reserved for future structured logs
+    }
+}
+
+// The pool's only sender of results. Workers and job streams send through
+// it, so `Pool::shutdown` can close the channel by taking it.
+type ResultsTx = Arc<Mutex<Option<mpsc::Sender<ResultItem>>>>;
+
+fn send_result(tx: &ResultsTx, res: ResultItem) {
+    if let Ok(tx) = tx.lock()
+        && let Some(tx) = tx.as_ref()
+    {
+        let _ = tx.send(res);
+    }
+}
+
+type StreamTx = Arc<Mutex<Option<ResultsTx>>>;
+
+// Sends each emitted chunk or progress note to the dispatcher as a result,
+// until the worker closes the returned stream.
+fn output_stream(job: &Job, worker: usize, results_tx: &ResultsTx) -> (Stream, StreamTx) {
+    let job = job.clone();
+    let tx: StreamTx = Arc::new(Mutex::new(Some(Arc::clone(results_tx))));
+    let stream_tx = Arc::clone(&tx);
+    let start = Instant::now();
+    let stream = Stream::new(move |output| {
+        if let Ok(tx) = tx.lock()
+            && let Some(tx) = tx.as_ref()
+        {
+            send_result(
+                tx,
+                ResultItem {
+                    job: job.clone(),
+                    output,
+                    err: None,
+                    finished_at: SystemTime::now(),
+                    dur: start.elapsed(),
+                    worker,
+                },
+            );
+        }
+    });
+    (stream, stream_tx)
+}
+
+// Detaches the stream from the results channel, so a task left running after
+// a timeout sends no output after its result.
+fn close_stream(tx: &StreamTx) {
+    if let Ok(mut tx) = tx.lock() {
+        tx.take();
+    }
+}
+
+// Runs the task on the worker thread, or on a helper thread when it has a
+// timeout. On timeout the job is canceled (killing any child process) and the
+// worker moves on; the helper thread keeps the job's slot until the task
+// notices and returns.
+fn run_task(
+    task: Arc<dyn Task>,
+    ctx: &TaskContext,
+    job: &Job,
+    timeout: Option<Duration>,
+    slot: &Arc<Slot>,
+) -> Result<TaskOutput, Error> {
+    let Some(timeout) = timeout else {
+        return task.run(ctx, job.input.clone());
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let ctx2 = ctx.clone();
+    let input = job.input.clone();
+    let slot = Arc::clone(slot);
+    thread::spawn(move || {
+        let _ = tx.send(task.run(&ctx2, input));
+        drop(slot);
+    });
+    match rx.recv_timeout(timeout) {
+        Ok(r) => r,
+        Err(_) => {
+            ctx.cancel.cancel_with(CancelReason::Timeout);
+            Err(Error::timeout(format!(
+                "timed out after {}",
+                fmt_dur(timeout)
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TaskInput;
+
+    // Ignores cancellation, like a task stuck in a blocking call.
+    struct Stuck;
+
+    impl Task for Stuck {
+        fn name(&self) -> &'static str {
+            "stuck"
+        }
+
+        fn validate(&self, _input: &TaskInput) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn run(&self, ctx: &TaskContext, _input: TaskInput) -> Result<TaskOutput, Error> {
+            thread::sleep(Duration::from_secs(2));
+            ctx.emit("late");
+            Ok(TaskOutput::Text("late".to_string()))
+        }
+    }
+
+    #[test]
+    fn shutdown_closes_results_despite_abandoned_workers() {
+        let reg = Arc::new(Registry::new());
+        reg.register(Arc::new(Stuck)).unwrap();
+        let env = TaskEnv {
+            config: Config::default(),
+            state: Arc::new(StateStore::new()),
+        };
+        let (mut pool, rx) = Pool::new(reg, Arc::new(Queue::new(8)), 1, TaskLimits::default(), env);
+        pool.start();
+        pool.submit(Job::test("j1", "stuck", "u")).unwrap();
+        while pool.jobs().running().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let abandoned = pool.shutdown(Duration::from_millis(50));
+        assert_eq!(abandoned.len(), 1);
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_millis(500)),
+            Err(mpsc::RecvTimeoutError::Disconnected)
+        ));
     }
 }